@@ -0,0 +1,83 @@
+//! Memoized membership checks for long running services.
+//!
+//! Caching only pays off for skewed query distributions where the same keys
+//! are checked over and over. For evenly spread queries the cache bookkeeping
+//! costs more than the hashing it saves.
+
+use crate::Filter;
+use helium_crypto::PublicKeyBinary;
+use lru::LruCache;
+use std::{num::NonZeroUsize, sync::Mutex};
+
+pub struct CachedFilter {
+    filter: Filter,
+    cache: Mutex<LruCache<Vec<u8>, bool>>,
+}
+
+impl CachedFilter {
+    pub fn new(filter: Filter, capacity: NonZeroUsize) -> Self {
+        Self {
+            filter,
+            cache: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    pub fn into_inner(self) -> Filter {
+        self.filter
+    }
+
+    pub fn contains(&self, public_key: &PublicKeyBinary) -> bool {
+        let key: &[u8] = public_key.as_ref();
+        let mut cache = self
+            .cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(in_filter) = cache.get(key) {
+            return *in_filter;
+        }
+        let in_filter = self.filter.contains(public_key);
+        cache.put(key.to_vec(), in_filter);
+        in_filter
+    }
+
+    pub fn contains_edge(&self, source: &PublicKeyBinary, target: &PublicKeyBinary) -> bool {
+        self.filter.contains_edge(source, target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, FilterOptions};
+
+    #[test]
+    fn cached_and_uncached_results_agree() {
+        let descriptor = test_util::descriptor(10, 2);
+        let filter = Filter::from_descriptor(1, &descriptor, &FilterOptions::default()).unwrap();
+        let members: Vec<PublicKeyBinary> = descriptor.iter_keys().collect();
+        let others = test_util::keys(10);
+        let expected: Vec<bool> = members
+            .iter()
+            .chain(&others)
+            .map(|key| filter.contains(key))
+            .collect();
+
+        // a capacity below the number of keys also exercises evictions
+        let cached = CachedFilter::new(filter, NonZeroUsize::new(4).unwrap());
+        for _ in 0..2 {
+            let results: Vec<bool> = members
+                .iter()
+                .chain(&others)
+                .map(|key| cached.contains(key))
+                .collect();
+            assert_eq!(results, expected);
+        }
+        for (source, target) in descriptor.iter_edges() {
+            assert!(cached.contains_edge(&source, &target));
+        }
+    }
+}
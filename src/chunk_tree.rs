@@ -0,0 +1,122 @@
+use crate::{base64_serde, Error, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Domain separation prefixes, so a leaf hash can never be mistaken for the
+/// hash of an inner node
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+/// A merkle tree over fixed size chunks of the signing bytes of a filter.
+///
+/// The leaves are kept so that the chunks that changed between two serials
+/// can be found without the signing bytes of either. Like the manifest hash,
+/// the tree is derived from the signing bytes and can be recomputed from
+/// them; it is not covered by the member signatures.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ChunkTree {
+    pub chunk_size: u32,
+    /// Base64 root hash of the tree
+    pub root: String,
+    /// Base64 hashes of each chunk, in order
+    pub leaves: Vec<String>,
+}
+
+/// A chunk that differs between two chunk trees. A missing hash means the
+/// chunk is past the end of that tree.
+#[derive(Debug, Clone, Serialize)]
+pub struct ChunkChange {
+    pub index: usize,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+impl ChunkTree {
+    pub fn new(data: &[u8], chunk_size: u32) -> Result<Self> {
+        if chunk_size == 0 {
+            return Err(Error::manifest("chunk size must not be zero"));
+        }
+        let leaves: Vec<Vec<u8>> = data
+            .chunks(chunk_size as usize)
+            .map(|chunk| leaf_hash(chunk).to_vec())
+            .collect();
+        Ok(Self {
+            chunk_size,
+            root: base64_serde::encode(&root_hash(&leaves)),
+            leaves: leaves
+                .iter()
+                .map(|leaf| base64_serde::encode(leaf))
+                .collect(),
+        })
+    }
+
+    /// Checks that the leaves hash to the root of the tree
+    pub fn verify(&self) -> Result {
+        let leaves = self
+            .leaves
+            .iter()
+            .map(|leaf| base64_serde::decode(leaf))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        if base64_serde::encode(&root_hash(&leaves)) != self.root {
+            return Err(Error::manifest("chunk tree leaves do not match its root"));
+        }
+        Ok(())
+    }
+
+    /// Returns the chunks that differ from the given newer tree. Both trees
+    /// must use the same chunk size.
+    pub fn diff(&self, new: &ChunkTree) -> Result<Vec<ChunkChange>> {
+        if self.chunk_size != new.chunk_size {
+            return Err(Error::Manifest(format!(
+                "chunk size {} differs from {}",
+                self.chunk_size, new.chunk_size
+            )));
+        }
+        let len = self.leaves.len().max(new.leaves.len());
+        let changes = (0..len)
+            .filter_map(|index| {
+                let old = self.leaves.get(index);
+                let new = new.leaves.get(index);
+                (old != new).then(|| ChunkChange {
+                    index,
+                    old: old.cloned(),
+                    new: new.cloned(),
+                })
+            })
+            .collect();
+        Ok(changes)
+    }
+}
+
+fn leaf_hash(chunk: &[u8]) -> [u8; 32] {
+    Sha256::new()
+        .chain_update([LEAF_PREFIX])
+        .chain_update(chunk)
+        .finalize()
+        .into()
+}
+
+/// Hashes pairs of nodes level by level. An odd node at the end of a level is
+/// carried up as is, and a tree without leaves has the hash of no data.
+fn root_hash(leaves: &[Vec<u8>]) -> Vec<u8> {
+    if leaves.is_empty() {
+        return Sha256::digest(b"").to_vec();
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair.get(1) {
+                Some(right) => Sha256::new()
+                    .chain_update([NODE_PREFIX])
+                    .chain_update(&pair[0])
+                    .chain_update(right)
+                    .finalize()
+                    .to_vec(),
+                None => pair[0].clone(),
+            })
+            .collect();
+    }
+    level.remove(0)
+}
@@ -0,0 +1,79 @@
+//! Overrides for default argument values read from a `.xorf.toml` file in the
+//! working directory.
+//!
+//! Tables name (sub)commands and values set the default for the argument with
+//! that name, e.g.:
+//!
+//! ```toml
+//! [filter.generate]
+//! output = "denylist.bin"
+//!
+//! [manifest.generate]
+//! manifest = "denylist.manifest.json"
+//! ```
+//!
+//! Arguments given on the command line still take precedence.
+
+use anyhow::{Context, Result};
+use std::{fs, path::Path};
+
+pub const CONFIG_FILE: &str = ".xorf.toml";
+
+pub fn apply_defaults(command: clap::Command) -> Result<clap::Command> {
+    let path = Path::new(CONFIG_FILE);
+    if !path.exists() {
+        return Ok(command);
+    }
+    let config: toml::Table = fs::read_to_string(path)?
+        .parse()
+        .context(format!("reading config {}", path.display()))?;
+    let name = command.get_name().to_string();
+    apply_table(command, &config, &name).context(format!("applying config {}", path.display()))
+}
+
+fn apply_table(
+    mut command: clap::Command,
+    table: &toml::Table,
+    path: &str,
+) -> Result<clap::Command> {
+    for (name, value) in table {
+        if let toml::Value::Table(table) = value {
+            let subcommand = command
+                .find_subcommand(name)
+                .ok_or_else(|| anyhow::anyhow!("unknown command \"{path} {name}\""))?
+                .clone();
+            let subcommand = apply_table(subcommand, table, &format!("{path} {name}"))?;
+            command = command.mut_subcommand(name, |_| subcommand);
+        } else {
+            if !command
+                .get_arguments()
+                .any(|arg| arg.get_id().as_str() == name)
+            {
+                anyhow::bail!("unknown argument \"{name}\" for \"{path}\"");
+            }
+            let values = default_values(value)?;
+            command = command.mut_arg(name, |arg| arg.default_values(values));
+        }
+    }
+    Ok(command)
+}
+
+fn default_values(value: &toml::Value) -> Result<Vec<&'static str>> {
+    let values = match value {
+        toml::Value::Array(values) => values.iter().map(default_value).collect(),
+        value => vec![default_value(value)],
+    };
+    values.into_iter().collect()
+}
+
+fn default_value(value: &toml::Value) -> Result<&'static str> {
+    let value = match value {
+        toml::Value::String(value) => value.clone(),
+        toml::Value::Integer(value) => value.to_string(),
+        toml::Value::Float(value) => value.to_string(),
+        toml::Value::Boolean(value) => value.to_string(),
+        other => anyhow::bail!("unsupported config value {other}"),
+    };
+    // clap holds on to default values for the lifetime of the command
+    Ok(Box::leak(value.into_boxed_str()))
+}
@@ -1,7 +1,12 @@
-use crate::cmd::open_output_file;
+use crate::cmd::{descriptor::check_descriptor, print_result, AtomicFile};
 use anyhow::{Context, Result};
+use indexmap::IndexMap;
+use serde_json::json;
 use std::{io::Write, path::PathBuf};
-use xorf_generator::{Descriptor, Filter};
+use xorf_generator::{
+    Descriptor, DescriptorBuilder, Entries, Filter, FilterBackend, FilterCompression,
+    FilterOptions, DEFAULT_FINGERPRINT_BITS, DEFAULT_MIN_ENTRIES,
+};
 
 #[derive(clap::Args, Debug)]
 pub struct Cmd {
@@ -35,22 +40,269 @@ pub struct Generate {
     /// The input descriptor file to generate signing bytes for
     #[arg(default_value = "descriptor.bin.gz")]
     input: PathBuf,
-    /// The file to write the resulting signing bytes to
+    /// The file to write the resulting signing bytes to. With more than one
+    /// serial the serial is added to the file name, as in `data.7.bin`
     #[arg(default_value = "data.bin")]
     output: PathBuf,
-    /// The serial number embedded in the signing bytes
+    /// The serial number embedded in the signing bytes. Given more than once,
+    /// signing bytes are written for every serial while the filter is built
+    /// only once
+    #[arg(long, short, required = true)]
+    serial: Vec<u32>,
+    /// The descriptor entries to include in the filter
+    #[arg(long, value_enum, default_value_t = EntriesArg::Both)]
+    entries: EntriesArg,
+    /// Print a summary of the descriptor entries that went into the filter
+    #[arg(long)]
+    descriptor_stats: bool,
+    /// Warn when the filter holds fewer entries than this
+    #[arg(long, default_value_t = DEFAULT_MIN_ENTRIES)]
+    min_entries: usize,
+    /// Include a hash of the descriptor entries in the signing bytes, so the
+    /// signature covers the exact descriptor
+    #[arg(long)]
+    entries_hash: bool,
+    /// The filter implementation to build
+    #[arg(long, value_enum, default_value_t = BackendArg::Xor)]
+    backend: BackendArg,
+    /// The width of the filter fingerprints in bits, 8, 16 or 32. Widths below
+    /// 32 require the binary fuse backend
+    #[arg(long, default_value_t = DEFAULT_FINGERPRINT_BITS, value_parser = parse_fingerprint_bits)]
+    fingerprint_bits: u8,
+    /// The salt to build the filter with. By default a failed binary fuse
+    /// construction is retried with a few fresh salts, this builds with the
+    /// given salt only, for example to reproduce a failed build
+    #[arg(long, hide = true)]
+    seed: Option<u64>,
+    /// The number of threads to hash the descriptor entries with, defaults to
+    /// the number of cpus
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+    /// Additional descriptor files to merge into the filter
+    #[arg(long)]
+    merge: Vec<PathBuf>,
+    /// Write a json file recording which descriptors each entry came from
+    #[arg(long)]
+    provenance: Option<PathBuf>,
+    /// Whether to force overwrite an existing output file
     #[arg(long, short)]
-    serial: u32,
+    force: bool,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum EntriesArg {
+    Keys,
+    Edges,
+    Both,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum BackendArg {
+    Xor,
+    BinaryFuse,
+}
+
+impl From<BackendArg> for FilterBackend {
+    fn from(value: BackendArg) -> Self {
+        match value {
+            BackendArg::Xor => Self::Xor,
+            BackendArg::BinaryFuse => Self::BinaryFuse,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum CompressArg {
+    None,
+    Zstd,
+}
+
+impl From<CompressArg> for FilterCompression {
+    fn from(value: CompressArg) -> Self {
+        match value {
+            CompressArg::None => Self::None,
+            CompressArg::Zstd => Self::Zstd,
+        }
+    }
+}
+
+/// Parses a fingerprint width argument, which is 8, 16 or 32 bits
+pub fn parse_fingerprint_bits(value: &str) -> std::result::Result<u8, String> {
+    match value.parse::<u8>() {
+        Ok(bits @ (8 | 16 | 32)) => Ok(bits),
+        _ => Err(format!("{value} is not 8, 16 or 32")),
+    }
+}
+
+impl From<EntriesArg> for Entries {
+    fn from(value: EntriesArg) -> Self {
+        match value {
+            EntriesArg::Keys => Self::Keys,
+            EntriesArg::Edges => Self::Edges,
+            EntriesArg::Both => Self::Both,
+        }
+    }
 }
 
 impl Generate {
     pub fn run(&self) -> Result<()> {
-        let mut data_file = open_output_file(&self.output, false)?;
-        let descriptor = Descriptor::from_path(&self.input)
-            .context(format!("reading descriptor {}", self.input.display()))?;
-        let filter = Filter::from_descriptor(self.serial, &descriptor)?;
-        let signing_bytes = filter.to_signing_bytes()?;
-        data_file.write_all(&signing_bytes)?;
+        let outputs = self.outputs();
+        let mut data_files = outputs
+            .iter()
+            .map(|(_, path)| AtomicFile::create(path, !self.force))
+            .collect::<Result<Vec<_>>>()?;
+        let descriptor = self.read_descriptors()?;
+        let options = FilterOptions {
+            entries: self.entries.into(),
+            min_entries: self.min_entries,
+            entries_hash: self.entries_hash,
+            backend: self.backend.into(),
+            fingerprint_bits: self.fingerprint_bits,
+            salt: self.seed,
+        };
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.threads)
+            .build()?;
+        let (mut filter, stats) = pool
+            .install(|| Filter::from_descriptor_with_stats(outputs[0].0, &descriptor, &options))?;
+        if let Some(warning) = stats.warning() {
+            eprintln!("warning: {warning}");
+        }
+        // Only the serial differs between the signing bytes of each output
+        for ((serial, _), data_file) in outputs.iter().zip(&mut data_files) {
+            filter.serial = *serial;
+            data_file.write_all(&filter.to_signing_bytes()?)?;
+        }
+        for data_file in data_files {
+            data_file.commit()?;
+        }
+        if self.descriptor_stats {
+            print_result(&stats)?;
+        }
         Ok(())
     }
+
+    /// The serials to write signing bytes for with the path of each
+    fn outputs(&self) -> Vec<(u32, PathBuf)> {
+        if let [serial] = self.serial[..] {
+            return vec![(serial, self.output.clone())];
+        }
+        let stem = self
+            .output
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let extension = self
+            .output
+            .extension()
+            .map(|extension| format!(".{}", extension.to_string_lossy()))
+            .unwrap_or_default();
+        self.serial
+            .iter()
+            .map(|serial| {
+                let path = self
+                    .output
+                    .with_file_name(format!("{stem}.{serial}{extension}"));
+                (*serial, path)
+            })
+            .collect()
+    }
+
+    /// Reads the input descriptor and any descriptors to merge into it,
+    /// writing the provenance file if requested. A single input descriptor is
+    /// used as is.
+    fn read_descriptors(&self) -> Result<Descriptor> {
+        let sources: Vec<&PathBuf> = std::iter::once(&self.input).chain(&self.merge).collect();
+        let mut descriptors = Vec::with_capacity(sources.len());
+        let mut provenance: IndexMap<String, Vec<usize>> = IndexMap::new();
+        for (index, path) in sources.iter().enumerate() {
+            let descriptor = Descriptor::from_path(path)
+                .context(format!("reading descriptor {}", path.display()))?;
+            check_descriptor(&descriptor, path)?;
+            if self.provenance.is_some() {
+                let keys = descriptor.iter_keys().map(|key| key.to_string());
+                let edges = descriptor
+                    .iter_edges()
+                    .map(|(source, target)| format!("{source},{target}"));
+                for entry in keys.chain(edges) {
+                    provenance.entry(entry).or_default().push(index);
+                }
+            }
+            descriptors.push(descriptor);
+        }
+
+        if let Some(path) = &self.provenance {
+            let json = json!({
+                "sources": sources,
+                "entries": provenance,
+            });
+            let mut file = AtomicFile::create(path, !self.force)?;
+            serde_json::to_writer_pretty(&mut file, &json)?;
+            file.commit()?;
+        }
+
+        if descriptors.len() == 1 {
+            return Ok(descriptors.remove(0));
+        }
+        let mut builder = DescriptorBuilder::default();
+        for descriptor in &descriptors {
+            builder.add_descriptor(descriptor);
+        }
+        Ok(builder.build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::test_util::{
+        arg, descriptor, parse, test_dir, write_descriptor, write_key_manifest,
+    };
+    use std::fs;
+    use xorf_generator::{Manifest, PublicKeyManifest, FILTTER_VERSION};
+
+    #[test]
+    fn serials_share_the_filter_bytes() {
+        let dir = test_dir("data-generate-serials");
+        let input = dir.join("descriptor.bin.gz");
+        write_descriptor(&input, &descriptor(100, 20));
+        let key = dir.join("public_key.json");
+        let keypairs = write_key_manifest(&key, 2, 2);
+        let key_manifest = PublicKeyManifest::from_path(&key).unwrap();
+        parse::<Generate>(&[
+            arg(&input),
+            arg(&dir.join("data.bin")),
+            "-s",
+            "3",
+            "-s",
+            "4",
+            "-s",
+            "5",
+        ])
+        .run()
+        .unwrap();
+
+        let mut filters = vec![];
+        for serial in [3, 4, 5] {
+            let data = fs::read(dir.join(format!("data.{serial}.bin"))).unwrap();
+            let mut filter = Filter::from_signing_bytes(&data, FILTTER_VERSION).unwrap();
+            assert_eq!(filter.serial, serial);
+            let manifest = {
+                let mut manifest = Manifest::from_filter(&filter, &key_manifest).unwrap();
+                manifest.sign_with(&data, &keypairs).unwrap();
+                manifest
+            };
+            filter.apply_manifest(&manifest, &key_manifest).unwrap();
+            filters.push((data, filter));
+        }
+        assert!(!dir.join("data.bin").exists());
+
+        let (first_data, first) = &filters[0];
+        for (data, filter) in &filters[1..] {
+            // Everything after the leading serial is the shared filter
+            assert_eq!(data[4..], first_data[4..]);
+            assert_ne!(filter.hash().unwrap(), first.hash().unwrap());
+            assert_ne!(filter.signature, first.signature);
+        }
+    }
 }
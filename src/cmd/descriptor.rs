@@ -1,9 +1,17 @@
-use crate::cmd::{open_output_file, print_json};
+use crate::cmd::{open_output, print_result, AtomicFile, FileLock};
 use anyhow::{Context, Result};
 use helium_crypto::{PublicKey, PublicKeyBinary};
+use rayon::prelude::*;
 use serde_json::json;
-use std::path::PathBuf;
-use xorf_generator::Descriptor;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use xorf_generator::{
+    open_reader, Descriptor, DescriptorBuilder, DescriptorDiagnostic, DescriptorFile,
+    DescriptorSource, MergeStrategy,
+};
 
 #[derive(clap::Args, Debug)]
 pub struct Cmd {
@@ -21,18 +29,34 @@ impl Cmd {
 #[derive(clap::Subcommand, Debug)]
 pub enum DescriptorCommand {
     Generate(Generate),
+    Add(Add),
+    FromFilterLog(FromFilterLog),
     CountEdges(CountEdges),
     Find(Box<Find>),
+    Contains(Contains),
     Info(Info),
+    Validate(Validate),
+    Stats(Stats),
+    Dedupe(Dedupe),
+    Export(Export),
+    Merge(Merge),
 }
 
 impl DescriptorCommand {
     pub fn run(&self) -> Result<()> {
         match self {
             Self::Generate(cmd) => cmd.run(),
+            Self::Add(cmd) => cmd.run(),
+            Self::FromFilterLog(cmd) => cmd.run(),
             Self::CountEdges(cmd) => cmd.run(),
             Self::Find(cmd) => cmd.run(),
+            Self::Contains(cmd) => cmd.run(),
             Self::Info(cmd) => cmd.run(),
+            Self::Validate(cmd) => cmd.run(),
+            Self::Stats(cmd) => cmd.run(),
+            Self::Dedupe(cmd) => cmd.run(),
+            Self::Export(cmd) => cmd.run(),
+            Self::Merge(cmd) => cmd.run(),
         }
     }
 }
@@ -40,20 +64,506 @@ impl DescriptorCommand {
 /// Generate a descriptor file for the given csv file
 #[derive(Debug, clap::Args)]
 pub struct Generate {
-    /// The input csv file to generate a descriptor for
+    /// The input file to generate a descriptor for
     input: PathBuf,
     /// The file to write the resulting descriptor file to
     #[arg(default_value = "descriptor.bin.gz")]
     output: PathBuf,
+    /// The format of the input file. Auto detects it from the content
+    #[arg(long, value_enum, default_value_t = InputFormat::Auto)]
+    input_format: InputFormat,
+    /// Report the detected input format on stderr
+    #[arg(long, short)]
+    verbose: bool,
+    /// Whether to force overwrite an existing output file
+    #[arg(long, short)]
+    force: bool,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum InputFormat {
+    Auto,
+    Csv,
+    EventLog,
+    Descriptor,
+}
+
+impl InputFormat {
+    /// The descriptor file to read the given path as, detecting the format
+    /// from the content for auto
+    pub fn source(&self, path: &Path) -> Result<DescriptorFile> {
+        let path = path.to_path_buf();
+        Ok(match self {
+            Self::Auto => DescriptorFile::detect(&path)
+                .context(format!("detecting format of {}", path.display()))?,
+            Self::Csv => DescriptorFile::Csv(path),
+            Self::EventLog => DescriptorFile::EventLog(path),
+            Self::Descriptor => DescriptorFile::Proto(path),
+        })
+    }
 }
 
 impl Generate {
     pub fn run(&self) -> Result<()> {
-        let descriptor = Descriptor::from_csv(&self.input)
+        let source = self.input_format.source(&self.input)?;
+        if self.verbose {
+            eprintln!(
+                "reading {} as {}",
+                self.input.display(),
+                source.format_name()
+            );
+        }
+        let descriptor = source
+            .load()
+            .context(format!("reading descriptor {}", self.input.display()))?;
+        let mut file = AtomicFile::create(&self.output, !self.force)?;
+        descriptor.to_path(&mut file)?;
+        file.commit()
+    }
+}
+
+/// Export a descriptor to a file other tools can edit
+///
+/// The csv rows are `address,target_address,reason,carryover`, with a blank
+/// target for full nodes. Directed edges have a fifth `directed` column set to
+/// `true`. The rows can be read back with `descriptor generate`.
+#[derive(Debug, clap::Args)]
+pub struct Export {
+    /// The descriptor to export
+    #[arg(long, short, default_value = "descriptor.bin.gz")]
+    input: PathBuf,
+    /// The format to export to
+    #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+    format: ExportFormat,
+    /// The file to write to, defaults to stdout
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+    /// Whether to force overwrite an existing output file
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+}
+
+impl Export {
+    pub fn run(&self) -> Result<()> {
+        let descriptor = Descriptor::from_path(&self.input)
+            .context(format!("reading descriptor {}", self.input.display()))?;
+        match (self.format, &self.output) {
+            (ExportFormat::Csv, Some(output)) => {
+                descriptor.to_csv(open_output(output, !self.force)?)?
+            }
+            (ExportFormat::Csv, None) => descriptor.to_csv(std::io::stdout().lock())?,
+        }
+        Ok(())
+    }
+}
+
+/// Validate the public keys and values in a descriptor csv file
+///
+/// Rows are checked in parallel, but errors are always reported in file order.
+///
+/// Protobuf descriptors are checked entry by entry instead. Keys that do not
+/// decode and edges to missing keys are reported as errors, duplicate nodes
+/// and edges and self edges as warnings, each with its index in the
+/// descriptor.
+///
+/// Data quality policies can be checked as well. Sources with more than
+/// --max-edges-per-source distinct edge targets, and with --forbid-self-edge
+/// edges from a key to itself, are reported as violations with the offending
+/// addresses.
+#[derive(Debug, clap::Args)]
+pub struct Validate {
+    /// The input csv file or descriptor to validate
+    input: PathBuf,
+    /// The format of the input file. Auto detects it from the content, and
+    /// reads stdin as csv
+    #[arg(long, value_enum, default_value_t = InputFormat::Auto)]
+    input_format: InputFormat,
+    /// The number of threads to validate with, defaults to the number of cpus
+    #[arg(long, default_value_t = 0)]
+    threads: usize,
+    /// The most distinct edge targets a single source may have
+    #[arg(long)]
+    max_edges_per_source: Option<usize>,
+    /// Report edges from a key to itself
+    #[arg(long)]
+    forbid_self_edge: bool,
+}
+
+impl Validate {
+    pub fn run(&self) -> Result<()> {
+        // the format of stdin can not be detected
+        let format = match (self.input_format, self.input.as_os_str() == "-") {
+            (InputFormat::Auto, true) => InputFormat::Csv,
+            (format, _) => format,
+        };
+        if let DescriptorFile::Proto(path) = format.source(&self.input)? {
+            return self.run_descriptor(&path);
+        }
+        let reader = open_reader(&self.input)
+            .context(format!("reading descriptor {}", self.input.display()))?;
+        let records = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(reader)
+            .into_records()
+            .collect::<std::result::Result<Vec<_>, _>>()
             .context(format!("reading descriptor {}", self.input.display()))?;
-        descriptor.to_path(open_output_file(&self.output, false)?)?;
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.threads)
+            .build()?;
+        let errors: Vec<serde_json::Value> = pool.install(|| {
+            records
+                .par_iter()
+                .enumerate()
+                .filter_map(|(index, record)| {
+                    validate_record(record).err().map(|error| {
+                        json!({
+                            "line": index + 1,
+                            "error": error,
+                        })
+                    })
+                })
+                .collect()
+        });
+
+        let violations = self.policy_violations(&records);
+
+        let json = json!({
+            "rows": records.len(),
+            "valid": errors.is_empty() && violations.is_empty(),
+            "errors": errors,
+            "violations": violations,
+        });
+        print_result(&json)?;
+        if !errors.is_empty() {
+            anyhow::bail!("{} invalid rows in {}", errors.len(), self.input.display());
+        }
+        if !violations.is_empty() {
+            anyhow::bail!(
+                "{} policy violations in {}",
+                violations.len(),
+                self.input.display()
+            );
+        }
         Ok(())
     }
+
+    fn run_descriptor(&self, path: &Path) -> Result<()> {
+        let descriptor = Descriptor::from_path(path)
+            .context(format!("reading descriptor {}", path.display()))?;
+        let (errors, warnings): (Vec<_>, Vec<_>) = descriptor
+            .validate()
+            .into_iter()
+            .partition(DescriptorDiagnostic::is_error);
+        let json = json!({
+            "nodes": descriptor.key_count(),
+            "edges": descriptor.edge_count(),
+            "valid": errors.is_empty(),
+            "errors": errors,
+            "warnings": warnings,
+        });
+        print_result(&json)?;
+        if !errors.is_empty() {
+            anyhow::bail!("{} invalid entries in {}", errors.len(), path.display());
+        }
+        Ok(())
+    }
+
+    fn policy_violations(&self, records: &[csv::StringRecord]) -> Vec<serde_json::Value> {
+        let mut violations = vec![];
+        let mut targets: BTreeMap<&str, HashSet<&str>> = BTreeMap::new();
+        for (index, record) in records.iter().enumerate() {
+            let field = |index| record.get(index).map(str::trim).unwrap_or_default();
+            let (source, target) = (field(0), field(1));
+            if target.is_empty() {
+                continue;
+            }
+            if self.forbid_self_edge && source == target {
+                violations.push(json!({
+                    "policy": "self_edge",
+                    "line": index + 1,
+                    "address": source,
+                }));
+            }
+            targets.entry(source).or_default().insert(target);
+        }
+        if let Some(max) = self.max_edges_per_source {
+            violations.extend(
+                targets
+                    .iter()
+                    .filter(|(_, targets)| targets.len() > max)
+                    .map(|(source, targets)| {
+                        json!({
+                            "policy": "max_edges_per_source",
+                            "address": source,
+                            "edges": targets.len(),
+                            "max": max,
+                        })
+                    }),
+            );
+        }
+        violations
+    }
+}
+
+/// Validates a descriptor before a filter is built from it. Warnings are
+/// summarized on stderr and errors fail with the first of them, `descriptor
+/// validate` lists them all.
+pub fn check_descriptor(descriptor: &Descriptor, path: &Path) -> Result<()> {
+    let (errors, warnings): (Vec<_>, Vec<_>) = descriptor
+        .validate()
+        .into_iter()
+        .partition(DescriptorDiagnostic::is_error);
+    if let Some(warning) = warnings.first() {
+        eprintln!(
+            "warning: descriptor {} has {} duplicate or self edge entries, the first: {warning}",
+            path.display(),
+            warnings.len()
+        );
+    }
+    if let Some(error) = errors.first() {
+        anyhow::bail!(
+            "descriptor {} has {} invalid entries, the first: {error}",
+            path.display(),
+            errors.len()
+        );
+    }
+    Ok(())
+}
+
+/// Checks a "public_key,target_key,reason,carryover,directed" row
+fn validate_record(record: &csv::StringRecord) -> std::result::Result<(), String> {
+    let field = |index| record.get(index).map(str::trim).unwrap_or_default();
+    field(0)
+        .parse::<PublicKey>()
+        .map_err(|err| format!("invalid public key \"{}\": {err}", field(0)))?;
+    if !field(1).is_empty() {
+        field(1)
+            .parse::<PublicKey>()
+            .map_err(|err| format!("invalid target key \"{}\": {err}", field(1)))?;
+    }
+    if !field(3).is_empty() {
+        field(3)
+            .parse::<u32>()
+            .map_err(|err| format!("invalid carryover \"{}\": {err}", field(3)))?;
+    }
+    if !field(4).is_empty() {
+        field(4)
+            .parse::<bool>()
+            .map_err(|err| format!("invalid directed flag \"{}\": {err}", field(4)))?;
+    }
+    Ok(())
+}
+
+/// Generate a descriptor file by replaying a log of block and unblock events
+///
+/// The log has one json object per line with an "action" of "block" or
+/// "unblock", an "address" and, for edges, a "target". Only entries that are
+/// still blocked after the last event end up in the descriptor.
+#[derive(Debug, clap::Args)]
+pub struct FromFilterLog {
+    /// The event log to replay
+    input: PathBuf,
+    /// The file to write the resulting descriptor file to
+    #[arg(default_value = "descriptor.bin.gz")]
+    output: PathBuf,
+    /// Whether to force overwrite an existing output file
+    #[arg(long, short)]
+    force: bool,
+}
+
+impl FromFilterLog {
+    pub fn run(&self) -> Result<()> {
+        let descriptor = Descriptor::from_event_log(&self.input)
+            .context(format!("reading event log {}", self.input.display()))?;
+        let mut file = AtomicFile::create(&self.output, !self.force)?;
+        descriptor.to_path(&mut file)?;
+        file.commit()
+    }
+}
+
+/// Merge descriptor files into one
+///
+/// The entries of all inputs are combined. When inputs disagree on the reason
+/// or carryover of the same entry, --strategy decides which input wins, or
+/// fails the merge. The merged descriptor is written in canonical order, so
+/// merging the same inputs again gives the same file. The entries of every
+/// input and the number of conflicts are reported.
+#[derive(Debug, clap::Args)]
+pub struct Merge {
+    /// The descriptor files to merge, in order
+    inputs: Vec<PathBuf>,
+    /// A descriptor file to merge, merged after the positional inputs in the
+    /// order given
+    #[arg(long = "input", short)]
+    input_flags: Vec<PathBuf>,
+    /// The format of the input files. Auto detects csv, event logs and
+    /// protobuf descriptors from the content of each
+    #[arg(long, value_enum, default_value_t = InputFormat::Auto)]
+    input_format: InputFormat,
+    /// The file to write the merged descriptor to
+    #[arg(long, short, default_value = "descriptor.bin.gz")]
+    output: PathBuf,
+    /// Which input's reason and carryover to keep for conflicting entries
+    #[arg(long, value_enum, default_value_t = StrategyArg::First)]
+    strategy: StrategyArg,
+    /// Whether to force overwrite an existing output file
+    #[arg(long, short)]
+    force: bool,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum StrategyArg {
+    First,
+    Last,
+    Error,
+}
+
+impl From<StrategyArg> for MergeStrategy {
+    fn from(value: StrategyArg) -> Self {
+        match value {
+            StrategyArg::First => Self::First,
+            StrategyArg::Last => Self::Last,
+            StrategyArg::Error => Self::Error,
+        }
+    }
+}
+
+impl Merge {
+    pub fn run(&self) -> Result<()> {
+        let inputs: Vec<&PathBuf> = self.inputs.iter().chain(&self.input_flags).collect();
+        if inputs.len() < 2 {
+            anyhow::bail!("at least two descriptors are needed to merge");
+        }
+        let mut builder = DescriptorBuilder::default();
+        let mut conflicts = 0;
+        let mut files = Vec::with_capacity(inputs.len());
+        for input in &inputs {
+            let descriptor = self
+                .input_format
+                .source(input)?
+                .load()
+                .context(format!("reading descriptor {}", input.display()))?;
+            conflicts += builder
+                .merge_descriptor(&descriptor, self.strategy.into())
+                .context(format!("merging descriptor {}", input.display()))?;
+            files.push(json!({
+                "input": input,
+                "keys": descriptor.key_count(),
+                "edges": descriptor.edge_count(),
+            }));
+        }
+        let descriptor = builder.build();
+        let mut file = AtomicFile::create(&self.output, !self.force)?;
+        descriptor.to_path(&mut file)?;
+        file.commit()?;
+        let json = json!({
+            "inputs": inputs.len(),
+            "files": files,
+            "keys": descriptor.key_count(),
+            "edges": descriptor.edge_count(),
+            "conflicts": conflicts,
+            "output": self.output,
+        });
+        print_result(&json)
+    }
+}
+
+/// Add public keys or edges to a descriptor file, creating it if it does not
+/// exist
+///
+/// The resulting descriptor is deduplicated and written in canonical order.
+/// Concurrent edits of the same descriptor are serialized with a lock file.
+#[derive(Debug, clap::Args)]
+pub struct Add {
+    /// The descriptor file to add entries to
+    #[arg(long, short, default_value = "descriptor.bin.gz")]
+    input: PathBuf,
+    /// A public key to add as a full node
+    #[arg(long)]
+    key: Vec<PublicKey>,
+    /// An edge to add, given as "source,target"
+    #[arg(long, value_parser = parse_edge)]
+    edge: Vec<(PublicKey, PublicKey)>,
+    /// Add the edges as directed, blocking only the source from the target
+    #[arg(long, requires = "edge")]
+    directed: bool,
+    /// The reason recorded for the added entries
+    #[arg(long)]
+    reason: Option<String>,
+    /// The carryover value recorded for the added entries
+    #[arg(long, default_value_t = 0)]
+    carryover: u32,
+    /// Seconds to wait for another edit of the descriptor to finish
+    #[arg(long, default_value_t = 30)]
+    lock_timeout: u64,
+}
+
+impl Add {
+    pub fn run(&self) -> Result<()> {
+        if self.key.is_empty() && self.edge.is_empty() {
+            anyhow::bail!("No --key or --edge given to add");
+        }
+        let _lock = FileLock::acquire(&self.input, Duration::from_secs(self.lock_timeout))?;
+        let descriptor = if self.input.exists() {
+            Descriptor::from_path(&self.input)
+                .context(format!("reading descriptor {}", self.input.display()))?
+        } else {
+            Descriptor::default()
+        };
+
+        let mut builder = DescriptorBuilder::from_descriptor(&descriptor);
+        let mut added = 0;
+        for key in &self.key {
+            if builder.add_node(key.clone().into(), self.reason.clone(), self.carryover) {
+                added += 1;
+            }
+        }
+        for (source, target) in &self.edge {
+            let (source, target) = (source.clone().into(), target.clone().into());
+            let reason = self.reason.clone();
+            let edge_added = if self.directed {
+                builder.add_directed_edge(&source, &target, reason, self.carryover)
+            } else {
+                builder.add_edge(&source, &target, reason, self.carryover)
+            };
+            if edge_added {
+                added += 1;
+            }
+        }
+        let descriptor = builder.build();
+
+        let mut file = AtomicFile::create(&self.input, false)?;
+        descriptor.to_path(&mut file)?;
+        file.commit()?;
+
+        let json = json!({
+            "added": added,
+            "nodes": descriptor.key_count(),
+            "edges": descriptor.edge_count(),
+        });
+        print_result(&json)
+    }
+}
+
+fn parse_edge(value: &str) -> std::result::Result<(PublicKey, PublicKey), String> {
+    let (source, target) = value
+        .split_once(',')
+        .ok_or_else(|| format!("expected \"source,target\", got \"{value}\""))?;
+    let source = source
+        .trim()
+        .parse::<PublicKey>()
+        .map_err(|err| format!("{err}"))?;
+    let target = target
+        .trim()
+        .parse::<PublicKey>()
+        .map_err(|err| format!("{err}"))?;
+    Ok((source, target))
 }
 
 /// Generate a json file with the number of edges per public key in a descriptor
@@ -67,6 +577,9 @@ pub struct CountEdges {
     /// The file to write the resulting edge counts to
     #[arg(default_value = "edge_counts.json")]
     output: PathBuf,
+    /// Whether to force overwrite an existing output file
+    #[arg(long, short)]
+    force: bool,
 }
 
 impl CountEdges {
@@ -74,9 +587,9 @@ impl CountEdges {
         let descriptor = Descriptor::from_path(&self.input)
             .context(format!("reading descriptor {}", self.input.display()))?;
         let counts = descriptor.edge_counts();
-        let file = open_output_file(&self.output, false)?;
-        serde_json::to_writer_pretty(file, &counts)?;
-        Ok(())
+        let mut file = AtomicFile::create(&self.output, !self.force)?;
+        serde_json::to_writer_pretty(&mut file, &counts)?;
+        file.commit()
     }
 }
 
@@ -105,7 +618,45 @@ impl Find {
         if !edges.is_empty() {
             json["edges"] = serde_json::to_value(edges)?;
         }
-        print_json(&json)
+        print_result(&json)
+    }
+}
+
+/// Check whether a key or edge is in a descriptor
+///
+/// Unlike `filter contains` the check is exact, without false positives, so
+/// it can be used to audit a filter against its source. With --target the
+/// edge between the two keys is checked, in either order.
+#[derive(clap::Args, Debug)]
+pub struct Contains {
+    /// The descriptor file to check for membership
+    #[arg(long, short, default_value = "descriptor.bin.gz")]
+    input: PathBuf,
+    /// The public key to check
+    #[arg(long)]
+    key: PublicKey,
+    /// The public key of the target of an edge to check
+    #[arg(long)]
+    target: Option<PublicKey>,
+}
+
+impl Contains {
+    pub fn run(&self) -> Result<()> {
+        let descriptor = Descriptor::from_path(&self.input)
+            .context(format!("reading descriptor {}", self.input.display()))?;
+        let key = PublicKeyBinary::from(self.key.clone());
+        let json = match &self.target {
+            Some(target) => json!({
+                "key": key.to_string(),
+                "target": target.to_string(),
+                "in_descriptor": descriptor.contains_edge(&key, &target.clone().into()),
+            }),
+            None => json!({
+                "key": key.to_string(),
+                "in_descriptor": descriptor.contains(&key),
+            }),
+        };
+        print_result(&json)
     }
 }
 
@@ -122,18 +673,313 @@ impl Info {
         let descriptor = Descriptor::from_path(&self.input)
             .context(format!("reading descriptor {}", self.input.display()))?;
 
-        let node_count = descriptor.nodes.len();
-        let (key_count, edge_count) = descriptor
+        let key_count = descriptor
             .edges
-            .map(|edges| (edges.keys.len(), edges.edges.len()))
-            .unwrap_or((0, 0));
+            .as_ref()
+            .map_or(0, |edges| edges.keys.len());
         let json = json!({
-            "nodes": node_count,
+            "nodes": descriptor.key_count(),
             "edges": {
-                "edges": edge_count,
+                "edges": descriptor.edge_count(),
                 "keys": key_count,
             }
         });
-        print_json(&json)
+        print_result(&json)
+    }
+}
+
+/// Print statistics on how the full node keys of a descriptor are distributed
+///
+/// With --histogram keys are bucketed by the hex of their first bytes after
+/// the key type byte, which is the same for all keys of a network and key type.
+///
+/// The edges are summarized by their ratio to full nodes, the number of
+/// distinct edge sources, and the addresses with the most edges.
+#[derive(clap::Args, Debug)]
+pub struct Stats {
+    /// The descriptor file to report statistics for
+    #[arg(long, short, default_value = "descriptor.bin.gz")]
+    input: PathBuf,
+    /// Report the number of keys per key prefix
+    #[arg(long)]
+    histogram: bool,
+    /// The number of key bytes to bucket keys by
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=4))]
+    prefix_bytes: u8,
+    /// The number of most connected addresses to report
+    #[arg(long, default_value_t = 10)]
+    top: usize,
+    /// The format to report in. Csv reports a header and a single row of the
+    /// scalar metrics, without the most connected addresses and histogram
+    #[arg(long, value_enum, default_value_t = StatsFormat::Json)]
+    output: StatsFormat,
+    /// The file to write the csv report to, defaults to stdout
+    #[arg(long)]
+    output_file: Option<PathBuf>,
+    /// Whether to force overwrite an existing output file
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum StatsFormat {
+    Json,
+    Csv,
+}
+
+/// The columns of the csv stats report, in order
+const STATS_COLUMNS: &[&str] = &[
+    "keys",
+    "edges",
+    "edge_key_ratio",
+    "edge_sources",
+    "prefix_bytes",
+    "buckets",
+    "largest_bucket",
+];
+
+impl Stats {
+    pub fn run(&self) -> Result<()> {
+        let descriptor = Descriptor::from_path(&self.input)
+            .context(format!("reading descriptor {}", self.input.display()))?;
+
+        let mut buckets: BTreeMap<String, usize> = BTreeMap::new();
+        for key in descriptor.iter_keys() {
+            let bytes: &[u8] = key.as_ref();
+            let prefix: String = bytes
+                .iter()
+                .skip(1)
+                .take(self.prefix_bytes as usize)
+                .map(|byte| format!("{byte:02x}"))
+                .collect();
+            *buckets.entry(prefix).or_default() += 1;
+        }
+
+        let mut sources: HashSet<PublicKeyBinary> = HashSet::new();
+        let mut degrees: HashMap<PublicKeyBinary, usize> = HashMap::new();
+        for (source, target) in descriptor.iter_edges() {
+            *degrees.entry(source.clone()).or_default() += 1;
+            *degrees.entry(target).or_default() += 1;
+            sources.insert(source);
+        }
+        let mut top: Vec<(PublicKeyBinary, usize)> = degrees.into_iter().collect();
+        top.sort_by(|(a_key, a_edges), (b_key, b_edges)| {
+            b_edges
+                .cmp(a_edges)
+                .then_with(|| a_key.to_string().cmp(&b_key.to_string()))
+        });
+        top.truncate(self.top);
+        let keys = descriptor.key_count();
+        let edges = descriptor.edge_count();
+
+        let mut json = json!({
+            "keys": keys,
+            "edges": edges,
+            "edge_key_ratio": (keys > 0).then(|| edges as f64 / keys as f64),
+            "edge_sources": sources.len(),
+            "top_connected": top
+                .iter()
+                .map(|(key, edges)| json!({ "address": key.to_string(), "edges": edges }))
+                .collect::<Vec<_>>(),
+            "prefix_bytes": self.prefix_bytes,
+            "buckets": buckets.len(),
+            "largest_bucket": buckets.values().max().copied().unwrap_or(0),
+        });
+        if self.histogram {
+            json["histogram"] = serde_json::to_value(&buckets)?;
+        }
+        match (self.output, &self.output_file) {
+            (StatsFormat::Json, _) => print_result(&json),
+            (StatsFormat::Csv, Some(path)) => {
+                write_stats_csv(&json, open_output(path, !self.force)?)
+            }
+            (StatsFormat::Csv, None) => write_stats_csv(&json, std::io::stdout().lock()),
+        }
+    }
+}
+
+fn write_stats_csv<W: std::io::Write>(json: &serde_json::Value, writer: W) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    writer.write_record(STATS_COLUMNS)?;
+    writer.write_record(STATS_COLUMNS.iter().map(|column| match &json[*column] {
+        serde_json::Value::Null => String::new(),
+        value => value.to_string(),
+    }))?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Report or remove duplicate entries of a descriptor file
+///
+/// Duplicate keys, duplicate edges and edges that are already covered by a
+/// full node for one of their keys are listed. With --report nothing is
+/// written, otherwise the deduplicated descriptor is written to the output.
+#[derive(clap::Args, Debug)]
+pub struct Dedupe {
+    /// The descriptor file to deduplicate
+    #[arg(long, short, default_value = "descriptor.bin.gz")]
+    input: PathBuf,
+    /// Only report duplicates without writing a deduplicated descriptor
+    #[arg(long, conflicts_with = "output")]
+    report: bool,
+    /// The file to write the deduplicated descriptor to
+    #[arg(long, short, required_unless_present = "report")]
+    output: Option<PathBuf>,
+    /// Whether to force overwrite an existing output file
+    #[arg(long, short)]
+    force: bool,
+}
+
+impl Dedupe {
+    pub fn run(&self) -> Result<()> {
+        let descriptor = Descriptor::from_path(&self.input)
+            .context(format!("reading descriptor {}", self.input.display()))?;
+        let node_keys: HashSet<PublicKeyBinary> = descriptor.iter_keys().collect();
+
+        let mut builder = DescriptorBuilder::default();
+        let mut duplicate_keys = vec![];
+        for key in descriptor.iter_keys() {
+            if !builder.add_node(key.clone(), None, 0) {
+                duplicate_keys.push(key.to_string());
+            }
+        }
+        let mut duplicate_edges = vec![];
+        let mut covered_edges = vec![];
+        for (source, target, directed) in descriptor.iter_edges_directed() {
+            let added = if directed {
+                builder.add_directed_edge(&source, &target, None, 0)
+            } else {
+                builder.add_edge(&source, &target, None, 0)
+            };
+            if !added {
+                let edge = json!({
+                    "address": source.to_string(),
+                    "target": target.to_string(),
+                });
+                if node_keys.contains(&source) || node_keys.contains(&target) {
+                    covered_edges.push(edge);
+                } else {
+                    duplicate_edges.push(edge);
+                }
+            }
+        }
+
+        let json = json!({
+            "keys": descriptor.key_count(),
+            "edges": descriptor.edge_count(),
+            "duplicate_keys": duplicate_keys,
+            "duplicate_edges": duplicate_edges,
+            "covered_edges": covered_edges,
+        });
+        print_result(&json)?;
+
+        if let Some(output) = &self.output {
+            // Rebuild from the descriptor itself to keep reasons and carryover
+            let deduped = DescriptorBuilder::from_descriptor(&descriptor).build();
+            let mut file = AtomicFile::create(output, !self.force)?;
+            deduped.to_path(&mut file)?;
+            file.commit()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::test_util::{arg, capture_results, key, parse, test_dir, write_descriptor};
+    use std::fs;
+
+    #[test]
+    fn stats_of_a_known_structure() {
+        let dir = test_dir("descriptor-stats");
+        let [hub, a, b, c] = [key(), key(), key(), key()];
+        let mut builder = DescriptorBuilder::default();
+        builder.add_node(key(), None, 0);
+        builder.add_node(key(), None, 0);
+        for target in [&a, &b, &c] {
+            builder.add_directed_edge(&hub, target, None, 0);
+        }
+        builder.add_directed_edge(&a, &b, None, 0);
+        let input = dir.join("descriptor.bin.gz");
+        write_descriptor(&input, &builder.build());
+
+        let (result, printed) =
+            capture_results(|| parse::<Stats>(&["--input", arg(&input), "--top", "3"]).run());
+        result.unwrap();
+        let stats = &printed[0];
+        assert_eq!(stats["keys"], 2);
+        assert_eq!(stats["edges"], 4);
+        assert_eq!(stats["edge_key_ratio"], 2.0);
+        assert_eq!(stats["edge_sources"], 2);
+        let mut tied = [a.to_string(), b.to_string()];
+        tied.sort();
+        let top: Vec<(String, u64)> = stats["top_connected"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| {
+                (
+                    entry["address"].as_str().unwrap().to_string(),
+                    entry["edges"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            top,
+            [
+                (hub.to_string(), 3),
+                (tied[0].clone(), 2),
+                (tied[1].clone(), 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_reports_each_policy() {
+        let dir = test_dir("validate-policies");
+        let [hub, other, looped] = [key(), key(), key()];
+        let rows: Vec<String> = (0..3)
+            .map(|_| format!("{hub},{},,0", key()))
+            .chain([
+                format!("{other},{},,0", key()),
+                format!("{looped},{looped},,0"),
+                format!("{},,,0", key()),
+            ])
+            .collect();
+        let input = dir.join("descriptor.csv");
+        fs::write(&input, rows.join("\n")).unwrap();
+        let validate = |policy: &[&str]| {
+            let mut args = vec![arg(&input), "--input-format", "csv"];
+            args.extend(policy);
+            capture_results(|| parse::<Validate>(&args).run())
+        };
+
+        let (result, printed) = validate(&[]);
+        result.unwrap();
+        assert_eq!(printed[0]["valid"], true);
+
+        let (result, printed) = validate(&["--max-edges-per-source", "2"]);
+        assert!(result.is_err());
+        assert_eq!(
+            printed[0]["violations"],
+            json!([{
+                "policy": "max_edges_per_source",
+                "address": hub.to_string(),
+                "edges": 3,
+                "max": 2,
+            }])
+        );
+
+        let (result, printed) = validate(&["--forbid-self-edge"]);
+        assert!(result.is_err());
+        assert_eq!(
+            printed[0]["violations"],
+            json!([{
+                "policy": "self_edge",
+                "line": 5,
+                "address": looped.to_string(),
+            }])
+        );
     }
 }
@@ -0,0 +1,174 @@
+use anyhow::Result;
+use clap::CommandFactory;
+
+/// Print the sequence of commands for a signing workflow
+///
+/// The commands are rendered from the definitions of the commands themselves,
+/// so the printed flags and defaults always match this build. Arguments that
+/// have to be filled in are shown as `<PLACEHOLDERS>`.
+#[derive(Debug, clap::Args)]
+pub struct Cmd {
+    /// The workflow to print the commands for
+    #[arg(value_enum, default_value_t = Workflow::Multisig)]
+    workflow: Workflow,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Workflow {
+    /// A coordinator holding the keys of all required members signs alone
+    SingleSigner,
+    /// Members sign the signing data with their own wallets
+    Multisig,
+}
+
+/// A command of a workflow and the arguments to show for it. Arguments are
+/// given by id with an optional example value. Required arguments are always
+/// shown.
+struct Step {
+    path: &'static [&'static str],
+    args: &'static [(&'static str, Option<&'static str>)],
+    note: Option<&'static str>,
+}
+
+const SINGLE_SIGNER: &[Step] = &[
+    Step {
+        path: &["key", "info"],
+        args: &[],
+        note: None,
+    },
+    Step {
+        path: &["descriptor", "generate"],
+        args: &[("input", Some("hotspots.csv"))],
+        note: None,
+    },
+    Step {
+        path: &["data", "generate"],
+        args: &[],
+        note: None,
+    },
+    Step {
+        path: &["manifest", "generate"],
+        args: &[],
+        note: None,
+    },
+    Step {
+        path: &["manifest", "sign"],
+        args: &[("keys_dir", Some("keys/"))],
+        note: None,
+    },
+    Step {
+        path: &["filter", "generate"],
+        args: &[],
+        note: None,
+    },
+    Step {
+        path: &["filter", "verify"],
+        args: &[],
+        note: None,
+    },
+];
+
+const MULTISIG: &[Step] = &[
+    Step {
+        path: &["key", "info"],
+        args: &[],
+        note: None,
+    },
+    Step {
+        path: &["descriptor", "generate"],
+        args: &[("input", Some("hotspots.csv"))],
+        note: None,
+    },
+    Step {
+        path: &["data", "generate"],
+        args: &[],
+        note: None,
+    },
+    Step {
+        path: &["manifest", "generate"],
+        args: &[],
+        note: Some("each member signs data.bin and hands back the signature, for example with `helium_wallet -f <wallet.key> sign file data.bin`"),
+    },
+    Step {
+        path: &["manifest", "sign"],
+        args: &[("signer", None), ("signature_file", None)],
+        note: Some("repeat for every member signature"),
+    },
+    Step {
+        path: &["filter", "generate"],
+        args: &[],
+        note: None,
+    },
+    Step {
+        path: &["filter", "verify"],
+        args: &[],
+        note: None,
+    },
+];
+
+impl Cmd {
+    pub fn run(&self) -> Result<()> {
+        let steps = match self.workflow {
+            Workflow::SingleSigner => SINGLE_SIGNER,
+            Workflow::Multisig => MULTISIG,
+        };
+        let root = crate::Cli::command();
+        for step in steps {
+            let mut command = &root;
+            for name in step.path {
+                command = command.find_subcommand(name).ok_or_else(|| {
+                    anyhow::anyhow!("unknown example command {}", step.path.join(" "))
+                })?;
+            }
+            if let Some(about) = command.get_about() {
+                println!("# {about}");
+            }
+            println!("$ {}", render(root.get_name(), step, command)?);
+            if let Some(note) = step.note {
+                println!("# {note}");
+            }
+            println!();
+        }
+        Ok(())
+    }
+}
+
+/// Renders the command line of a step, with the listed arguments followed by
+/// any other required ones
+fn render(bin: &str, step: &Step, command: &clap::Command) -> Result<String> {
+    let mut line = vec![bin.to_string()];
+    line.extend(step.path.iter().map(|name| name.to_string()));
+    for (id, value) in step.args {
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_id() == *id)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "unknown argument {id} in example command {}",
+                    step.path.join(" ")
+                )
+            })?;
+        line.extend(render_arg(arg, *value));
+    }
+    for arg in command.get_arguments().filter(|arg| arg.is_required_set()) {
+        if !step.args.iter().any(|(id, _)| arg.get_id() == *id) {
+            line.extend(render_arg(arg, None));
+        }
+    }
+    Ok(line.join(" "))
+}
+
+fn render_arg(arg: &clap::Arg, value: Option<&str>) -> Vec<String> {
+    let placeholder = || format!("<{}>", arg.get_id().as_str().to_uppercase());
+    let value = value.map(str::to_string).unwrap_or_else(|| {
+        arg.get_default_values()
+            .first()
+            .map(|value| value.to_string_lossy().into_owned())
+            .unwrap_or_else(placeholder)
+    });
+    match arg.get_long() {
+        Some(long) if arg.get_action().takes_values() => vec![format!("--{long}"), value],
+        Some(long) => vec![format!("--{long}")],
+        None => vec![value],
+    }
+}
@@ -1,9 +1,31 @@
-use crate::cmd::{open_output_file, print_json};
+use crate::cmd::{
+    data::{parse_fingerprint_bits, BackendArg, CompressArg, EntriesArg},
+    descriptor::InputFormat,
+    human_size, open_output, print_result, print_result_line, read_manifest, AtomicFile, ErrorMode,
+    InlineKey, OutputName,
+};
 use anyhow::{Context, Result};
-use helium_crypto::PublicKey;
+use helium_crypto::{KeyTag, KeyType, Keypair, Network, PublicKey, PublicKeyBinary};
+use rand::{rngs::OsRng, seq::IteratorRandom};
 use serde_json::json;
-use std::{io::Write, path::PathBuf};
-use xorf_generator::{base64_serde, Filter, Manifest, PublicKeyManifest, FILTTER_VERSION};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use xorf::{BinaryFuse16, BinaryFuse32, BinaryFuse8, Filter as XorfFilter};
+use xorf_generator::{
+    base64_serde, build_signed_filter_with_stats, open_input, public_key_hash, read_input,
+    Descriptor, DescriptorBuilder, DescriptorSource, EmbeddedManifest, Entries, Error, Filter,
+    FilterOptions, FilterVerifier, Manifest, MembershipFilter, PublicKeyManifest,
+    DEFAULT_FINGERPRINT_BITS, FILTTER_VERSION,
+};
 
 #[derive(clap::Args, Debug)]
 pub struct Cmd {
@@ -22,8 +44,26 @@ impl Cmd {
 pub enum FilterCommand {
     Generate(Generate),
     Contains(Contains),
+    ContainsBatch(ContainsBatch),
     Verify(Verify),
+    VerifyBatch(VerifyBatch),
     Info(Info),
+    DiffBits(DiffBits),
+    Sample(Sample),
+    VerifyChain(VerifyChain),
+    Prune(Prune),
+    Blocked(Blocked),
+    Analyze(Analyze),
+    Serve(Serve),
+    Intersect(Intersect),
+    SigningBytes(SigningBytes),
+    Repack(Repack),
+    Tap(Tap),
+    Fuzz(Fuzz),
+    Bench(Bench),
+    Diff(Diff),
+    Stats(Stats),
+    Selftest(Selftest),
 }
 
 impl FilterCommand {
@@ -31,113 +71,1480 @@ impl FilterCommand {
         match self {
             Self::Generate(cmd) => cmd.run(),
             Self::Contains(cmd) => cmd.run(),
+            Self::ContainsBatch(cmd) => cmd.run(),
             Self::Verify(cmd) => cmd.run(),
+            Self::VerifyBatch(cmd) => cmd.run(),
             Self::Info(cmd) => cmd.run(),
+            Self::DiffBits(cmd) => cmd.run(),
+            Self::Sample(cmd) => cmd.run(),
+            Self::VerifyChain(cmd) => cmd.run(),
+            Self::Prune(cmd) => cmd.run(),
+            Self::Blocked(cmd) => cmd.run(),
+            Self::Analyze(cmd) => cmd.run(),
+            Self::Serve(cmd) => cmd.run(),
+            Self::Intersect(cmd) => cmd.run(),
+            Self::SigningBytes(cmd) => cmd.run(),
+            Self::Repack(cmd) => cmd.run(),
+            Self::Tap(cmd) => cmd.run(),
+            Self::Fuzz(cmd) => cmd.run(),
+            Self::Bench(cmd) => cmd.run(),
+            Self::Diff(cmd) => cmd.run(),
+            Self::Stats(cmd) => cmd.run(),
+            Self::Selftest(cmd) => cmd.run(),
         }
     }
 }
 
 /// Check if a given filter file contains a given public key or edge.
+///
+/// Keys can also be given by a friendly name when a names file is passed. The
+/// names file is a json object mapping names to public keys.
+///
+/// With --descriptor every key and edge of the descriptor is checked instead,
+/// and any entry missing from the filter is reported as an error.
+///
+/// With --needles every key and edge of a descriptor is looked up, and an
+/// array with the result for each entry is reported.
+///
+/// With --allow the decision after the allowlist override is reported as
+/// `effective_block`. Like `filter prune`, a key that is allowlisted, or an
+/// edge touching one, is never blocked even when it is in the filter.
 #[derive(clap::Args, Debug)]
 pub struct Contains {
     /// The filter to check for membership
     #[arg(long, short, default_value = "filter.bin")]
     input: PathBuf,
-    /// The public key to check
-    key: PublicKey,
-    /// The publc key of the target of an edge to check
-    target: Option<PublicKey>,
+    /// A json file mapping friendly names to public keys
+    #[arg(long)]
+    names: Option<PathBuf>,
+    /// Check that all entries of this descriptor are in the filter
+    #[arg(long, conflicts_with_all = ["key", "target", "names"])]
+    descriptor: Option<PathBuf>,
+    /// Look up every key and edge of this descriptor in the filter
+    #[arg(long, conflicts_with_all = ["key", "target", "names", "descriptor", "allow"])]
+    needles: Option<PathBuf>,
+    /// The public key or name to check
+    #[arg(required_unless_present_any = ["descriptor", "needles"])]
+    key: Option<String>,
+    /// The publc key or name of the target of an edge to check
+    target: Option<String>,
+    /// Also match directed edges between the keys in either direction, for
+    /// when the direction of the edge is not known. This triples the false
+    /// positive rate of the edge check
+    #[arg(long, requires = "target")]
+    either_direction: bool,
+    /// A json array of allowlisted public keys that override the filter
+    #[arg(long, conflicts_with = "descriptor")]
+    allow: Option<PathBuf>,
 }
 
 impl Contains {
     pub fn run(&self) -> Result<()> {
         let filter = Filter::from_path(&self.input)
             .context(format!("reading filter {}", self.input.display()))?;
-        let source = self.key.clone().into();
-        let in_filter = if let Some(target) = self.target.clone() {
-            filter.contains_edge(&source, &target.into())
+        if let Some(path) = &self.descriptor {
+            let descriptor = Descriptor::from_path(path)
+                .context(format!("reading descriptor {}", path.display()))?;
+            return descriptor_coverage(&filter, &descriptor);
+        }
+        if let Some(path) = &self.needles {
+            return print_result(&needles_json(&filter, path)?);
+        }
+        let Some(key) = &self.key else {
+            anyhow::bail!("No public key given to check");
+        };
+        let names = self.names.as_deref().map(read_names).transpose()?;
+        let key = resolve_key(names.as_ref(), key)?;
+        let target = self
+            .target
+            .as_ref()
+            .map(|target| resolve_key(names.as_ref(), target))
+            .transpose()?;
+        let mut json = contains_json(&filter, &key, target.as_ref(), self.either_direction);
+        if let Some(path) = &self.allow {
+            let allowlist: HashSet<PublicKeyBinary> =
+                read_key_list(path, "allowlist")?.into_iter().collect();
+            let allowlisted = std::iter::once(&key)
+                .chain(target.as_ref())
+                .any(|key| allowlist.contains(&PublicKeyBinary::from(key.clone())));
+            let in_filter = json["in_filter"].as_bool().unwrap_or_default();
+            json["allowlisted"] = allowlisted.into();
+            json["effective_block"] = (in_filter && !allowlisted).into();
+        }
+        print_result(&json)
+    }
+}
+
+/// Looks up every key and edge of a descriptor in the filter
+fn needles_json(filter: &Filter, path: &Path) -> Result<Vec<serde_json::Value>> {
+    let descriptor =
+        Descriptor::from_path(path).context(format!("reading descriptor {}", path.display()))?;
+    let to_key = |key: &PublicKeyBinary| {
+        let bytes: &[u8] = key.as_ref();
+        PublicKey::try_from(bytes).context(format!("invalid public key {key}"))
+    };
+    let mut results = vec![];
+    for key in descriptor.iter_keys() {
+        results.push(contains_json(filter, &to_key(&key)?, None, false));
+    }
+    for (source, target) in descriptor.iter_edges() {
+        results.push(contains_json(
+            filter,
+            &to_key(&source)?,
+            Some(&to_key(&target)?),
+            false,
+        ));
+    }
+    Ok(results)
+}
+
+/// Whether the filter has a descriptor edge, looked up with the one hash it
+/// was inserted with
+pub(crate) fn contains_descriptor_edge(
+    filter: &Filter,
+    source: &PublicKeyBinary,
+    target: &PublicKeyBinary,
+    directed: bool,
+) -> bool {
+    if directed {
+        filter.contains_directed_edge(source, target)
+    } else {
+        filter.contains_edge(source, target)
+    }
+}
+
+/// Checks that every key and edge of a descriptor is in the filter
+fn descriptor_coverage(filter: &Filter, descriptor: &Descriptor) -> Result<()> {
+    let mut missing = vec![];
+    for key in descriptor.iter_keys() {
+        if !filter.contains(&key) {
+            missing.push(json!({ "address": key.to_string() }));
+        }
+    }
+    for (source, target, directed) in descriptor.iter_edges_directed() {
+        if !contains_descriptor_edge(filter, &source, &target, directed) {
+            missing.push(json!({
+                "address": source.to_string(),
+                "target": target.to_string(),
+            }));
+        }
+    }
+    let entries = descriptor.key_count() + descriptor.edge_count();
+    let json = json!({
+        "keys": descriptor.key_count(),
+        "edges": descriptor.edge_count(),
+        "present": entries - missing.len(),
+        "missing": missing,
+    });
+    print_result(&json)?;
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "{} descriptor entries are missing from the filter",
+            missing.len()
+        );
+    }
+    Ok(())
+}
+
+fn read_names(path: &Path) -> Result<HashMap<String, PublicKey>> {
+    let file = fs::File::open(path).context(format!("reading names {}", path.display()))?;
+    let names: HashMap<String, String> = serde_json::from_reader(BufReader::new(file))
+        .context(format!("reading names {}", path.display()))?;
+    names
+        .into_iter()
+        .map(|(name, key)| {
+            let key = key
+                .parse::<PublicKey>()
+                .context(format!("invalid public key \"{key}\" for name \"{name}\""))?;
+            Ok((name, key))
+        })
+        .collect()
+}
+
+/// Resolves a friendly name through the given names, falling back to parsing
+/// the value as a public key
+fn resolve_key(names: Option<&HashMap<String, PublicKey>>, value: &str) -> Result<PublicKey> {
+    if let Some(key) = names.and_then(|names| names.get(value)) {
+        return Ok(key.clone());
+    }
+    match value.parse::<PublicKey>() {
+        Ok(key) => Ok(key),
+        Err(_) if names.is_some() => anyhow::bail!("unknown name or public key \"{value}\""),
+        Err(_) => anyhow::bail!("invalid public key \"{value}\""),
+    }
+}
+
+/// Check that every entry of the descriptor a filter was built from is in it
+///
+/// A correctly built filter has no false negatives, so every key and edge of
+/// its descriptor must test positive. Any entry that does not means the filter
+/// is corrupt or was built from other entries, another fingerprint width or
+/// another seed. Such entries are listed and fail the command.
+#[derive(clap::Args, Debug)]
+pub struct Selftest {
+    /// The filter to check
+    #[arg(long, short, default_value = "filter.bin")]
+    input: PathBuf,
+    /// The descriptor the filter was built from
+    #[arg(long, short, default_value = "descriptor.bin.gz")]
+    descriptor: PathBuf,
+    /// The format of the descriptor. Auto detects csv, event logs and
+    /// protobuf descriptors from the content
+    #[arg(long, value_enum, default_value_t = InputFormat::Auto)]
+    descriptor_format: InputFormat,
+}
+
+impl Selftest {
+    pub fn run(&self) -> Result<()> {
+        let filter = Filter::from_path(&self.input)
+            .context(format!("reading filter {}", self.input.display()))?;
+        let descriptor = self
+            .descriptor_format
+            .source(&self.descriptor)?
+            .load()
+            .context(format!("reading descriptor {}", self.descriptor.display()))?;
+        descriptor_coverage(&filter, &descriptor)
+    }
+}
+
+/// Check a list of public keys or edges against a filter
+///
+/// The keys file lists one public key per line, or "key,target" for an edge.
+/// The filter is loaded once for all entries.
+///
+/// Large files can be checked in windows of input lines with --offset and
+/// --limit, for example to resume an interrupted --ndjson run.
+#[derive(clap::Args, Debug)]
+pub struct ContainsBatch {
+    /// The filter to check for membership
+    #[arg(long, short, default_value = "filter.bin")]
+    input: PathBuf,
+    /// The file with the public keys or edges to check, one per line, or "-"
+    /// to read them from stdin
+    #[arg(long)]
+    keys: PathBuf,
+    /// Print one json result per line as each entry is checked. With yaml
+    /// output every result is a separate yaml document
+    #[arg(long, conflicts_with = "pretty")]
+    ndjson: bool,
+    /// Print all results as a single json array (the default)
+    #[arg(long)]
+    pretty: bool,
+    /// Print only the number of checked entries that are and are not in the
+    /// filter, instead of a result per entry
+    #[arg(long, conflicts_with_all = ["ndjson", "pretty"])]
+    count_only: bool,
+    /// The number of input lines to skip before checking entries
+    #[arg(long, default_value_t = 0)]
+    offset: usize,
+    /// The maximum number of input lines to check after the offset
+    #[arg(long)]
+    limit: Option<usize>,
+    #[command(flatten)]
+    errors: ErrorMode,
+}
+
+/// The counters of a contains-batch run
+#[derive(Debug, Default)]
+struct BatchCounts {
+    checked: usize,
+    in_filter: usize,
+    failed: usize,
+}
+
+impl ContainsBatch {
+    pub fn run(&self) -> Result<()> {
+        let filter = Filter::from_path(&self.input)
+            .context(format!("reading filter {}", self.input.display()))?;
+        let mut results = vec![];
+        let counts = self.check(&filter, |json| {
+            if self.ndjson {
+                print_result_line(&json)
+            } else {
+                results.push(json);
+                Ok(())
+            }
+        })?;
+        if self.count_only {
+            print_result(&json!({
+                "total": counts.checked,
+                "in_filter": counts.in_filter,
+                "not_in_filter": counts.checked - counts.in_filter,
+            }))?;
+        } else if !self.ndjson {
+            print_result(&results)?;
+        }
+        if counts.failed > 0 {
+            anyhow::bail!(
+                "{} entries in {} could not be checked",
+                counts.failed,
+                self.keys.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// Checks the entries in the window of input lines, passing the result
+    /// for each entry to `emit` unless only counting
+    fn check(
+        &self,
+        filter: &Filter,
+        mut emit: impl FnMut(serde_json::Value) -> Result<()>,
+    ) -> Result<BatchCounts> {
+        let reader = BufReader::new(
+            open_input(&self.keys).context(format!("reading keys {}", self.keys.display()))?,
+        );
+        let mut counts = BatchCounts::default();
+        let lines = reader
+            .lines()
+            .enumerate()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX));
+        for (index, line) in lines {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let json = match parse_entry(line) {
+                Ok((key, target)) if self.count_only => {
+                    counts.checked += 1;
+                    if membership(filter, &key, target.as_ref(), false).0 {
+                        counts.in_filter += 1;
+                    }
+                    continue;
+                }
+                Ok((key, target)) => contains_json(filter, &key, target.as_ref(), false),
+                Err(err) if self.errors.collect() => {
+                    counts.failed += 1;
+                    json!({
+                        "line": index + 1,
+                        "error": err.to_string(),
+                    })
+                }
+                Err(err) => {
+                    return Err(err.context(format!("{} line {}", self.keys.display(), index + 1)))
+                }
+            };
+            emit(json)?;
+        }
+        Ok(counts)
+    }
+}
+
+/// Serve membership checks for a filter over a socket
+///
+/// The filter is loaded once. Each connection sends one public key, or
+/// "key,target" for an edge, per line and gets one json result per line back.
+/// Lines that can not be parsed get a json error result and the connection
+/// stays open. Listen on a tcp address like "127.0.0.1:4000", or on a unix
+/// socket with "unix:/path/to/socket". A socket file left behind by a server
+/// that did not shut down cleanly is replaced, and the socket file is removed
+/// when the server stops.
+///
+/// With --rate-limit each connection is slowed down to that many queries per
+/// second. With --metrics a "metrics" line is answered with the number of
+/// queries and positive results so far, and with --metrics-interval those are
+/// also printed to stderr periodically.
+#[derive(clap::Args, Debug)]
+pub struct Serve {
+    /// The filter to check for membership
+    #[arg(long, short, default_value = "filter.bin")]
+    input: PathBuf,
+    /// The address to listen on
+    #[arg(long)]
+    listen: String,
+    /// The number of connections to serve concurrently, each on its own
+    /// thread. Further connections wait until one of them closes.
+    #[arg(long, default_value_t = 4)]
+    threads: usize,
+    /// The number of queries per second to answer on each connection
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    rate_limit: Option<u32>,
+    /// Answer a "metrics" line with the query counters
+    #[arg(long)]
+    metrics: bool,
+    /// Print the query counters to stderr every this many seconds
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    metrics_interval: Option<u64>,
+}
+
+/// The filter and counters shared by all connections of a server
+struct ServeState {
+    filter: Filter,
+    rate_limit: Option<u32>,
+    metrics: bool,
+    queries: AtomicU64,
+    positives: AtomicU64,
+}
+
+impl ServeState {
+    fn metrics_json(&self) -> serde_json::Value {
+        let queries = self.queries.load(Ordering::Relaxed);
+        let positives = self.positives.load(Ordering::Relaxed);
+        json!({
+            "queries": queries,
+            "positives": positives,
+            "positive_ratio": positives as f64 / queries.max(1) as f64,
+        })
+    }
+}
+
+impl Serve {
+    pub fn run(&self) -> Result<()> {
+        let state = Arc::new(self.state()?);
+        if let Some(interval) = self.metrics_interval {
+            let state = state.clone();
+            std::thread::spawn(move || loop {
+                std::thread::sleep(Duration::from_secs(interval));
+                eprintln!("{}", state.metrics_json());
+            });
+        }
+
+        if let Some(path) = self.listen.strip_prefix("unix:") {
+            #[cfg(unix)]
+            {
+                let socket = UnixSocket::bind(Path::new(path))
+                    .context(format!("listening on {}", self.listen))?;
+                return serve_connections(socket.listener.incoming(), state, self.threads);
+            }
+            #[cfg(not(unix))]
+            anyhow::bail!("unix socket {path} is not supported on this platform");
+        }
+
+        let listener = std::net::TcpListener::bind(&self.listen)
+            .context(format!("listening on {}", self.listen))?;
+        serve_connections(listener.incoming(), state, self.threads)
+    }
+
+    /// Loads the filter and sets up the counters to serve it with
+    fn state(&self) -> Result<ServeState> {
+        let filter = Filter::from_path(&self.input)
+            .context(format!("reading filter {}", self.input.display()))?;
+        Ok(ServeState {
+            filter,
+            rate_limit: self.rate_limit,
+            metrics: self.metrics,
+            queries: AtomicU64::new(0),
+            positives: AtomicU64::new(0),
+        })
+    }
+}
+
+/// A listening unix socket, whose file is removed when the server stops
+#[cfg(unix)]
+struct UnixSocket {
+    path: PathBuf,
+    listener: std::os::unix::net::UnixListener,
+}
+
+#[cfg(unix)]
+impl UnixSocket {
+    /// Binds the socket, replacing the socket file of a server that did not
+    /// shut down cleanly. A socket that still accepts connections is in use
+    /// and left alone.
+    fn bind(path: &Path) -> Result<Self> {
+        use std::os::unix::{fs::FileTypeExt, net::UnixStream};
+        let is_socket =
+            fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket());
+        if is_socket {
+            if UnixStream::connect(path).is_ok() {
+                anyhow::bail!("socket {} is in use by another server", path.display());
+            }
+            fs::remove_file(path).context(format!("removing stale socket {}", path.display()))?;
+        }
+        let listener = std::os::unix::net::UnixListener::bind(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            listener,
+        })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Serves every accepted connection on its own thread, with at most the given
+/// number of connections served at once. A failed connection is reported on
+/// stderr and does not stop the server.
+fn serve_connections<S>(
+    mut incoming: impl Iterator<Item = io::Result<S>>,
+    state: Arc<ServeState>,
+    threads: usize,
+) -> Result<()>
+where
+    S: Send + 'static,
+    for<'a> &'a S: io::Read + Write,
+{
+    // a token for each connection that can be served at once, taken before a
+    // connection is accepted and handed back when it closes
+    let threads = threads.max(1);
+    let (release, tokens) = std::sync::mpsc::sync_channel(threads);
+    for _ in 0..threads {
+        release.send(())?;
+    }
+    loop {
+        tokens.recv()?;
+        let Some(stream) = incoming.next() else {
+            return Ok(());
+        };
+        let stream = stream?;
+        let state = state.clone();
+        let release = release.clone();
+        std::thread::spawn(move || {
+            if let Err(err) = serve_lines(&state, &stream, &stream) {
+                eprintln!("error serving connection: {err:#}");
+            }
+            let _ = release.send(());
+        });
+    }
+}
+
+fn serve_lines<R: io::Read, W: Write>(state: &ServeState, reader: R, mut writer: W) -> Result<()> {
+    // the start of the current rate limit window and the queries in it
+    let mut window = (Instant::now(), 0);
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(limit) = state.rate_limit {
+            if window.0.elapsed() >= Duration::from_secs(1) {
+                window = (Instant::now(), 0);
+            } else if window.1 >= limit {
+                std::thread::sleep(Duration::from_secs(1).saturating_sub(window.0.elapsed()));
+                window = (Instant::now(), 0);
+            }
+            window.1 += 1;
+        }
+        let json = if state.metrics && line == "metrics" {
+            state.metrics_json()
         } else {
-            filter.contains(&source)
+            let json = match parse_entry(line) {
+                Ok((key, target)) => contains_json(&state.filter, &key, target.as_ref(), false),
+                Err(err) => json!({ "entry": line, "error": err.to_string() }),
+            };
+            state.queries.fetch_add(1, Ordering::Relaxed);
+            if json["in_filter"].as_bool().unwrap_or_default() {
+                state.positives.fetch_add(1, Ordering::Relaxed);
+            }
+            json
         };
+        serde_json::to_writer(&mut writer, &json)?;
+        writeln!(writer)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Check whether a public key is blocked as a key or by any of its edges
+///
+/// The targets file lists the public keys of the edge targets to check, one
+/// per line.
+#[derive(clap::Args, Debug)]
+pub struct Blocked {
+    /// The filter to check for membership
+    #[arg(long, short, default_value = "filter.bin")]
+    input: PathBuf,
+    /// The public key to check
+    #[arg(long)]
+    key: PublicKey,
+    /// The file with edge target public keys to check
+    #[arg(long)]
+    targets_file: Option<PathBuf>,
+}
+
+impl Blocked {
+    pub fn run(&self) -> Result<()> {
+        let filter = Filter::from_path(&self.input)
+            .context(format!("reading filter {}", self.input.display()))?;
+        let mut targets: Vec<PublicKeyBinary> = vec![];
+        if let Some(path) = &self.targets_file {
+            let file =
+                fs::File::open(path).context(format!("reading targets {}", path.display()))?;
+            for (index, line) in BufReader::new(file).lines().enumerate() {
+                let line = line?;
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let target = line
+                    .parse::<PublicKey>()
+                    .context(format!("invalid public key \"{line}\""))
+                    .context(format!("{} line {}", path.display(), index + 1))?;
+                targets.push(target.into());
+            }
+        }
+        let key = self.key.clone().into();
         let json = json!({
-            "address":  self.key.to_string(),
-            "in_filter": in_filter,
+            "address": self.key.to_string(),
+            "targets": targets.len(),
+            "blocked": filter.is_blocked(&key, &targets),
         });
-        print_json(&json)
+        print_result(&json)
+    }
+}
+
+/// Parses a "key" or "key,target" entry
+fn parse_entry(line: &str) -> Result<(PublicKey, Option<PublicKey>)> {
+    let (key, target) = match line.split_once(',') {
+        Some((key, target)) => (key, Some(target)),
+        None => (line, None),
+    };
+    let key = key
+        .trim()
+        .parse::<PublicKey>()
+        .context(format!("invalid public key \"{key}\""))?;
+    let target = target
+        .map(|target| {
+            target
+                .trim()
+                .parse::<PublicKey>()
+                .context(format!("invalid public key \"{target}\""))
+        })
+        .transpose()?;
+    Ok((key, target))
+}
+
+/// The membership result for a key or edge. The `match_type` is `key` or
+/// `edge` for the kind of entry that tested positive. For an edge that is not
+/// in the filter itself it is `key` when either endpoint is in the filter as a
+/// key, while `in_filter` stays false.
+fn contains_json(
+    filter: &Filter,
+    key: &PublicKey,
+    target: Option<&PublicKey>,
+    either_direction: bool,
+) -> serde_json::Value {
+    let mut json = json!({ "address": key.to_string() });
+    if let Some(target) = target {
+        json["target"] = target.to_string().into();
+    }
+    let (in_filter, match_type) = membership(filter, key, target, either_direction);
+    json["in_filter"] = in_filter.into();
+    json["match_type"] = match_type.into();
+    json
+}
+
+/// Whether a key, or the edge to the target when given, is in the filter,
+/// along with the match type reported by [`contains_json`]. Edges are checked
+/// as undirected, and as directed in either direction if asked to.
+fn membership(
+    filter: &Filter,
+    key: &PublicKey,
+    target: Option<&PublicKey>,
+    either_direction: bool,
+) -> (bool, &'static str) {
+    let source = key.clone().into();
+    if let Some(target) = target {
+        let target = target.clone().into();
+        let contains_edge = if either_direction {
+            filter.contains_edge_either(&source, &target)
+        } else {
+            filter.contains_edge(&source, &target)
+        };
+        if contains_edge {
+            (true, "edge")
+        } else if filter.contains(&source) || filter.contains(&target) {
+            (false, "key")
+        } else {
+            (false, "none")
+        }
+    } else if filter.contains(&source) {
+        (true, "key")
+    } else {
+        (false, "none")
     }
 }
 
 /// Verifies a given filter against the given multisig public key
+///
+/// When multiple keys are given, for example during a key rotation, the filter
+/// verifies if it matches any of them and the matching key is reported.
+///
+/// With --self-contained the filter is verified against its embedded manifest.
+/// The reported address must still be compared to the expected multisig key.
+///
+/// The filter format has no CRC, so there is no separate checksum to check
+/// and `crc_ok` is always null: the signature, and --expect-hash when given,
+/// cover the filter payload.
 #[derive(clap::Args, Debug)]
 pub struct Verify {
     /// The input file to verify the signature for
     #[arg(long, short, default_value = "filter.bin")]
     input: PathBuf,
+    /// The public key(s) to use for verification [default: public_key.json]
+    #[arg(long, short)]
+    key: Vec<PathBuf>,
+    #[command(flatten)]
+    inline_key: InlineKey,
+    /// Fail if the filter is not for this serial
+    #[arg(long)]
+    require_serial: Option<u32>,
+    /// Fail if the filter hash differs from this base64 hash, obtained out of
+    /// band, regardless of the signature
+    #[arg(long)]
+    expect_hash: Option<String>,
+    /// Reject filters with trailing bytes after the filter payload. Trailing
+    /// zero padding is accepted by default
+    #[arg(long)]
+    strict: bool,
+    /// Verify against the manifest embedded in the filter. The embedded key
+    /// must still be one of the given public keys, or public_key.json when it
+    /// exists, to be trusted
+    #[arg(long)]
+    self_contained: bool,
+    /// List the member keys whose manifest signatures verify for the filter
+    #[arg(long)]
+    print_signers: bool,
+    /// The signature manifest to report signature counts and signers from
+    /// [default: manifest.json with --print-signers]. When given, the filter
+    /// hash must also match the manifest hash, so a different filter with a
+    /// valid signature is rejected
+    #[arg(long, short)]
+    manifest: Option<PathBuf>,
+    /// Passphrase to decrypt the manifest file with
+    #[arg(long, env = "XORF_MANIFEST_PASSPHRASE", hide_env_values = true)]
+    passphrase: Option<String>,
+    /// Print nothing when the filter verifies. Failures are still reported
+    /// in full
+    #[arg(long)]
+    quiet_ok: bool,
+    /// Only check that the filter parses and, with --expect-hash, that its
+    /// hash matches, without verifying the signature. The output reports that
+    /// authenticity was not checked
+    #[arg(long, conflicts_with_all = ["self_contained", "print_signers", "manifest"])]
+    checksum_only: bool,
+    /// A json array of public keys that should not be in the filter. The
+    /// candidates that test positive anyway are reported as false positives
+    #[arg(long, requires = "exclude", conflicts_with = "quiet_ok")]
+    candidates: Option<PathBuf>,
+    /// The descriptor the filter was built from. Candidates in it are true
+    /// positives and are not reported
+    #[arg(long, requires = "candidates")]
+    exclude: Option<PathBuf>,
+    /// Write a csv of `serial,signer_address,participated,valid` for every
+    /// member of the key to this file, from the manifest signatures
+    /// [default manifest: manifest.json]
+    #[arg(long, conflicts_with = "checksum_only")]
+    output_signers_csv: Option<PathBuf>,
+}
+
+impl Verify {
+    pub fn run(&self) -> Result<()> {
+        let data =
+            read_input(&self.input).context(format!("reading filter {}", self.input.display()))?;
+        let parsed = if self.strict {
+            Filter::from_bytes_strict(&data)
+        } else {
+            Filter::from_bytes(&data)
+        };
+        let filter = match parsed {
+            Err(Error::UnsupportedVersion(version)) => {
+                print_result(&json!({
+                    "version": version,
+                    "version_supported": false,
+                    "crc_ok": null,
+                    "signature_verified": false,
+                    "verified": false,
+                }))?;
+                return Err(Error::UnsupportedVersion(version))
+                    .context(format!("reading filter {}", self.input.display()));
+            }
+            Err(Error::UnsupportedFormat(format_version)) => {
+                print_result(&json!({
+                    "format_version": format_version,
+                    "version_supported": false,
+                    "crc_ok": null,
+                    "signature_verified": false,
+                    "verified": false,
+                }))?;
+                return Err(Error::UnsupportedFormat(format_version))
+                    .context(format!("reading filter {}", self.input.display()));
+            }
+            result => result.context(format!("reading filter {}", self.input.display()))?,
+        };
+        let false_positives = self.false_positives(&filter)?;
+        let checks = self.filter_checks(&filter)?;
+        if self.checksum_only {
+            let mut json = json!({
+                "serial": filter.serial,
+                "hash": base64_serde::encode(&filter.hash()?),
+                "hash_checked": self.expect_hash.is_some(),
+                "version_supported": true,
+                "crc_ok": null,
+                "signature_verified": false,
+                "authenticity_checked": false,
+            });
+            checks.add_to(&mut json);
+            add_false_positives(&mut json, &false_positives);
+            return self.finish(&mut json, &checks);
+        }
+        if self.self_contained {
+            let key = match filter.verify_embedded() {
+                Ok(key) => key,
+                Err(err) => {
+                    let mut json = json!({
+                        "embedded": true,
+                        "version_supported": true,
+                        "crc_ok": null,
+                        "signature_verified": false,
+                        "verified": false,
+                        "error": err.to_string(),
+                    });
+                    checks.add_to(&mut json);
+                    print_result(&json)?;
+                    return Err(err).context("verifying filter against its embedded manifest");
+                }
+            };
+            let trusted = self.key_manifests(true)?;
+            let mut trusted_by = None;
+            for (key_path, key_manifest) in &trusted {
+                if key_manifest.public_key()? == key {
+                    trusted_by = Some(key_path.clone());
+                    break;
+                }
+            }
+            let mut json = json!({
+                "address": key.to_string(),
+                "embedded": true,
+                "embedded_key_trusted": trusted_by.is_some(),
+                "version_supported": true,
+                "crc_ok": null,
+                "signature_verified": true,
+                "verified": trusted_by.is_some(),
+            });
+            checks.add_to(&mut json);
+            if let Some(embedded) = &filter.embedded {
+                self.report_signatures(
+                    &mut json,
+                    &filter,
+                    &embedded.manifest,
+                    &embedded.public_key,
+                )?;
+            }
+            let Some(key_path) = trusted_by else {
+                print_result(&json)?;
+                if trusted.is_empty() {
+                    anyhow::bail!(
+                        "Filter verifies against its embedded key {key}, but no trusted public key \
+                         was given to check that key against"
+                    );
+                }
+                anyhow::bail!(
+                    "Embedded key {key} of the filter is not one of the given public keys"
+                );
+            };
+            json["key"] = key_path;
+            if let Some(manifest_path) = &self.manifest {
+                let manifest = read_manifest(manifest_path, self.passphrase.as_deref())?;
+                check_manifest_hash(&mut json, &filter, &manifest, manifest_path)?;
+            }
+            add_false_positives(&mut json, &false_positives);
+            return self.finish(&mut json, &checks);
+        }
+        let key_manifests = self.key_manifests(false)?;
+        let mut failures = vec![];
+        for (key_path, key_manifest) in &key_manifests {
+            let key = key_manifest.public_key()?;
+            if let Err(err) = filter.verify(&key) {
+                failures.push(json!({
+                    "address": key.to_string(),
+                    "key": key_path,
+                    "reason": verify_failure(&err),
+                    "error": err.to_string(),
+                }));
+            } else {
+                let mut json = json!({
+                    "address": key.to_string(),
+                    "key": key_path,
+                    "version_supported": true,
+                    "crc_ok": null,
+                    "signature_verified": true,
+                    "verified": true,
+                });
+                checks.add_to(&mut json);
+                let manifest_path = self.manifest.clone().or_else(|| {
+                    (self.print_signers || self.output_signers_csv.is_some())
+                        .then(|| PathBuf::from("manifest.json"))
+                });
+                if let Some(manifest_path) = manifest_path {
+                    let manifest = read_manifest(&manifest_path, self.passphrase.as_deref())?;
+                    self.report_signatures(&mut json, &filter, &manifest, key_manifest)?;
+                    if self.manifest.is_some() {
+                        check_manifest_hash(&mut json, &filter, &manifest, &manifest_path)?;
+                    }
+                }
+                add_false_positives(&mut json, &false_positives);
+                return self.finish(&mut json, &checks);
+            }
+        }
+        let mut json = json!({
+            "version_supported": true,
+            "crc_ok": null,
+            "signature_verified": false,
+            "verified": false,
+            "failures": failures,
+        });
+        checks.add_to(&mut json);
+        add_false_positives(&mut json, &false_positives);
+        print_result(&json)?;
+        match failures.as_slice() {
+            [failure] => anyhow::bail!(
+                "Filter does not verify against {}: {}",
+                failure["address"].as_str().unwrap_or_default(),
+                failure["error"].as_str().unwrap_or_default()
+            ),
+            _ => anyhow::bail!("Filter does not verify against any of the given keys"),
+        }
+    }
+
+    /// The public keys to verify with, labelled by where they came from: the
+    /// inline key, the given key files or public_key.json. When `optional`,
+    /// a missing public_key.json that was not asked for yields no keys.
+    fn key_manifests(&self, optional: bool) -> Result<Vec<(serde_json::Value, PublicKeyManifest)>> {
+        if let Some(key_manifest) = self.inline_key.inline()? {
+            return Ok(vec![(json!("inline"), key_manifest)]);
+        }
+        let default = [PathBuf::from("public_key.json")];
+        let paths = match self.key.as_slice() {
+            [] if optional && !default[0].exists() => return Ok(vec![]),
+            [] => default.as_slice(),
+            paths => paths,
+        };
+        paths
+            .iter()
+            .map(|key_path| {
+                let key_manifest = PublicKeyManifest::from_path(key_path)
+                    .context(format!("reading public key {}", key_path.display()))?;
+                Ok((json!(key_path), key_manifest))
+            })
+            .collect()
+    }
+
+    /// The candidates that test positive in the filter without being in the
+    /// excluded descriptor, when candidates are given
+    fn false_positives(&self, filter: &Filter) -> Result<Option<Vec<String>>> {
+        let (Some(candidates), Some(exclude)) = (&self.candidates, &self.exclude) else {
+            return Ok(None);
+        };
+        let descriptor = Descriptor::from_path(exclude)
+            .context(format!("reading descriptor {}", exclude.display()))?;
+        let members: HashSet<PublicKeyBinary> = descriptor.iter_keys().collect();
+        let false_positives = read_key_list(candidates, "candidates")?
+            .into_iter()
+            .filter(|key| !members.contains(key) && filter.contains(key))
+            .map(|key| key.to_string())
+            .collect();
+        Ok(Some(false_positives))
+    }
+
+    /// Checks the filter against --require-serial and --expect-hash. A
+    /// failed check does not stop the signature checks, so the output still
+    /// reports all of them.
+    fn filter_checks(&self, filter: &Filter) -> Result<FilterChecks> {
+        let mut checks = FilterChecks::default();
+        if let Some(serial) = self.require_serial {
+            let serial_matches = filter.serial == serial;
+            checks.json.insert("serial".into(), filter.serial.into());
+            checks.json.insert("required_serial".into(), serial.into());
+            checks
+                .json
+                .insert("serial_matches".into(), serial_matches.into());
+            if !serial_matches {
+                checks.failures.push(format!(
+                    "Filter serial {} does not match required serial {serial}",
+                    filter.serial
+                ));
+            }
+        }
+        if let Some(expect_hash) = &self.expect_hash {
+            let hash = base64_serde::encode(&filter.hash()?);
+            let hash_matches = &hash == expect_hash;
+            checks
+                .json
+                .insert("expected_hash".into(), expect_hash.as_str().into());
+            checks
+                .json
+                .insert("expected_hash_matches".into(), hash_matches.into());
+            if !hash_matches {
+                checks.failures.push(format!(
+                    "Filter hash {hash} does not match expected hash {expect_hash}"
+                ));
+            }
+            checks.json.insert("hash".into(), hash.into());
+        }
+        Ok(checks)
+    }
+
+    /// Prints the output of a filter whose signature checks passed, failing
+    /// with the output printed when a serial or hash check did not
+    fn finish(&self, json: &mut serde_json::Value, checks: &FilterChecks) -> Result<()> {
+        if let Some(failure) = checks.failures.first() {
+            json["verified"] = false.into();
+            print_result(&*json)?;
+            anyhow::bail!("{failure}");
+        }
+        self.print_ok(json)
+    }
+
+    fn print_ok(&self, json: &serde_json::Value) -> Result<()> {
+        if self.quiet_ok {
+            return Ok(());
+        }
+        print_result(json)
+    }
+
+    /// Adds the number of signatures present in and valid for the manifest,
+    /// and the required threshold, to the verify output. The valid signers
+    /// are listed with --print-signers.
+    fn report_signatures(
+        &self,
+        json: &mut serde_json::Value,
+        filter: &Filter,
+        manifest: &Manifest,
+        key_manifest: &PublicKeyManifest,
+    ) -> Result<()> {
+        let signers = manifest.signers(filter, key_manifest)?;
+        json["signatures_present"] = manifest
+            .signatures
+            .iter()
+            .filter(|signature| signature.is_signed())
+            .count()
+            .into();
+        json["signatures_valid"] = signers.len().into();
+        json["threshold"] = key_manifest.required.into();
+        if self.print_signers {
+            json["signers"] = signers.iter().map(|key| key.to_string()).collect();
+        }
+        if let Some(path) = &self.output_signers_csv {
+            let mut writer = csv::Writer::from_writer(open_output(path, false)?);
+            writer.write_record(["serial", "signer_address", "participated", "valid"])?;
+            for member in key_manifest.public_keys() {
+                let participated = manifest
+                    .signatures
+                    .iter()
+                    .any(|signature| signature.address() == &member && signature.is_signed());
+                writer.write_record([
+                    filter.serial.to_string(),
+                    member.to_string(),
+                    participated.to_string(),
+                    signers.contains(&member).to_string(),
+                ])?;
+            }
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of the serial and hash checks of `filter verify`
+#[derive(Default)]
+struct FilterChecks {
+    json: serde_json::Map<String, serde_json::Value>,
+    failures: Vec<String>,
+}
+
+impl FilterChecks {
+    fn add_to(&self, json: &mut serde_json::Value) {
+        for (key, value) in &self.json {
+            json[key] = value.clone();
+        }
+    }
+}
+
+/// Adds whether the filter hash matches the manifest hash to the verify
+/// output, and fails with the output printed if it does not
+fn check_manifest_hash(
+    json: &mut serde_json::Value,
+    filter: &Filter,
+    manifest: &Manifest,
+    manifest_path: &Path,
+) -> Result<()> {
+    let filter_hash = base64_serde::encode(&filter.hash()?);
+    let hash_matches = filter_hash == manifest.hash;
+    json["hash_matches"] = hash_matches.into();
+    if !hash_matches {
+        json["verified"] = false.into();
+        print_result(json)?;
+        anyhow::bail!(
+            "Filter hash {filter_hash} does not match hash {} of manifest {}",
+            manifest.hash,
+            manifest_path.display()
+        );
+    }
+    Ok(())
+}
+
+fn add_false_positives(json: &mut serde_json::Value, false_positives: &Option<Vec<String>>) {
+    if let Some(false_positives) = false_positives {
+        json["false_positives"] = false_positives.clone().into();
+    }
+}
+
+/// Verify a number of filters against a multisig public key
+///
+/// Directories are searched for `.bin` filter files. Every filter is checked
+/// for a valid signature and, when given, for a serial within the expected
+/// range, since stale or future serials point at a distribution problem. The
+/// command fails if any filter does not pass.
+#[derive(clap::Args, Debug)]
+pub struct VerifyBatch {
+    /// The filter files or directories of filter files to verify
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
     /// The public key to use for verification
     #[arg(long, short, default_value = "public_key.json")]
     key: PathBuf,
+    #[command(flatten)]
+    inline_key: InlineKey,
+    /// The lowest serial a filter may have
+    #[arg(long)]
+    min_serial: Option<u32>,
+    /// The highest serial a filter may have
+    #[arg(long)]
+    max_serial: Option<u32>,
+    /// Also search the subdirectories of directories, reporting filters by
+    /// their path relative to the given directory
+    #[arg(long)]
+    recursive: bool,
+    #[command(flatten)]
+    errors: ErrorMode,
 }
 
-impl Verify {
+impl VerifyBatch {
     pub fn run(&self) -> Result<()> {
-        let filter = Filter::from_path(&self.input)
-            .context(format!("reading filter {}", self.input.display()))?;
-        let key_manifest = PublicKeyManifest::from_path(&self.key)
-            .context(format!("reading public key {}", self.key.display()))?;
-        let key = key_manifest.public_key()?;
-        let verified = filter.verify(&key).is_ok();
-        if !verified {
-            anyhow::bail!("Filter does not verify");
+        let key_manifest = self.inline_key.key_manifest(&self.key)?;
+        let verifier = FilterVerifier::from_key_manifest(&key_manifest)?;
+
+        // the path to read each filter from and the path to report it as
+        let mut paths: Vec<(PathBuf, PathBuf)> = vec![];
+        for input in &self.inputs {
+            if input.is_dir() {
+                let mut found = vec![];
+                find_filters(input, self.recursive, &mut found)?;
+                found.sort();
+                paths.extend(found.into_iter().map(|path| {
+                    let reported = match self.recursive {
+                        true => path.strip_prefix(input).unwrap_or(&path).to_path_buf(),
+                        false => path.clone(),
+                    };
+                    (path, reported)
+                }));
+            } else {
+                paths.push((input.clone(), input.clone()));
+            }
+        }
+
+        let mut failed = 0;
+        let mut results = vec![];
+        for (path, reported) in &paths {
+            let json = match Filter::from_path(path) {
+                Ok(filter) => {
+                    let verified = verifier.verify(&filter).is_ok();
+                    let in_range = self.min_serial.map_or(true, |min| filter.serial >= min)
+                        && self.max_serial.map_or(true, |max| filter.serial <= max);
+                    if !verified || !in_range {
+                        failed += 1;
+                    }
+                    json!({
+                        "input": reported,
+                        "serial": filter.serial,
+                        "verified": verified,
+                        "serial_in_range": in_range,
+                    })
+                }
+                Err(err) if self.errors.collect() => {
+                    failed += 1;
+                    json!({
+                        "input": reported,
+                        "error": err.to_string(),
+                    })
+                }
+                Err(err) => return Err(err).context(format!("reading filter {}", path.display())),
+            };
+            results.push(json);
+        }
+        let json = json!({
+            "filters": results,
+            "passed": paths.len() - failed,
+            "failed": failed,
+        });
+        print_result(&json)?;
+        if failed > 0 {
+            anyhow::bail!("{failed} of {} filters failed verification", paths.len());
+        }
+        Ok(())
+    }
+}
+
+/// Adds the `.bin` files in a directory, and with `recursive` in all of its
+/// subdirectories, to the given list
+fn find_filters(dir: &Path, recursive: bool, found: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = fs::read_dir(dir)
+        .context(format!("reading directory {}", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    for path in entries {
+        if path.is_dir() {
+            if recursive {
+                find_filters(&path, recursive, found)?;
+            }
+        } else if path.extension().is_some_and(|ext| ext == "bin") {
+            found.push(path);
         }
-        print_verified(&key, verified)
     }
+    Ok(())
 }
 
 /// Generate a binary filter for the hotspots listed in the given file.
 ///
 /// This converts a generated data binary, with a given multisig public key and
-/// manifest and generates a signed binary xor filter (a binary fuse with 32 bit
-/// fingerprints to be precise).
+/// manifest and generates a signed binary xor filter (an xor or binary fuse
+/// filter, with 32 bit fingerprints unless built narrower).
 #[derive(Debug, clap::Args)]
 pub struct Generate {
     /// The data file with signing data, generated by the manifest command, to
-    /// generate a filter for
-    #[arg(long, short, default_value = "data.bin")]
-    data: PathBuf,
+    /// generate a filter for. Given more than once, the parts are joined in
+    /// order [default: data.bin]
+    #[arg(long, short)]
+    data: Vec<PathBuf>,
     /// The public key file to use
     #[arg(long, short, default_value = "public_key.json")]
     key: PathBuf,
 
-    /// The file to write the resulting binary filter to
-    #[arg(long, short, default_value = "filter.bin")]
-    output: PathBuf,
+    /// The file to write the resulting binary filter to [default: filter.bin]
+    #[arg(long, short)]
+    output: Option<PathBuf>,
 
-    /// The path for the signature manifet to use
-    #[arg(long, short, default_value = "manifest.json")]
-    manifest: PathBuf,
+    /// The path for the signature manifet to use [default: manifest.json]
+    #[arg(long, short)]
+    manifest: Option<PathBuf>,
+
+    #[command(flatten)]
+    naming: OutputName,
+
+    /// Passphrase to encrypt or decrypt the manifest file with
+    #[arg(long, env = "XORF_MANIFEST_PASSPHRASE", hide_env_values = true)]
+    passphrase: Option<String>,
+
+    /// Skip verifying the written filter against the public key
+    #[arg(long)]
+    no_verify: bool,
+
+    /// Embed the manifest and signer keys in the filter file, so it can be
+    /// checked with `filter verify --self-contained`
+    #[arg(long)]
+    embed_manifest: bool,
+
+    /// Record the version of this tool in the filter header, reported as
+    /// `generated_by` by `filter info`. Like an embedded manifest it is not
+    /// signed, and older versions of this tool can not read such filters
+    #[arg(long)]
+    record_generator: bool,
+
+    /// Compress the written filter. Readers detect compressed filters by
+    /// their magic bytes, and the signature covers the same signing bytes
+    /// either way. Zstd requires the zstd feature
+    #[arg(long, value_enum, default_value_t = CompressArg::None)]
+    compress: CompressArg,
+
+    /// Write the filter without the magic and format version header, for
+    /// consumers that predate it
+    #[arg(long)]
+    headerless: bool,
+
+    /// Build the filter directly from a descriptor instead of a data file.
+    /// The manifest must already carry the required signatures for it
+    #[arg(long, conflicts_with = "data")]
+    descriptor: Option<PathBuf>,
+
+    /// The serial to build the descriptor filter with, defaults to the
+    /// manifest serial
+    #[arg(long, requires = "descriptor")]
+    serial: Option<u32>,
+
+    /// The filter implementation to build the descriptor filter with
+    #[arg(long, value_enum, default_value_t = BackendArg::Xor, requires = "descriptor")]
+    backend: BackendArg,
+    /// The width of the descriptor filter fingerprints in bits, 8, 16 or 32. Widths below
+    /// 32 require the binary fuse backend
+    #[arg(long, default_value_t = DEFAULT_FINGERPRINT_BITS, value_parser = parse_fingerprint_bits, requires = "descriptor")]
+    fingerprint_bits: u8,
+    /// The salt to build the descriptor filter with, instead of retrying a
+    /// failed binary fuse construction with a few fresh salts
+    #[arg(long, hide = true, requires = "descriptor")]
+    seed: Option<u64>,
+
+    /// Keep the written filter even when it fails verification
+    #[arg(long)]
+    keep_partial: bool,
+
+    /// Sign with this shell command instead of the manifest signatures. The
+    /// command is given the signing bytes on stdin and must write the raw
+    /// multisig signature to stdout
+    #[arg(long, conflicts_with_all = ["manifest", "embed_manifest", "descriptor"])]
+    signer_cmd: Option<String>,
+
+    /// Also verify the written filter against this independent copy of the
+    /// public key, for example one from a trusted source, to catch signing
+    /// with the wrong key file
+    #[arg(long, conflicts_with = "no_verify")]
+    verify_against: Option<PathBuf>,
+
+    /// Whether to force overwrite an existing output file
+    #[arg(long, short)]
+    force: bool,
 }
 
 impl Generate {
     pub fn run(&self) -> Result<()> {
-        let manifest = Manifest::from_path(&self.manifest)
-            .context(format!("reading manifest {}", self.manifest.display()))?;
+        let data = match self.data.as_slice() {
+            [] => vec![self.naming.path(&None, ".data.bin", "data.bin")],
+            parts => parts.to_vec(),
+        };
+        let output = self.naming.path(&self.output, ".bin", "filter.bin");
+        let manifest_path = self
+            .naming
+            .path(&self.manifest, ".manifest.json", "manifest.json");
         let key_manifest = PublicKeyManifest::from_path(&self.key)
             .context(format!("reading public key {}", self.key.display()))?;
         let key = key_manifest.public_key()?;
 
-        let mut filter = Filter::from_signing_path(&self.data, FILTTER_VERSION)?;
-        filter.signature = manifest.sign(&key_manifest)?;
-        filter.serial = manifest.serial;
+        if let Some(command) = &self.signer_cmd {
+            let mut filter = Filter::from_signing_parts(&data, FILTTER_VERSION)?;
+            filter
+                .sign_with(&key, |bytes| run_signer_cmd(command, bytes))
+                .context(format!("signing with {command}"))?;
+            return self.write_filter(filter, &output, &key);
+        }
+
+        let manifest = read_manifest(&manifest_path, self.passphrase.as_deref())?;
+        let mut filter = match &self.descriptor {
+            Some(path) => {
+                let descriptor = Descriptor::from_path(path)
+                    .context(format!("reading descriptor {}", path.display()))?;
+                let serial = self.serial.unwrap_or(manifest.serial);
+                let options = FilterOptions {
+                    backend: self.backend.into(),
+                    fingerprint_bits: self.fingerprint_bits,
+                    salt: self.seed,
+                    ..Default::default()
+                };
+                let (filter, stats) = build_signed_filter_with_stats(
+                    &descriptor,
+                    serial,
+                    &options,
+                    &key_manifest,
+                    &manifest,
+                )?;
+                if let Some(warning) = stats.warning() {
+                    eprintln!("warning: {warning}");
+                }
+                filter
+            }
+            None => {
+                let mut filter = Filter::from_signing_parts(&data, FILTTER_VERSION)?;
+                filter.apply_manifest(&manifest, &key_manifest)?;
+                filter
+            }
+        };
+        if self.embed_manifest {
+            filter.embedded = Some(EmbeddedManifest {
+                manifest,
+                public_key: key_manifest,
+            });
+        }
+        self.write_filter(filter, &output, &key)
+    }
+
+    /// Writes the signed filter to the output and verifies it unless told not
+    /// to
+    fn write_filter(&self, mut filter: Filter, output: &Path, key: &PublicKey) -> Result<()> {
+        if self.record_generator {
+            filter.generated_by = Some(format!(
+                "{} {}",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION")
+            ));
+        }
+        filter.compression = self.compress.into();
+        if self.headerless {
+            filter.format_version = 0;
+        }
         let filter_bytes = filter.to_bytes()?;
-        let mut file = open_output_file(&self.output, false)?;
+        let mut file = AtomicFile::create(output, !self.force)?;
         file.write_all(&filter_bytes)?;
 
-        let verified = filter.verify(&key).is_ok();
+        if self.no_verify {
+            file.commit()?;
+            let json = json!({
+                "address": key.to_string(),
+                "verification_skipped": true,
+            });
+            return print_result(&json);
+        }
+        let verified = filter.verify(key).is_ok();
         if !verified {
+            if self.keep_partial {
+                file.commit()?;
+            }
             anyhow::bail!("Filter does not verify");
         }
-        print_verified(&key, verified)
+        if let Some(path) = &self.verify_against {
+            let other = PublicKeyManifest::from_path(path)
+                .context(format!("reading public key {}", path.display()))?
+                .public_key()?;
+            if let Err(err) = filter.verify(&other) {
+                if self.keep_partial {
+                    file.commit()?;
+                }
+                return Err(err).context(format!(
+                    "Filter does not verify against {} from {}",
+                    other,
+                    path.display()
+                ));
+            }
+        }
+        file.commit()?;
+        print_verified(key, verified)
+    }
+}
+
+/// Runs a signing command through the shell, giving it the signing bytes on
+/// stdin and returning what it writes to stdout as the signature
+fn run_signer_cmd(command: &str, signing_bytes: &[u8]) -> io::Result<Vec<u8>> {
+    use std::process::{Command, Stdio};
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    // stdin is written on its own thread while stdout is read, so a command
+    // that writes output before reading all of its input can not deadlock on
+    // a full pipe. Stdin is closed once the thread is done with it.
+    let stdin = child.stdin.take();
+    let (written, output) = std::thread::scope(|scope| {
+        let writer =
+            scope.spawn(move || stdin.map_or(Ok(()), |mut stdin| stdin.write_all(signing_bytes)));
+        let output = child.wait_with_output();
+        (writer.join().expect("signing command input thread"), output)
+    });
+    let output = output?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "signing command failed with {}",
+            output.status
+        )));
     }
+    written?;
+    Ok(output.stdout)
 }
 
 /// Displays filter information for a given filter
@@ -146,24 +1553,1805 @@ pub struct Info {
     /// The input file to generate a filter for
     #[arg(long, short, default_value = "filter.bin")]
     input: PathBuf,
+    /// Check that the filter parses and is well formed, without verifying
+    /// the signature against a public key
+    #[arg(long)]
+    verify_self: bool,
+    /// Also report the filter size in KiB, MiB or GiB
+    #[arg(long)]
+    human: bool,
+    /// Print only the value of this field, without json quoting, for use in
+    /// scripts
+    #[arg(long, value_enum, conflicts_with = "verify_self")]
+    field: Option<InfoField>,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum InfoField {
+    Serial,
+    Hash,
+    Fingerprints,
+    Size,
+}
+
+impl InfoField {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Serial => "serial",
+            Self::Hash => "hash",
+            Self::Fingerprints => "fingerprints",
+            Self::Size => "size",
+        }
+    }
 }
 
 impl Info {
     pub fn run(&self) -> Result<()> {
+        if self.verify_self {
+            return self.run_verify_self();
+        }
         let filter = Filter::from_path(&self.input)
             .context(format!("reading filter {}", self.input.display()))?;
+        let json = info_json(&filter, self.human)?;
+        match self.field {
+            Some(field) => {
+                match &json[field.name()] {
+                    serde_json::Value::String(value) => println!("{value}"),
+                    value => println!("{value}"),
+                }
+                Ok(())
+            }
+            None => print_result(&json),
+        }
+    }
 
-        let mut json = serde_json::to_value(&filter)?;
-        json["fingerprints"] = filter.len().into();
-        json["hash"] = base64_serde::encode(&filter.hash()?).into();
-        print_json(&json)
+    fn run_verify_self(&self) -> Result<()> {
+        let data =
+            read_input(&self.input).context(format!("reading filter {}", self.input.display()))?;
+        let mut issues: Vec<String> = vec![];
+        let mut json = match Filter::from_bytes(&data) {
+            Ok(filter) => {
+                if filter.signature.is_empty() {
+                    issues.push("filter is not signed".to_string());
+                }
+                if filter.to_bytes()? != data {
+                    issues.push("filter does not re-encode to the same bytes".to_string());
+                }
+                info_json(&filter, self.human)?
+            }
+            Err(err) => {
+                issues.push(err.to_string());
+                json!({})
+            }
+        };
+        let structurally_valid = issues.is_empty();
+        json["structurally_valid"] = structurally_valid.into();
+        json["issues"] = issues.into();
+        print_result(&json)?;
+        if !structurally_valid {
+            anyhow::bail!("Filter is not structurally valid");
+        }
+        Ok(())
     }
 }
 
-fn print_verified(public_key: &PublicKey, verified: bool) -> Result<()> {
+/// Compare two filter files field by field
+///
+/// Reports which parts of the two filters differ (version, serial, signature,
+/// filter kind, seed or fingerprints) instead of a raw byte diff, which helps
+/// track down why two builds are not identical.
+#[derive(clap::Args, Debug)]
+pub struct DiffBits {
+    /// The first filter file
+    a: PathBuf,
+    /// The second filter file
+    b: PathBuf,
+}
+
+impl DiffBits {
+    pub fn run(&self) -> Result<()> {
+        let a =
+            Filter::from_path(&self.a).context(format!("reading filter {}", self.a.display()))?;
+        let b =
+            Filter::from_path(&self.b).context(format!("reading filter {}", self.b.display()))?;
+
+        let mut differs = vec![];
+        if a.version != b.version {
+            differs.push("version");
+        }
+        if a.serial != b.serial {
+            differs.push("serial");
+        }
+        if a.signature != b.signature {
+            differs.push("signature");
+        }
+        if a.filter.kind() != b.filter.kind() {
+            differs.push("kind");
+        }
+        if a.filter.seed()? != b.filter.seed()? {
+            differs.push("seed");
+        }
+        if a.filter.fingerprint_bits() != b.filter.fingerprint_bits() {
+            differs.push("fingerprint_bits");
+        }
+        if a.filter.fingerprints() != b.filter.fingerprints() {
+            differs.push("fingerprints");
+        }
+        let json = json!({
+            "identical": differs.is_empty(),
+            "differs": differs,
+        });
+        print_result(&json)
+    }
+}
+
+/// Verify a descriptor, manifest and filter against each other
+///
+/// Checks every link of the release chain: the descriptor rebuilds to the
+/// manifest hash, the manifest holds enough valid member signatures, and the
+/// filter carries the same serial and hash and verifies against the multisig
+/// public key. The status of each link is reported, and the command fails if
+/// any link is broken.
+#[derive(clap::Args, Debug)]
+pub struct VerifyChain {
+    /// The descriptor the filter was built from
+    #[arg(long, default_value = "descriptor.bin.gz")]
+    descriptor: PathBuf,
+    /// The descriptor entries the filter was built with
+    #[arg(long, value_enum, default_value_t = EntriesArg::Both)]
+    entries: EntriesArg,
+    /// The signature manifest for the filter
+    #[arg(long, short, default_value = "manifest.json")]
+    manifest: PathBuf,
+    /// The public key file to use
+    #[arg(long, short, default_value = "public_key.json")]
+    key: PathBuf,
+    #[command(flatten)]
+    inline_key: InlineKey,
+    /// The filter file to verify
+    #[arg(long, short, default_value = "filter.bin")]
+    input: PathBuf,
+    /// Passphrase to decrypt the manifest file with
+    #[arg(long, env = "XORF_MANIFEST_PASSPHRASE", hide_env_values = true)]
+    passphrase: Option<String>,
+    /// Check the filter signature while the descriptor is rebuilt instead of
+    /// after it. The results are the same either way
+    #[arg(long)]
+    parallel_verify: bool,
+}
+
+impl VerifyChain {
+    pub fn run(&self) -> Result<()> {
+        let manifest = read_manifest(&self.manifest, self.passphrase.as_deref())?;
+        let key_manifest = self.inline_key.key_manifest(&self.key)?;
+        let key = key_manifest.public_key()?;
+        let descriptor = Descriptor::from_path(&self.descriptor)
+            .context(format!("reading descriptor {}", self.descriptor.display()))?;
+        let filter = Filter::from_path(&self.input)
+            .context(format!("reading filter {}", self.input.display()))?;
+
+        let options = FilterOptions {
+            entries: self.entries.into(),
+            entries_hash: manifest.entries_hash.is_some(),
+            backend: filter.filter.backend().unwrap_or_default(),
+            fingerprint_bits: filter.filter.fingerprint_bits(),
+            salt: Some(filter.salt),
+            ..Default::default()
+        };
+        let rebuild = || -> Result<(String, usize)> {
+            let rebuilt = Filter::from_descriptor(manifest.serial, &descriptor, &options)?;
+            let rebuilt_hash = base64_serde::encode(&rebuilt.hash()?);
+            let signing_bytes = rebuilt.to_signing_bytes()?;
+            let signatures_verified = manifest
+                .signatures
+                .iter()
+                .filter(|signature| signature.verify(&signing_bytes).is_verified())
+                .count();
+            Ok((rebuilt_hash, signatures_verified))
+        };
+        let check_filter = || -> Result<(String, bool)> {
+            let filter_hash = base64_serde::encode(&filter.hash()?);
+            let filter_verified = filter.serial == manifest.serial
+                && filter_hash == manifest.hash
+                && filter.verify(&key).is_ok();
+            Ok((filter_hash, filter_verified))
+        };
+        let (rebuilt, checked) = if self.parallel_verify {
+            rayon::join(rebuild, check_filter)
+        } else {
+            (rebuild(), check_filter())
+        };
+        let (rebuilt_hash, signatures_verified) = rebuilt?;
+        let (filter_hash, filter_verified) = checked?;
+        let descriptor_verified = rebuilt_hash == manifest.hash;
+        let manifest_verified = signatures_verified >= key_manifest.required as usize;
+
+        let verified = descriptor_verified && manifest_verified && filter_verified;
+        let json = json!({
+            "descriptor": {
+                "hash": rebuilt_hash,
+                "verified": descriptor_verified,
+            },
+            "manifest": {
+                "serial": manifest.serial,
+                "hash": manifest.hash,
+                "signatures": signatures_verified,
+                "required": key_manifest.required,
+                "verified": manifest_verified,
+            },
+            "filter": {
+                "address": key.to_string(),
+                "serial": filter.serial,
+                "hash": filter_hash,
+                "verified": filter_verified,
+            },
+            "verified": verified,
+        });
+        print_result(&json)?;
+        if !verified {
+            anyhow::bail!("Release chain does not verify");
+        }
+        Ok(())
+    }
+}
+
+/// Generate signing data for a descriptor without the keys in an allowlist
+///
+/// Full nodes and edges touching an allowlisted key are dropped before the
+/// filter is built, so protected hotspots can not end up in a filter even if
+/// they were added to the descriptor by mistake. The allowlist is a json array
+/// of public keys.
+#[derive(Debug, clap::Args)]
+pub struct Prune {
+    /// The descriptor to build the filter from
+    #[arg(long, short, default_value = "descriptor.bin.gz")]
+    descriptor: PathBuf,
+    /// The json allowlist of public keys to keep out of the filter
+    #[arg(long)]
+    allow: PathBuf,
+    /// The serial number embedded in the signing bytes
+    #[arg(long, short)]
+    serial: u32,
+    /// The file to write the resulting signing bytes to
+    #[arg(long, short, default_value = "data.bin")]
+    output: PathBuf,
+    /// Whether to force overwrite an existing output file
+    #[arg(long, short)]
+    force: bool,
+}
+
+impl Prune {
+    pub fn run(&self) -> Result<()> {
+        let descriptor = Descriptor::from_path(&self.descriptor)
+            .context(format!("reading descriptor {}", self.descriptor.display()))?;
+        let allow = read_key_list(&self.allow, "allowlist")?;
+
+        let mut builder = DescriptorBuilder::from_descriptor(&descriptor);
+        let pruned: usize = allow.iter().map(|key| builder.remove_key(key)).sum();
+        let descriptor = builder.build();
+
+        let (filter, stats) = Filter::from_descriptor_with_stats(
+            self.serial,
+            &descriptor,
+            &FilterOptions::default(),
+        )?;
+        if let Some(warning) = stats.warning() {
+            eprintln!("warning: {warning}");
+        }
+        if let Some(key) = allow.iter().find(|key| filter.contains(key)) {
+            anyhow::bail!("Allowlisted key {key} tests positive in the pruned filter");
+        }
+
+        let mut file = AtomicFile::create(&self.output, !self.force)?;
+        file.write_all(&filter.to_signing_bytes()?)?;
+        file.commit()?;
+
+        let json = json!({
+            "pruned": pruned,
+            "allowlist": allow.len(),
+            "entries": stats.entries(),
+        });
+        print_result(&json)
+    }
+}
+
+/// Draw random member and non-member keys of a filter as test vectors
+///
+/// Members are sampled from the full nodes of the descriptor the filter was
+/// built from. Non-members are freshly generated keys that the filter does not
+/// contain.
+#[derive(clap::Args, Debug)]
+pub struct Sample {
+    /// The filter to sample keys for
+    #[arg(long, short, default_value = "filter.bin")]
+    input: PathBuf,
+    /// The descriptor the filter was built from
+    #[arg(long, short, default_value = "descriptor.bin.gz")]
+    descriptor: PathBuf,
+    /// The number of member keys to sample
+    #[arg(long, default_value_t = 10)]
+    members: usize,
+    /// The number of non-member keys to generate
+    #[arg(long, default_value_t = 10)]
+    non_members: usize,
+}
+
+impl Sample {
+    pub fn run(&self) -> Result<()> {
+        let filter = Filter::from_path(&self.input)
+            .context(format!("reading filter {}", self.input.display()))?;
+        let descriptor = Descriptor::from_path(&self.descriptor)
+            .context(format!("reading descriptor {}", self.descriptor.display()))?;
+
+        let mut rng = rand::thread_rng();
+        let members = descriptor
+            .iter_keys()
+            .choose_multiple(&mut rng, self.members);
+        if members.len() < self.members {
+            anyhow::bail!(
+                "Descriptor only has {} keys to sample {} members from",
+                members.len(),
+                self.members
+            );
+        }
+        if let Some(key) = members.iter().find(|key| !filter.contains(key)) {
+            anyhow::bail!("Descriptor key {key} is not in the filter");
+        }
+
+        let key_tag = KeyTag {
+            network: Network::MainNet,
+            key_type: KeyType::Ed25519,
+        };
+        let mut non_members = Vec::with_capacity(self.non_members);
+        while non_members.len() < self.non_members {
+            let public_key = Keypair::generate(key_tag, &mut OsRng).public_key().clone();
+            if !filter.contains(&public_key.clone().into()) {
+                non_members.push(public_key.to_string());
+            }
+        }
+
+        let json = json!({
+            "members": members.iter().map(|key| key.to_string()).collect::<Vec<_>>(),
+            "non_members": non_members,
+        });
+        print_result(&json)
+    }
+}
+
+/// Compare binary fuse filters of 8, 16 and 32 bit fingerprints for a
+/// descriptor
+///
+/// For each width the filter is built from the descriptor and its serialized
+/// size, build time and false positive rate are reported. The false positive
+/// rate is measured against random hashes that are not in the descriptor.
+/// Generated filters use 32 bit fingerprints unless `--fingerprint-bits` is
+/// given, this informs that choice.
+#[derive(clap::Args, Debug)]
+pub struct Analyze {
+    /// The descriptor to build the filters from
+    #[arg(long, short, default_value = "descriptor.bin.gz")]
+    descriptor: PathBuf,
+    /// The descriptor entries to build the filters with
+    #[arg(long, value_enum, default_value_t = EntriesArg::Both)]
+    entries: EntriesArg,
+    /// The number of random non-members to measure the false positive rate
+    /// with
+    #[arg(long, default_value_t = 1_000_000)]
+    samples: usize,
+}
+
+impl Analyze {
+    pub fn run(&self) -> Result<()> {
+        let descriptor = Descriptor::from_path(&self.descriptor)
+            .context(format!("reading descriptor {}", self.descriptor.display()))?;
+        let entries: Entries = self.entries.into();
+        let mut hashes: Vec<u64> = Vec::new();
+        if entries.includes_keys() {
+            hashes.extend(descriptor.iter_keys().map(|key| public_key_hash(&key)));
+        }
+        if entries.includes_edges() {
+            hashes.extend(descriptor.iter_edge_hashes());
+        }
+        hashes.sort_unstable();
+        hashes.dedup();
+        if hashes.is_empty() {
+            anyhow::bail!("Descriptor has no entries to analyze");
+        }
+
+        let mut samples = Vec::with_capacity(self.samples);
+        while samples.len() < self.samples {
+            let sample: u64 = rand::random();
+            if hashes.binary_search(&sample).is_err() {
+                samples.push(sample);
+            }
+        }
+
+        let json = json!({
+            "entries": hashes.len(),
+            "samples": samples.len(),
+            "widths": [
+                analyze_width::<BinaryFuse8>(8, &hashes, &samples)?,
+                analyze_width::<BinaryFuse16>(16, &hashes, &samples)?,
+                analyze_width::<BinaryFuse32>(32, &hashes, &samples)?,
+            ],
+        });
+        print_result(&json)
+    }
+}
+
+/// Check filter construction and membership against a descriptor
+///
+/// A filter is built from the descriptor, every key and edge of the
+/// descriptor is looked up in it, and random hashes that are not in the
+/// descriptor are looked up to measure the false positive rate. The command
+/// fails if any descriptor entry is missing, or if there are more false
+/// positives than `--max-fpp-factor` times the number expected of the
+/// fingerprint width, rounded up.
+#[derive(clap::Args, Debug)]
+pub struct Fuzz {
+    /// The descriptor to build the filter from
+    #[arg(long, short, default_value = "descriptor.bin.gz")]
+    descriptor: PathBuf,
+    /// The descriptor entries to build the filter with
+    #[arg(long, value_enum, default_value_t = EntriesArg::Both)]
+    entries: EntriesArg,
+    /// The filter implementation to build
+    #[arg(long, value_enum, default_value_t = BackendArg::Xor)]
+    backend: BackendArg,
+    /// The width of the filter fingerprints in bits, 8, 16 or 32. Widths below
+    /// 32 require the binary fuse backend
+    #[arg(long, default_value_t = DEFAULT_FINGERPRINT_BITS, value_parser = parse_fingerprint_bits)]
+    fingerprint_bits: u8,
+    /// The number of random non-members to look up
+    #[arg(long, default_value_t = 1_000_000)]
+    random: usize,
+    /// How many times the expected number of false positives is tolerated
+    #[arg(long, default_value_t = 10.0)]
+    max_fpp_factor: f64,
+}
+
+impl Fuzz {
+    pub fn run(&self) -> Result<()> {
+        let descriptor = Descriptor::from_path(&self.descriptor)
+            .context(format!("reading descriptor {}", self.descriptor.display()))?;
+        let entries: Entries = self.entries.into();
+        let options = FilterOptions {
+            entries,
+            backend: self.backend.into(),
+            fingerprint_bits: self.fingerprint_bits,
+            ..Default::default()
+        };
+        let filter = Filter::from_descriptor(0, &descriptor, &options)?;
+
+        let mut members: HashSet<u64> = HashSet::new();
+        let mut missing = vec![];
+        if entries.includes_keys() {
+            for key in descriptor.iter_keys() {
+                members.insert(public_key_hash(&key));
+                if !filter.contains(&key) {
+                    missing.push(json!({ "key": key.to_string() }));
+                }
+            }
+        }
+        if entries.includes_edges() {
+            members.extend(descriptor.iter_edge_hashes());
+            for (source, target, directed) in descriptor.iter_edges_directed() {
+                if !contains_descriptor_edge(&filter, &source, &target, directed) {
+                    missing.push(json!({
+                        "key": source.to_string(),
+                        "target": target.to_string(),
+                    }));
+                }
+            }
+        }
+
+        let mut samples = 0;
+        let mut false_positives = 0;
+        while samples < self.random {
+            let sample: u64 = rand::random();
+            if members.contains(&sample) {
+                continue;
+            }
+            samples += 1;
+            if filter.contains_hash(sample) {
+                false_positives += 1;
+            }
+        }
+        let expected_fpp = 2f64.powi(-i32::from(self.fingerprint_bits));
+        let allowed = (self.max_fpp_factor * expected_fpp * samples as f64).ceil() as usize;
+
+        let json = json!({
+            "members": members.len(),
+            "missing": missing,
+            "samples": samples,
+            "false_positives": false_positives,
+            "fpp": false_positives as f64 / samples.max(1) as f64,
+            "expected_fpp": expected_fpp,
+            "allowed_false_positives": allowed,
+        });
+        print_result(&json)?;
+        if !missing.is_empty() {
+            anyhow::bail!("{} descriptor entries are not in the filter", missing.len());
+        }
+        if false_positives > allowed {
+            anyhow::bail!("{false_positives} false positives exceed the {allowed} allowed");
+        }
+        Ok(())
+    }
+}
+
+/// Measure the query throughput of filters
+///
+/// The same workload of random keys is looked up in the filter a few times,
+/// and the best run is reported as queries per second. With --compare an old
+/// and a new filter are measured with the same workload, and the command fails
+/// if the new filter is more than --threshold percent slower, as a regression
+/// gate for format or backend changes.
+#[derive(clap::Args, Debug)]
+pub struct Bench {
+    /// The filter to measure
+    #[arg(long, short, default_value = "filter.bin", conflicts_with = "compare")]
+    input: PathBuf,
+    /// Compare an old and a new filter instead
+    #[arg(long, num_args = 2, value_names = ["OLD", "NEW"])]
+    compare: Vec<PathBuf>,
+    /// The number of random keys in the workload
+    #[arg(long, default_value_t = 1_000_000)]
+    queries: usize,
+    /// The number of times the workload is run against each filter
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+    runs: u32,
+    /// The largest percentage the new filter may be slower than the old one
+    #[arg(long, default_value_t = 5.0)]
+    threshold: f64,
+}
+
+impl Bench {
+    pub fn run(&self) -> Result<()> {
+        // ed25519 mainnet keys with random key bytes
+        let workload: Vec<PublicKeyBinary> = (0..self.queries)
+            .map(|_| {
+                let mut key = vec![0x01];
+                key.extend(rand::random::<[u8; 32]>());
+                PublicKeyBinary::from(key.as_slice())
+            })
+            .collect();
+        let read = |path: &PathBuf| {
+            Filter::from_path(path).context(format!("reading filter {}", path.display()))
+        };
+
+        let [old_path, new_path] = self.compare.as_slice() else {
+            let filter = read(&self.input)?;
+            let json = json!({
+                "input": self.input,
+                "queries": self.queries,
+                "queries_per_sec": self.throughput(&filter, &workload),
+            });
+            return print_result(&json);
+        };
+        let (old, new) = (read(old_path)?, read(new_path)?);
+        // alternate the runs so both filters see the same machine conditions
+        let (mut old_best, mut new_best) = (0f64, 0f64);
+        for _ in 0..self.runs {
+            old_best = old_best.max(measure(&old, &workload));
+            new_best = new_best.max(measure(&new, &workload));
+        }
+        let change = (new_best - old_best) / old_best.max(f64::MIN_POSITIVE) * 100.0;
+        let regressed = change < -self.threshold;
+        let json = json!({
+            "queries": self.queries,
+            "old": { "input": old_path, "queries_per_sec": old_best },
+            "new": { "input": new_path, "queries_per_sec": new_best },
+            "change_percent": change,
+            "threshold_percent": self.threshold,
+            "regressed": regressed,
+        });
+        print_result(&json)?;
+        if regressed {
+            anyhow::bail!(
+                "{} is {:.1}% slower than {}, more than the {}% threshold",
+                new_path.display(),
+                -change,
+                old_path.display(),
+                self.threshold
+            );
+        }
+        Ok(())
+    }
+
+    fn throughput(&self, filter: &Filter, workload: &[PublicKeyBinary]) -> f64 {
+        (0..self.runs)
+            .map(|_| measure(filter, workload))
+            .fold(0f64, f64::max)
+    }
+}
+
+/// Looks up every key of the workload and returns the queries per second
+fn measure(filter: &Filter, workload: &[PublicKeyBinary]) -> f64 {
+    let start = Instant::now();
+    let positives = workload.iter().filter(|key| filter.contains(key)).count();
+    std::hint::black_box(positives);
+    workload.len() as f64 / start.elapsed().as_secs_f64().max(f64::MIN_POSITIVE)
+}
+
+fn analyze_width<F>(bits: u8, hashes: &[u64], samples: &[u64]) -> Result<serde_json::Value>
+where
+    F: XorfFilter<u64> + serde::Serialize + for<'a> TryFrom<&'a [u64], Error = &'static str>,
+{
+    let start = Instant::now();
+    let filter = F::try_from(hashes).map_err(|err| anyhow::anyhow!("building filter: {err}"))?;
+    let build_time = start.elapsed();
+    let size = bincode::serialized_size(&filter)?;
+    let false_positives = samples
+        .iter()
+        .filter(|sample| filter.contains(sample))
+        .count();
+    Ok(json!({
+        "bits": bits,
+        "size": size,
+        "build_ms": build_time.as_secs_f64() * 1000.0,
+        "false_positives": false_positives,
+        "fpp": false_positives as f64 / samples.len().max(1) as f64,
+    }))
+}
+
+/// Write the exact bytes the signature of a filter is made over
+///
+/// These are the same bytes as the signing data the filter was generated
+/// from, so they can be byte compared between machines when signatures
+/// disagree.
+#[derive(clap::Args, Debug)]
+pub struct SigningBytes {
+    /// The filter to write the signing bytes of
+    #[arg(long, short, default_value = "filter.bin")]
+    input: PathBuf,
+    /// The file to write the signing bytes to, standard output if not given
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+    /// Whether to force overwrite an existing output file
+    #[arg(long, short)]
+    force: bool,
+}
+
+impl SigningBytes {
+    pub fn run(&self) -> Result<()> {
+        let filter = Filter::from_path(&self.input)
+            .context(format!("reading filter {}", self.input.display()))?;
+        let signing_bytes = filter.to_signing_bytes()?;
+        match &self.output {
+            Some(path) => {
+                let mut file = AtomicFile::create(path, !self.force)?;
+                file.write_all(&signing_bytes)?;
+                file.commit()
+            }
+            None => {
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(&signing_bytes)?;
+                stdout.flush()?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Reads a json array of public keys
+fn read_key_list(path: &Path, what: &str) -> Result<Vec<PublicKeyBinary>> {
+    let file = fs::File::open(path).context(format!("reading {what} {}", path.display()))?;
+    let keys: Vec<String> = serde_json::from_reader(BufReader::new(file))
+        .context(format!("reading {what} {}", path.display()))?;
+    keys.iter()
+        .map(|key| {
+            key.parse::<PublicKey>()
+                .map(PublicKeyBinary::from)
+                .context(format!("invalid public key \"{key}\" in {what}"))
+        })
+        .collect()
+}
+
+/// Compare which keys of a key universe two filters contain
+///
+/// Every key is checked against both filters, and the number of keys in both,
+/// in only one and in neither filter is reported. The keys are read from a
+/// json array of public keys, or from the full nodes of a descriptor.
+#[derive(clap::Args, Debug)]
+pub struct Intersect {
+    /// The first filter, for example the current deny list
+    a: PathBuf,
+    /// The second filter, for example the proposed deny list
+    b: PathBuf,
+    /// A json array of the public keys to compare
+    #[arg(
+        long,
+        required_unless_present = "descriptor",
+        conflicts_with = "descriptor"
+    )]
+    keys: Option<PathBuf>,
+    /// A descriptor whose full node keys to compare
+    #[arg(long)]
+    descriptor: Option<PathBuf>,
+    /// List the keys in each group rather than only counting them
+    #[arg(long)]
+    list: bool,
+}
+
+impl Intersect {
+    pub fn run(&self) -> Result<()> {
+        let a =
+            Filter::from_path(&self.a).context(format!("reading filter {}", self.a.display()))?;
+        let b =
+            Filter::from_path(&self.b).context(format!("reading filter {}", self.b.display()))?;
+        let keys = match (&self.keys, &self.descriptor) {
+            (Some(path), _) => read_key_list(path, "keys")?,
+            (None, Some(path)) => Descriptor::from_path(path)
+                .context(format!("reading descriptor {}", path.display()))?
+                .iter_keys()
+                .collect(),
+            (None, None) => anyhow::bail!("No keys given to compare"),
+        };
+
+        let mut groups: [Vec<String>; 4] = Default::default();
+        for key in &keys {
+            let group = match (a.contains(key), b.contains(key)) {
+                (true, true) => 0,
+                (true, false) => 1,
+                (false, true) => 2,
+                (false, false) => 3,
+            };
+            groups[group].push(key.to_string());
+        }
+        let [both, only_a, only_b, neither] = groups;
+        let group_json = |keys: Vec<String>| {
+            if self.list {
+                json!({ "count": keys.len(), "keys": keys })
+            } else {
+                json!({ "count": keys.len() })
+            }
+        };
+        let json = json!({
+            "keys": keys.len(),
+            "both": group_json(both),
+            "only_a": group_json(only_a),
+            "only_b": group_json(only_b),
+            "neither": group_json(neither),
+        });
+        print_result(&json)
+    }
+}
+
+/// Report how the membership of candidate keys changed between two filters
+///
+/// Filters can not be enumerated, so the diff is probe based: only the keys
+/// listed in the candidates file are looked up, and changes to any other key
+/// are not seen. Each candidate is reported as added, removed or unchanged
+/// from the old to the new filter. Like any lookup, a candidate can change
+/// through a false positive in either filter.
+#[derive(clap::Args, Debug)]
+pub struct Diff {
+    /// The old and the new filter, in that order
+    #[arg(long, short, num_args = 2, required = true, value_names = ["OLD", "NEW"])]
+    input: Vec<PathBuf>,
+    /// The candidate public keys, or "key,target" edges, one per line
+    #[arg(long)]
+    keys: PathBuf,
+}
+
+impl Diff {
+    pub fn run(&self) -> Result<()> {
+        let [old_path, new_path] = self.input.as_slice() else {
+            anyhow::bail!("Expected an old and a new filter");
+        };
+        let old = Filter::from_path(old_path)
+            .context(format!("reading filter {}", old_path.display()))?;
+        let new = Filter::from_path(new_path)
+            .context(format!("reading filter {}", new_path.display()))?;
+        let file =
+            fs::File::open(&self.keys).context(format!("reading keys {}", self.keys.display()))?;
+
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        let mut changes = vec![];
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, target) = parse_entry(line)
+                .with_context(|| format!("{} line {}", self.keys.display(), index + 1))?;
+            let key = PublicKeyBinary::from(key);
+            let target = target.map(PublicKeyBinary::from);
+            let lookup = |filter: &Filter| match &target {
+                Some(target) => filter.contains_edge(&key, target),
+                None => filter.contains(&key),
+            };
+            let change = match (lookup(&old), lookup(&new)) {
+                (false, true) => "added",
+                (true, false) => "removed",
+                _ => "unchanged",
+            };
+            *counts.entry(change).or_default() += 1;
+            let mut json = json!({ "address": key.to_string(), "change": change });
+            if let Some(target) = &target {
+                json["target"] = target.to_string().into();
+            }
+            changes.push(json);
+        }
+        let count = |change| counts.get(change).copied().unwrap_or_default();
+        let json = json!({
+            "old": { "input": old_path, "serial": old.serial },
+            "new": { "input": new_path, "serial": new.serial },
+            "candidates": changes.len(),
+            "added": count("added"),
+            "removed": count("removed"),
+            "unchanged": count("unchanged"),
+            "changes": changes,
+        });
+        print_result(&json)
+    }
+}
+
+/// Report the size and accuracy tradeoff of a filter
+///
+/// Reports the serialized size, the size of the fingerprint array and the
+/// false positive probability expected of the fingerprint width. Filters do
+/// not record how many entries they were built from, so bits per entry are
+/// only reported with the --descriptor the filter was built from. With
+/// --sample the false positive rate is also measured over keys known not to
+/// be in the filter.
+#[derive(clap::Args, Debug)]
+pub struct Stats {
+    /// The filter to report on
+    #[arg(long, short, default_value = "filter.bin")]
+    input: PathBuf,
+    /// The descriptor the filter was built from
+    #[arg(long)]
+    descriptor: Option<PathBuf>,
+    /// The descriptor entries the filter was built with
+    #[arg(long, value_enum, default_value_t = EntriesArg::Both, requires = "descriptor")]
+    entries: EntriesArg,
+    /// Public keys, or "key,target" edges, not in the filter, one per line
+    #[arg(long)]
+    sample: Option<PathBuf>,
+}
+
+impl Stats {
+    pub fn run(&self) -> Result<()> {
+        let filter = Filter::from_path(&self.input)
+            .context(format!("reading filter {}", self.input.display()))?;
+        let size = filter.to_bytes()?.len();
+        let bits = filter.filter.fingerprint_bits();
+        let array_length = filter.filter.params()?.array_length;
+        let fingerprint_bytes = array_length * usize::from(bits) / 8;
+        let mut json = json!({
+            "input": self.input,
+            "kind": filter.filter.kind(),
+            "size": size,
+            "fingerprint_bits": bits,
+            "fingerprints": array_length,
+            "fingerprint_bytes": fingerprint_bytes,
+            "overhead_bytes": size.saturating_sub(fingerprint_bytes),
+            "expected_fpp": 2f64.powi(-i32::from(bits)),
+        });
+        if let Some(path) = &self.descriptor {
+            let descriptor = Descriptor::from_path(path)
+                .context(format!("reading descriptor {}", path.display()))?;
+            let entries: Entries = self.entries.into();
+            let mut count = 0;
+            if entries.includes_keys() {
+                count += descriptor.key_count();
+            }
+            if entries.includes_edges() {
+                count += descriptor.edge_count();
+            }
+            json["entries"] = count.into();
+            json["bits_per_entry"] = (size as f64 * 8.0 / count.max(1) as f64).into();
+        }
+        if let Some(path) = &self.sample {
+            let file =
+                fs::File::open(path).context(format!("reading sample {}", path.display()))?;
+            let (mut samples, mut false_positives) = (0usize, 0usize);
+            for (index, line) in BufReader::new(file).lines().enumerate() {
+                let line = line?;
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let (key, target) = parse_entry(line)
+                    .with_context(|| format!("{} line {}", path.display(), index + 1))?;
+                let key = PublicKeyBinary::from(key);
+                let positive = match target.map(PublicKeyBinary::from) {
+                    Some(target) => filter.contains_edge(&key, &target),
+                    None => filter.contains(&key),
+                };
+                samples += 1;
+                false_positives += usize::from(positive);
+            }
+            json["sample"] = json!({
+                "samples": samples,
+                "false_positives": false_positives,
+                "fpp": false_positives as f64 / samples.max(1) as f64,
+            });
+        }
+        print_result(&json)
+    }
+}
+
+fn info_json(filter: &Filter, human: bool) -> Result<serde_json::Value> {
+    let mut json = serde_json::to_value(filter)?;
+    let size = filter.to_bytes()?.len();
+    let uncompressed_size = filter.to_uncompressed_bytes()?.len();
+    json["size"] = size.into();
+    json["uncompressed_size"] = uncompressed_size.into();
+    json["compression"] = filter.compression.name().into();
+    if human {
+        json["size_human"] = human_size(size).into();
+        json["uncompressed_size_human"] = human_size(uncompressed_size).into();
+    }
+    json["fingerprints"] = filter.len().into();
+    json["hash"] = base64_serde::encode(&filter.hash()?).into();
+    json["kind"] = filter.filter.kind().into();
+    json["fingerprint_bits"] = filter.filter.fingerprint_bits().into();
+    json["params"] = serde_json::to_value(filter.filter.params()?)?;
+    json["seed"] = filter.filter.seed()?.into();
+    // the seed is derived from the entries by the construction itself and
+    // retry salts are fixed, so the same entries always rebuild the same bytes
+    json["reproducible"] = true.into();
+    json["embedded_manifest"] = filter.embedded.is_some().into();
+    if let Some(entries_hash) = &filter.entries_hash {
+        json["entries_hash"] = base64_serde::encode(entries_hash).into();
+    }
+    Ok(json)
+}
+
+/// Rebuild a filter with another backend from the descriptor it was built from
+///
+/// The signature of the old filter is verified, and the descriptor must
+/// rebuild to exactly the old filter's signing data before anything is
+/// written, so a mismatched descriptor can not silently change membership.
+/// The new filter keeps the serial and is written as signing data, which has
+/// to go through a new manifest and signing round. The fingerprint width can
+/// be changed along with the backend.
+#[derive(Debug, clap::Args)]
+pub struct Repack {
+    /// The descriptor the old filter was built from
+    #[arg(long, default_value = "descriptor.bin.gz")]
+    descriptor: PathBuf,
+    /// The descriptor entries the old filter was built with
+    #[arg(long, value_enum, default_value_t = EntriesArg::Both)]
+    entries: EntriesArg,
+    /// The signed filter to repack
+    #[arg(long, short, default_value = "filter.bin")]
+    input: PathBuf,
+    /// The public key the old filter is signed with
+    #[arg(long, short, default_value = "public_key.json")]
+    key: PathBuf,
+    /// The filter implementation to rebuild with
+    #[arg(long, value_enum)]
+    backend: BackendArg,
+    /// The width of the fingerprints to rebuild with in bits, 8, 16 or 32. Widths below
+    /// 32 require the binary fuse backend
+    #[arg(long, default_value_t = DEFAULT_FINGERPRINT_BITS, value_parser = parse_fingerprint_bits)]
+    fingerprint_bits: u8,
+    /// The file to write the signing data of the rebuilt filter to
+    #[arg(long, short, default_value = "data.bin")]
+    output: PathBuf,
+    /// Whether to force overwrite an existing output file
+    #[arg(long, short)]
+    force: bool,
+}
+
+impl Repack {
+    pub fn run(&self) -> Result<()> {
+        let old = Filter::from_path(&self.input)
+            .context(format!("reading filter {}", self.input.display()))?;
+        let key_manifest = PublicKeyManifest::from_path(&self.key)
+            .context(format!("reading public key {}", self.key.display()))?;
+        old.verify(&key_manifest.public_key()?)
+            .context(format!("verifying filter {}", self.input.display()))?;
+        let descriptor = Descriptor::from_path(&self.descriptor)
+            .context(format!("reading descriptor {}", self.descriptor.display()))?;
+
+        let options = FilterOptions {
+            entries: self.entries.into(),
+            entries_hash: old.entries_hash.is_some(),
+            backend: old.filter.backend().unwrap_or_default(),
+            fingerprint_bits: old.filter.fingerprint_bits(),
+            salt: Some(old.salt),
+            ..Default::default()
+        };
+        let rebuilt = Filter::from_descriptor(old.serial, &descriptor, &options)?;
+        let old_hash = old.hash()?;
+        if rebuilt.hash()? != old_hash {
+            let old_hash = base64_serde::encode(&old_hash);
+            anyhow::bail!(
+                "descriptor {} does not rebuild filter {} with hash {old_hash}",
+                self.descriptor.display(),
+                self.input.display()
+            );
+        }
+
+        let options = FilterOptions {
+            backend: self.backend.into(),
+            fingerprint_bits: self.fingerprint_bits,
+            salt: None,
+            ..options
+        };
+        let repacked = Filter::from_descriptor(old.serial, &descriptor, &options)?;
+        let mut file = AtomicFile::create(&self.output, !self.force)?;
+        file.write_all(&repacked.to_signing_bytes()?)?;
+        file.commit()?;
+
+        let json = json!({
+            "serial": repacked.serial,
+            "old": {
+                "kind": old.filter.kind(),
+                "hash": base64_serde::encode(&old_hash),
+            },
+            "new": {
+                "kind": repacked.filter.kind(),
+                "hash": base64_serde::encode(&repacked.hash()?),
+            },
+            "output": self.output,
+        });
+        print_result(&json)
+    }
+}
+
+/// Pass a filter from stdin through to stdout, reporting on it on stderr
+///
+/// The filter info, and with --verify the verification result, is written to
+/// stderr as json, while the bytes read are written to stdout exactly as they
+/// were read. The filter has to be read in full before it can be parsed, and
+/// nothing is written to stdout if it does not parse or verify, so a broken
+/// filter can not make its way further down a pipeline.
+#[derive(clap::Args, Debug)]
+pub struct Tap {
+    /// Verify the filter signature before passing it through. Implied when
+    /// an inline key is given
+    #[arg(long)]
+    verify: bool,
+    /// The public key to verify with
+    #[arg(long, short, default_value = "public_key.json")]
+    key: PathBuf,
+    #[command(flatten)]
+    inline_key: InlineKey,
+}
+
+impl Tap {
+    pub fn run(&self) -> Result<()> {
+        let mut data = vec![];
+        io::Read::read_to_end(&mut io::stdin().lock(), &mut data).context("reading stdin")?;
+        let filter = Filter::from_bytes(&data).context("reading filter from stdin")?;
+        let mut json = info_json(&filter, false)?;
+        let verified = if self.verify || self.inline_key.inline()?.is_some() {
+            let key = self.inline_key.key_manifest(&self.key)?.public_key()?;
+            let result = filter.verify(&key);
+            json["address"] = key.to_string().into();
+            json["verified"] = result.is_ok().into();
+            if let Err(err) = &result {
+                json["reason"] = verify_failure(err).into();
+                json["error"] = err.to_string().into();
+            }
+            result.is_ok()
+        } else {
+            true
+        };
+        writeln!(
+            io::stderr().lock(),
+            "{}",
+            serde_json::to_string_pretty(&json)?
+        )?;
+        if !verified {
+            anyhow::bail!("Filter does not verify");
+        }
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&data)?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+/// Names the reason a filter failed to verify against a key, so a filter
+/// signed by another key can be told apart from a corrupt signature
+fn verify_failure(err: &Error) -> &'static str {
+    match err {
+        Error::SignatureMismatch(_) => "mismatch",
+        Error::MalformedSignature(_) => "malformed",
+        _ => "error",
+    }
+}
+
+fn print_verified(public_key: &PublicKey, verified: bool) -> Result<()> {
     let json = json!({
         "address":  public_key.to_string(),
         "verified": verified,
     });
-    print_json(&json)
+    print_result(&json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::test_util::{
+        arg, capture_results, descriptor, key, parse, sign_filter, test_dir, write_data,
+        write_descriptor, write_key_list, write_key_manifest, write_signed_filter,
+        write_signed_manifest,
+    };
+    use xorf_generator::FilterBackend;
+
+    #[test]
+    fn no_verify_output_still_verifies() {
+        let dir = test_dir("generate-no-verify");
+        let key = dir.join("public_key.json");
+        let keypairs = write_key_manifest(&key, 2, 2);
+        let data = dir.join("data.bin");
+        let filter = write_data(&data, 5);
+        let manifest = dir.join("manifest.json");
+        write_signed_manifest(&manifest, &filter, &key, &keypairs);
+        let output = dir.join("filter.bin");
+
+        parse::<Generate>(&[
+            "--data",
+            arg(&data),
+            "--key",
+            arg(&key),
+            "--manifest",
+            arg(&manifest),
+            "--output",
+            arg(&output),
+            "--no-verify",
+        ])
+        .run()
+        .unwrap();
+
+        let written = Filter::from_path(&output).unwrap();
+        assert_eq!(written.hash().unwrap(), filter.hash().unwrap());
+        let key_manifest = PublicKeyManifest::from_path(&key).unwrap();
+        written.verify(&key_manifest.public_key().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn verify_against_rejects_a_mismatched_key() {
+        let dir = test_dir("generate-verify-against");
+        let key = dir.join("public_key.json");
+        let keypairs = write_key_manifest(&key, 2, 2);
+        let trusted_key = dir.join("trusted_key.json");
+        fs::copy(&key, &trusted_key).unwrap();
+        let other_key = dir.join("other_key.json");
+        write_key_manifest(&other_key, 2, 2);
+        let data = dir.join("data.bin");
+        let filter = write_data(&data, 5);
+        let manifest = dir.join("manifest.json");
+        write_signed_manifest(&manifest, &filter, &key, &keypairs);
+        let output = dir.join("filter.bin");
+        let generate = |against: &Path| {
+            parse::<Generate>(&[
+                "--data",
+                arg(&data),
+                "--key",
+                arg(&key),
+                "--manifest",
+                arg(&manifest),
+                "--output",
+                arg(&output),
+                "--verify-against",
+                arg(against),
+                "--force",
+            ])
+            .run()
+        };
+
+        assert!(generate(&other_key).is_err());
+        assert!(!output.exists());
+        generate(&trusted_key).unwrap();
+        Filter::from_path(&output).unwrap();
+    }
+
+    #[test]
+    fn self_contained_verify_requires_a_trusted_key() {
+        let dir = test_dir("verify-self-contained");
+        let key = dir.join("public_key.json");
+        let keypairs = write_key_manifest(&key, 2, 2);
+        let other_key = dir.join("other_key.json");
+        write_key_manifest(&other_key, 2, 2);
+        let data = dir.join("data.bin");
+        let filter = write_data(&data, 5);
+        let manifest = dir.join("manifest.json");
+        write_signed_manifest(&manifest, &filter, &key, &keypairs);
+        let output = dir.join("filter.bin");
+        parse::<Generate>(&[
+            "--data",
+            arg(&data),
+            "--key",
+            arg(&key),
+            "--manifest",
+            arg(&manifest),
+            "--output",
+            arg(&output),
+            "--embed-manifest",
+        ])
+        .run()
+        .unwrap();
+
+        let verify = |key: &Path| {
+            parse::<Verify>(&[
+                "--input",
+                arg(&output),
+                "--self-contained",
+                "--key",
+                arg(key),
+            ])
+            .run()
+        };
+        verify(&key).unwrap();
+        let err = verify(&other_key).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("is not one of the given public keys"));
+    }
+
+    #[test]
+    fn pruned_keys_test_negative() {
+        let dir = test_dir("prune");
+        let nodes: Vec<PublicKeyBinary> = (0..150).map(|_| key()).collect();
+        let (edge_source, edge_target) = (key(), key());
+        let mut builder = DescriptorBuilder::default();
+        for node in &nodes {
+            builder.add_node(node.clone(), None, 0);
+        }
+        builder.add_edge(&edge_source, &edge_target, None, 0);
+        let descriptor = dir.join("descriptor.bin");
+        write_descriptor(&descriptor, &builder.build());
+        let mut allowed = nodes[..10].to_vec();
+        allowed.push(edge_source.clone());
+        let allow = dir.join("allow.json");
+        write_key_list(&allow, &allowed);
+        let output = dir.join("data.bin");
+
+        parse::<Prune>(&[
+            "--descriptor",
+            arg(&descriptor),
+            "--allow",
+            arg(&allow),
+            "--serial",
+            "1",
+            "--output",
+            arg(&output),
+        ])
+        .run()
+        .unwrap();
+
+        let filter = Filter::from_signing_path(&output, FILTTER_VERSION).unwrap();
+        assert!(allowed.iter().all(|key| !filter.contains(key)));
+        assert!(!filter.contains_edge(&edge_source, &edge_target));
+        assert!(nodes[10..].iter().all(|key| filter.contains(key)));
+    }
+
+    #[test]
+    fn windowed_contains_batch_matches_the_full_run() {
+        let dir = test_dir("contains-batch-window");
+        let descriptor = descriptor(100, 0);
+        let filter = Filter::from_descriptor(1, &descriptor, &FilterOptions::default()).unwrap();
+        let mut lines: Vec<String> = descriptor
+            .iter_keys()
+            .take(30)
+            .chain((0..30).map(|_| key()))
+            .map(|key| key.to_string())
+            .collect();
+        lines.insert(10, String::new());
+        lines.push(format!("{},{}", key(), key()));
+        let keys = dir.join("keys.txt");
+        fs::write(&keys, lines.join("\n")).unwrap();
+
+        let check = |window: &[&str]| {
+            let mut args = vec!["--keys", arg(&keys)];
+            args.extend(window);
+            let mut results = vec![];
+            parse::<ContainsBatch>(&args)
+                .check(&filter, |json| {
+                    results.push(json);
+                    Ok(())
+                })
+                .unwrap();
+            results
+        };
+        let full = check(&[]);
+        assert_eq!(full.len(), 61);
+        let mut windowed = vec![];
+        for offset in (0..lines.len()).step_by(7) {
+            let offset = offset.to_string();
+            windowed.extend(check(&["--offset", &offset, "--limit", "7"]));
+        }
+        assert_eq!(windowed, full);
+        assert_eq!(check(&["--offset", "60", "--limit", "10"]), full[59..]);
+    }
+
+    #[test]
+    fn signature_counts_report_a_barely_met_threshold() {
+        let dir = test_dir("verify-threshold");
+        let key = dir.join("public_key.json");
+        let keypairs = write_key_manifest(&key, 3, 2);
+        let key_manifest = PublicKeyManifest::from_path(&key).unwrap();
+        let filter = write_data(&dir.join("data.bin"), 1);
+        let signing_bytes = filter.to_signing_bytes().unwrap();
+        let report = |keypairs: &[Keypair], forged: usize| {
+            let mut manifest = Manifest::from_filter(&filter, &key_manifest).unwrap();
+            manifest.sign_with(&signing_bytes, keypairs).unwrap();
+            let mut manifest = serde_json::to_value(&manifest).unwrap();
+            let unsigned = manifest["signatures"]
+                .as_array_mut()
+                .unwrap()
+                .iter_mut()
+                .filter(|slot| slot["signature"] == "");
+            for slot in unsigned.take(forged) {
+                slot["signature"] = base64_serde::encode(&[1; 64]).into();
+            }
+            let manifest: Manifest = serde_json::from_value(manifest).unwrap();
+            let mut json = json!({});
+            parse::<Verify>(&["--print-signers"])
+                .report_signatures(&mut json, &filter, &manifest, &key_manifest)
+                .unwrap();
+            json
+        };
+
+        let json = report(&keypairs[..2], 1);
+        assert_eq!(json["signatures_present"], 3);
+        assert_eq!(json["signatures_valid"], 2);
+        assert_eq!(json["threshold"], 2);
+        assert_eq!(json["signers"].as_array().unwrap().len(), 2);
+
+        let json = report(&keypairs[..1], 0);
+        assert_eq!(json["signatures_present"], 1);
+        assert_eq!(json["signatures_valid"], 1);
+        assert_eq!(json["threshold"], 2);
+    }
+
+    #[test]
+    fn verify_cross_checks_the_expected_hash() {
+        let dir = test_dir("verify-expect-hash");
+        let filter = write_signed_filter(&dir, 1);
+        let hash = base64_serde::encode(&filter.hash().unwrap());
+        let verify = |expect_hash: &str| {
+            parse::<Verify>(&[
+                "--input",
+                arg(&dir.join("filter.bin")),
+                "--key",
+                arg(&dir.join("public_key.json")),
+                "--expect-hash",
+                expect_hash,
+            ])
+            .run()
+        };
+        verify(&hash).unwrap();
+        let other = base64_serde::encode(&[0; 32]);
+        let err = verify(&other).unwrap_err();
+        assert!(err.to_string().contains("does not match expected hash"));
+    }
+
+    #[test]
+    fn verify_reports_each_failed_check() {
+        let dir = test_dir("verify-checks");
+        let filter = write_signed_filter(&dir, 1);
+        let input = dir.join("filter.bin");
+        let key = dir.join("public_key.json");
+        let verify = |input: &Path, extra: &[&str]| {
+            let mut args = vec!["--input", arg(input), "--key", arg(&key)];
+            args.extend(extra);
+            let (result, printed) = capture_results(|| parse::<Verify>(&args).run());
+            let [json] = printed.as_slice() else {
+                panic!("expected a single result, got {printed:?}");
+            };
+            (result, json.clone())
+        };
+
+        let (result, json) = verify(&input, &[]);
+        result.unwrap();
+        assert_eq!(json["version_supported"], true);
+        assert_eq!(json.get("crc_ok"), Some(&serde_json::Value::Null));
+        assert_eq!(json["signature_verified"], true);
+        assert_eq!(json["verified"], true);
+
+        let (result, json) = verify(&input, &["--require-serial", "2"]);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("does not match required serial"));
+        assert_eq!(json["serial_matches"], false);
+        assert_eq!(json["signature_verified"], true);
+        assert_eq!(json["verified"], false);
+
+        let other_hash = base64_serde::encode(&[0; 32]);
+        let (result, json) = verify(&input, &["--expect-hash", other_hash.as_str()]);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("does not match expected hash"));
+        assert_eq!(json["expected_hash_matches"], false);
+        assert_eq!(json["hash"], base64_serde::encode(&filter.hash().unwrap()));
+        assert_eq!(json["signature_verified"], true);
+        assert_eq!(json["verified"], false);
+
+        let other_dir = test_dir("verify-checks-other");
+        write_signed_filter(&other_dir, 1);
+        let (result, json) = verify(&other_dir.join("filter.bin"), &[]);
+        assert!(result.is_err());
+        assert_eq!(json["version_supported"], true);
+        assert_eq!(json.get("crc_ok"), Some(&serde_json::Value::Null));
+        assert_eq!(json["signature_verified"], false);
+        assert_eq!(json["failures"][0]["reason"], "mismatch");
+
+        let bytes = filter.to_bytes().unwrap();
+        // the format version follows the magic, the filter version follows
+        // the reserved format flags
+        for (offset, field) in [(4, "format_version"), (6, "version")] {
+            let mut unsupported = bytes.clone();
+            unsupported[offset] = 9;
+            let path = dir.join(format!("unsupported-{field}.bin"));
+            fs::write(&path, unsupported).unwrap();
+            let (result, json) = verify(&path, &[]);
+            assert!(result.is_err());
+            assert_eq!(json[field], 9);
+            assert_eq!(json["version_supported"], false);
+            assert_eq!(json.get("crc_ok"), Some(&serde_json::Value::Null));
+            assert_eq!(json["signature_verified"], false);
+        }
+    }
+
+    #[test]
+    fn allowlisted_members_are_not_blocked() {
+        let dir = test_dir("contains-allow");
+        let descriptor = descriptor(100, 20);
+        let filter = Filter::from_descriptor(1, &descriptor, &FilterOptions::default()).unwrap();
+        let input = dir.join("filter.bin");
+        fs::write(&input, filter.to_bytes().unwrap()).unwrap();
+        let members: Vec<PublicKeyBinary> = descriptor.iter_keys().take(2).collect();
+        let allow = dir.join("allow.json");
+        write_key_list(&allow, &members[..1]);
+
+        for (member, allowlisted) in members.iter().zip([true, false]) {
+            let member = member.to_string();
+            let (result, printed) = capture_results(|| {
+                parse::<Contains>(&[
+                    "--input",
+                    arg(&input),
+                    "--allow",
+                    arg(&allow),
+                    member.as_str(),
+                ])
+                .run()
+            });
+            result.unwrap();
+            assert_eq!(printed[0]["in_filter"], true);
+            assert_eq!(printed[0]["allowlisted"], allowlisted);
+            assert_eq!(printed[0]["effective_block"], !allowlisted);
+        }
+    }
+
+    #[test]
+    fn parallel_chain_verify_matches_sequential() {
+        let dir = test_dir("verify-chain-parallel");
+        let signed = descriptor(100, 20);
+        let signed_path = dir.join("descriptor.bin.gz");
+        write_descriptor(&signed_path, &signed);
+        let other_path = dir.join("other.bin.gz");
+        write_descriptor(&other_path, &descriptor(100, 20));
+        let key = dir.join("public_key.json");
+        let keypairs = write_key_manifest(&key, 2, 2);
+        let manifest = dir.join("manifest.json");
+        let mut filter = Filter::from_descriptor(3, &signed, &FilterOptions::default()).unwrap();
+        write_signed_manifest(&manifest, &filter, &key, &keypairs);
+        filter
+            .apply_manifest(
+                &Manifest::from_path(&manifest).unwrap(),
+                &PublicKeyManifest::from_path(&key).unwrap(),
+            )
+            .unwrap();
+        let input = dir.join("filter.bin");
+        fs::write(&input, filter.to_bytes().unwrap()).unwrap();
+
+        for (descriptor, verified) in [(&signed_path, true), (&other_path, false)] {
+            let verify_chain = |parallel: bool| {
+                let mut args = vec![
+                    "--descriptor",
+                    arg(descriptor),
+                    "--manifest",
+                    arg(&manifest),
+                    "--key",
+                    arg(&key),
+                    "--input",
+                    arg(&input),
+                ];
+                if parallel {
+                    args.push("--parallel-verify");
+                }
+                capture_results(|| parse::<VerifyChain>(&args).run())
+            };
+            let (sequential, sequential_json) = verify_chain(false);
+            let (parallel, parallel_json) = verify_chain(true);
+            assert_eq!(sequential.is_ok(), verified);
+            assert_eq!(parallel.is_ok(), verified);
+            assert_eq!(sequential_json[0]["descriptor"]["verified"], verified);
+            assert_eq!(sequential_json[0]["filter"]["verified"], true);
+            assert_eq!(parallel_json, sequential_json);
+        }
+    }
+
+    #[test]
+    fn repack_rejects_a_mismatched_descriptor() {
+        let dir = test_dir("repack-guard");
+        let built = descriptor(100, 20);
+        let filter = Filter::from_descriptor(2, &built, &FilterOptions::default()).unwrap();
+        sign_filter(&dir, filter);
+        let matching = dir.join("descriptor.bin.gz");
+        write_descriptor(&matching, &built);
+        let mismatched = dir.join("other.bin.gz");
+        write_descriptor(&mismatched, &descriptor(100, 20));
+        let output = dir.join("data.bin");
+        let repack = |descriptor: &Path| {
+            parse::<Repack>(&[
+                "--descriptor",
+                arg(descriptor),
+                "--input",
+                arg(&dir.join("filter.bin")),
+                "--key",
+                arg(&dir.join("public_key.json")),
+                "--backend",
+                "binary-fuse",
+                "--output",
+                arg(&output),
+            ])
+            .run()
+        };
+
+        let err = repack(&mismatched).unwrap_err();
+        assert!(err.to_string().contains("does not rebuild filter"));
+        assert!(!output.exists());
+
+        repack(&matching).unwrap();
+        let repacked = Filter::from_signing_path(&output, FILTTER_VERSION).unwrap();
+        assert_eq!(repacked.serial, 2);
+        assert_eq!(repacked.filter.backend(), Some(FilterBackend::BinaryFuse));
+        assert!(built.iter_keys().all(|key| repacked.contains(&key)));
+    }
+
+    #[test]
+    fn quiet_ok_still_prints_every_failure() {
+        let dir = test_dir("verify-quiet-ok");
+        write_signed_filter(&dir, 1);
+        let input = dir.join("filter.bin");
+        let mut unsupported = fs::read(&input).unwrap();
+        unsupported[6] = 9;
+        let unsupported_input = dir.join("unsupported.bin");
+        fs::write(&unsupported_input, unsupported).unwrap();
+        let other_dir = test_dir("verify-quiet-ok-other");
+        write_signed_filter(&other_dir, 1);
+        let other_hash = base64_serde::encode(&[0; 32]);
+        let verify = |input: &Path, extra: &[&str]| {
+            let key = dir.join("public_key.json");
+            let mut args = vec!["--quiet-ok", "--input", arg(input), "--key", arg(&key)];
+            args.extend(extra);
+            capture_results(|| parse::<Verify>(&args).run())
+        };
+
+        let (result, printed) = verify(&input, &[]);
+        result.unwrap();
+        assert!(printed.is_empty());
+
+        let other_input = other_dir.join("filter.bin");
+        let failures: [(&Path, &[&str]); 5] = [
+            (&input, &["--require-serial", "2"]),
+            (&input, &["--expect-hash", other_hash.as_str()]),
+            (&input, &["--self-contained"]),
+            (&unsupported_input, &[]),
+            (&other_input, &[]),
+        ];
+        for (input, extra) in failures {
+            let (result, printed) = verify(input, extra);
+            assert!(result.is_err(), "{extra:?}");
+            assert_eq!(printed.len(), 1, "{extra:?}");
+            assert_eq!(printed[0]["verified"], false, "{extra:?}");
+        }
+    }
+
+    #[test]
+    fn checksum_only_skips_signature_work() {
+        let dir = test_dir("verify-checksum-only");
+        let mut filter = write_signed_filter(&dir, 4);
+        // a signature that would fail verification, and a key that does not
+        // exist, are never looked at
+        filter.signature = vec![0xff; 64];
+        let input = dir.join("corrupt.bin");
+        fs::write(&input, filter.to_bytes().unwrap()).unwrap();
+        let hash = base64_serde::encode(&filter.hash().unwrap());
+        let verify = |extra: &[&str]| {
+            let missing_key = dir.join("missing.json");
+            let mut args = vec!["--input", arg(&input), "--key", arg(&missing_key)];
+            args.extend(extra);
+            capture_results(|| parse::<Verify>(&args).run())
+        };
+
+        let (result, printed) = verify(&["--checksum-only", "--expect-hash", hash.as_str()]);
+        result.unwrap();
+        assert_eq!(printed[0]["hash"], hash);
+        assert_eq!(printed[0]["hash_checked"], true);
+        assert_eq!(printed[0].get("crc_ok"), Some(&serde_json::Value::Null));
+        assert_eq!(printed[0]["signature_verified"], false);
+        assert_eq!(printed[0]["authenticity_checked"], false);
+
+        let (result, _) = verify(&[]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn candidates_report_only_false_positives() {
+        let dir = test_dir("verify-candidates");
+        let built = descriptor(200, 0);
+        // 8 bit fingerprints make false positives common enough to find
+        let options = FilterOptions {
+            backend: FilterBackend::BinaryFuse,
+            fingerprint_bits: 8,
+            ..Default::default()
+        };
+        let filter = sign_filter(&dir, Filter::from_descriptor(1, &built, &options).unwrap());
+        let exclude = dir.join("descriptor.bin.gz");
+        write_descriptor(&exclude, &built);
+        let false_positive = std::iter::repeat_with(key)
+            .find(|key| filter.contains(key))
+            .unwrap();
+        let negative = std::iter::repeat_with(key)
+            .find(|key| !filter.contains(key))
+            .unwrap();
+        let mut candidates: Vec<PublicKeyBinary> = built.iter_keys().take(3).collect();
+        candidates.extend([false_positive.clone(), negative]);
+        let candidates_path = dir.join("candidates.json");
+        write_key_list(&candidates_path, &candidates);
+
+        let (result, printed) = capture_results(|| {
+            parse::<Verify>(&[
+                "--input",
+                arg(&dir.join("filter.bin")),
+                "--key",
+                arg(&dir.join("public_key.json")),
+                "--candidates",
+                arg(&candidates_path),
+                "--exclude",
+                arg(&exclude),
+            ])
+            .run()
+        });
+        result.unwrap();
+        assert_eq!(printed[0]["verified"], true);
+        assert_eq!(
+            printed[0]["false_positives"],
+            serde_json::json!([false_positive.to_string()])
+        );
+    }
+
+    #[test]
+    fn selftest_lists_entries_missing_from_the_filter() {
+        let dir = test_dir("filter-selftest");
+        let built_from = descriptor(50, 10);
+        let filter = Filter::from_descriptor(1, &built_from, &FilterOptions::default()).unwrap();
+        let input = dir.join("filter.bin");
+        fs::write(&input, filter.to_bytes().unwrap()).unwrap();
+        let selftest = |built_from: &Descriptor| {
+            let path = dir.join("descriptor.bin.gz");
+            write_descriptor(&path, built_from);
+            capture_results(|| {
+                parse::<Selftest>(&["--input", arg(&input), "--descriptor", arg(&path)]).run()
+            })
+        };
+
+        let (result, printed) = selftest(&built_from);
+        result.unwrap();
+        assert_eq!(printed[0]["present"], 60);
+        assert_eq!(printed[0]["missing"], json!([]));
+
+        let (result, printed) = selftest(&descriptor(3, 1));
+        assert!(result.is_err());
+        assert_eq!(printed[0]["missing"].as_array().unwrap().len(), 4);
+    }
+
+    /// Writes a filter over a few fresh keys and loads it to serve with the
+    /// given extra arguments
+    fn serve_state(dir: &Path, extra: &[&str]) -> (Descriptor, Arc<ServeState>) {
+        let descriptor = descriptor(10, 0);
+        let input = dir.join("filter.bin");
+        let filter = Filter::from_descriptor(1, &descriptor, &FilterOptions::default()).unwrap();
+        fs::write(&input, filter.to_bytes().unwrap()).unwrap();
+        let mut args = vec!["--input", arg(&input), "--listen", "unused"];
+        args.extend_from_slice(extra);
+        let state = parse::<Serve>(&args).state().unwrap();
+        (descriptor, Arc::new(state))
+    }
+
+    /// Sends the lines over the connection and reads a json result for each
+    fn query<S>(stream: S, lines: &[String]) -> Vec<serde_json::Value>
+    where
+        S: io::Read + Write,
+    {
+        let mut reader = BufReader::new(stream);
+        lines
+            .iter()
+            .map(|line| {
+                writeln!(reader.get_mut(), "{line}").unwrap();
+                let mut response = String::new();
+                reader.read_line(&mut response).unwrap();
+                serde_json::from_str(&response).unwrap()
+            })
+            .collect()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn serve_answers_over_a_unix_socket() {
+        use std::os::unix::net::{UnixListener, UnixStream};
+        let dir = test_dir("serve-unix");
+        let (descriptor, state) = serve_state(&dir, &[]);
+        let path = dir.join("filter.sock");
+        // the socket file of a server that did not shut down cleanly
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+
+        let socket = UnixSocket::bind(&path).unwrap();
+        assert!(UnixSocket::bind(&path).is_err());
+        let listener = socket.listener.try_clone().unwrap();
+        std::thread::spawn(move || serve_connections(listener.incoming(), state, 1));
+
+        let member = descriptor.iter_keys().next().unwrap().to_string();
+        let lines = [member.clone(), key().to_string(), "not a key".to_string()];
+        let results = query(UnixStream::connect(&path).unwrap(), &lines);
+        assert_eq!(results[0]["address"], member);
+        assert_eq!(results[0]["in_filter"], true);
+        assert_eq!(results[1]["in_filter"], false);
+        assert!(results[2]["error"].is_string());
+        // the single connection slot is handed back when a connection closes
+        let results = query(UnixStream::connect(&path).unwrap(), &lines[..1]);
+        assert_eq!(results[0]["in_filter"], true);
+
+        drop(socket);
+        assert!(!path.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn serve_answers_metrics_over_tcp() {
+        use std::net::{TcpListener, TcpStream};
+        let dir = test_dir("serve-tcp");
+        let (descriptor, state) = serve_state(&dir, &["--metrics", "--rate-limit", "100"]);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || serve_connections(listener.incoming(), state, 2));
+
+        let members: Vec<String> = descriptor.iter_keys().map(|key| key.to_string()).collect();
+        let mut lines = members[..3].to_vec();
+        lines.push(key().to_string());
+        lines.push("metrics".to_string());
+        let results = query(TcpStream::connect(address).unwrap(), &lines);
+        assert!(results[..3]
+            .iter()
+            .all(|result| result["in_filter"] == true));
+        assert_eq!(results[3]["in_filter"], false);
+        assert_eq!(results[4]["queries"], 4);
+        assert_eq!(results[4]["positives"], 3);
+        assert_eq!(results[4]["positive_ratio"], 0.75);
+
+        // the counters are shared by all connections
+        let results = query(TcpStream::connect(address).unwrap(), &lines[4..]);
+        assert_eq!(results[0]["queries"], 4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn signer_cmd_output_larger_than_a_pipe_does_not_deadlock() {
+        // the command fills its stdout before reading any of its input
+        let signing_bytes = vec![7u8; 256 * 1024];
+        let output =
+            run_signer_cmd("head -c 262144 /dev/zero; cat > /dev/null", &signing_bytes).unwrap();
+        assert_eq!(output.len(), 256 * 1024);
+
+        let output = run_signer_cmd("cat", &signing_bytes).unwrap();
+        assert_eq!(output, signing_bytes);
+        assert!(run_signer_cmd("cat > /dev/null; exit 3", &signing_bytes).is_err());
+    }
 }
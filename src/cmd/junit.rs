@@ -0,0 +1,93 @@
+use crate::cmd::open_output;
+use anyhow::{Context, Result};
+use std::{io::Write, path::Path};
+
+/// The outcome of a single check in a JUnit report
+pub enum Outcome {
+    Passed,
+    Failed(String),
+    Skipped(String),
+}
+
+/// A check reported as one test case of a JUnit report
+pub struct Case {
+    pub name: String,
+    pub outcome: Outcome,
+}
+
+impl Case {
+    pub fn new(name: impl Into<String>, outcome: Outcome) -> Self {
+        Self {
+            name: name.into(),
+            outcome,
+        }
+    }
+
+    /// A case that passes when `passed` and fails with the message otherwise
+    pub fn check(name: impl Into<String>, passed: bool, message: impl Into<String>) -> Self {
+        let outcome = if passed {
+            Outcome::Passed
+        } else {
+            Outcome::Failed(message.into())
+        };
+        Self::new(name, outcome)
+    }
+}
+
+/// Writes the cases as a single JUnit test suite to the given file, replacing
+/// any previous report
+pub fn write(path: &Path, suite: &str, cases: &[Case]) -> Result<()> {
+    let count = |f: fn(&Outcome) -> bool| cases.iter().filter(|case| f(&case.outcome)).count();
+    let failures = count(|outcome| matches!(outcome, Outcome::Failed(_)));
+    let skipped = count(|outcome| matches!(outcome, Outcome::Skipped(_)));
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuites tests=\"{}\" failures=\"{failures}\" skipped=\"{skipped}\">\n",
+        cases.len()
+    ));
+    xml.push_str(&format!(
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{failures}\" skipped=\"{skipped}\">\n",
+        escape(suite),
+        cases.len()
+    ));
+    for case in cases {
+        let open = format!(
+            "    <testcase classname=\"{}\" name=\"{}\"",
+            escape(suite),
+            escape(&case.name)
+        );
+        match &case.outcome {
+            Outcome::Passed => xml.push_str(&format!("{open}/>\n")),
+            Outcome::Failed(message) => xml.push_str(&format!(
+                "{open}>\n      <failure message=\"{}\"/>\n    </testcase>\n",
+                escape(message)
+            )),
+            Outcome::Skipped(message) => xml.push_str(&format!(
+                "{open}>\n      <skipped message=\"{}\"/>\n    </testcase>\n",
+                escape(message)
+            )),
+        }
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+
+    let mut file = open_output(path, false)?;
+    file.write_all(xml.as_bytes())
+        .context(format!("writing junit report {}", path.display()))
+}
+
+/// Escapes text for use in xml attribute values
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
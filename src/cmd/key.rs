@@ -1,5 +1,5 @@
-use crate::cmd::print_json;
-use anyhow::{Context, Result};
+use crate::cmd::print_result;
+use anyhow::{bail, Context, Result};
 use serde_json::json;
 use std::path::PathBuf;
 use xorf_generator::PublicKeyManifest;
@@ -20,17 +20,24 @@ impl Cmd {
 #[derive(clap::Subcommand, Debug)]
 pub enum KeyCommand {
     Info(Info),
+    Diff(Diff),
 }
 
 impl KeyCommand {
     pub fn run(&self) -> Result<()> {
         match self {
             Self::Info(cmd) => cmd.run(),
+            Self::Diff(cmd) => cmd.run(),
         }
     }
 }
 
 /// Displays key information for a given keypair
+///
+/// Lists the aggregate multisig address, each member key by its position in
+/// the file and the required signature threshold. Member keys listed more
+/// than once are reported as duplicates, and a file without members is an
+/// error.
 #[derive(clap::Args, Debug)]
 pub struct Info {
     /// File to read public key from
@@ -46,11 +53,93 @@ impl Info {
     }
 }
 
+/// Compares the members and threshold of two public key manifests
+///
+/// Reports the member keys that were added and removed going from the old to
+/// the new manifest, and the required signature threshold of each.
+#[derive(clap::Args, Debug)]
+pub struct Diff {
+    /// The old public key file
+    old: PathBuf,
+    /// The new public key file
+    new: PathBuf,
+}
+
+impl Diff {
+    pub fn run(&self) -> Result<()> {
+        let old = PublicKeyManifest::from_path(&self.old)
+            .context(format!("reading public key {}", self.old.display()))?;
+        let new = PublicKeyManifest::from_path(&self.new)
+            .context(format!("reading public key {}", self.new.display()))?;
+        let old_keys = old.public_keys();
+        let new_keys = new.public_keys();
+        let added: Vec<String> = new_keys
+            .iter()
+            .filter(|key| !old_keys.contains(key))
+            .map(|key| key.to_string())
+            .collect();
+        let removed: Vec<String> = old_keys
+            .iter()
+            .filter(|key| !new_keys.contains(key))
+            .map(|key| key.to_string())
+            .collect();
+        let json = json!({
+            "added": added,
+            "removed": removed,
+            "required": {
+                "old": old.required,
+                "new": new.required,
+                "changed": old.required != new.required,
+            },
+            "address": {
+                "old": old.public_key()?.to_string(),
+                "new": new.public_key()?.to_string(),
+            },
+        });
+        print_result(&json)
+    }
+}
+
 fn print_manifest(manifest: &PublicKeyManifest) -> Result<()> {
+    let keys: Vec<String> = manifest
+        .public_keys()
+        .iter()
+        .map(|key| key.to_string())
+        .collect();
+    if keys.is_empty() {
+        bail!("public key lists no member keys");
+    }
+    let members: Vec<_> = keys
+        .iter()
+        .enumerate()
+        .map(|(index, key)| json!({"member": index + 1, "key": key}))
+        .collect();
+    let mut duplicates: Vec<&String> = keys
+        .iter()
+        .enumerate()
+        .filter(|(index, key)| keys[..*index].contains(key))
+        .map(|(_, key)| key)
+        .collect();
+    duplicates.sort();
+    duplicates.dedup();
+    let address = match manifest.public_key() {
+        Ok(address) => address,
+        Err(_) if !duplicates.is_empty() => bail!(
+            "public key lists duplicate member keys: {}",
+            duplicates
+                .iter()
+                .map(|key| key.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Err(err) => return Err(err.into()),
+    };
     let json = json!({
-        "address": manifest.public_key()?.to_string(),
-        "keys": manifest.public_keys().len(),
+        "address": address.to_string(),
+        "keys": keys.len(),
         "required": manifest.required,
+        "members": members,
+        "duplicates": duplicates,
     });
-    print_json(&json)
+    print_result(&json)
 }
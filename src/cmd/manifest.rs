@@ -1,11 +1,26 @@
-use crate::cmd::{open_output_file, print_json};
+use crate::cmd::{
+    data::{parse_fingerprint_bits, BackendArg, EntriesArg},
+    descriptor::{check_descriptor, InputFormat},
+    human_size, junit, print_result, read_manifest, write_manifest, AtomicFile, ErrorMode,
+    FileLock, InlineKey, ManifestFormat, OutputName, Outputs, Progress,
+};
 use anyhow::{Context, Result};
+use helium_crypto::{Keypair, PublicKey};
+use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::path::PathBuf;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use xorf_generator::{
-    base64_serde, Filter, Manifest, ManifestSignature, ManifestSignatureVerify, PublicKeyManifest,
-    FILTTER_VERSION,
+    base64_serde, ChunkTree, Descriptor, DescriptorSource, Entries, Error, Filter, FilterOptions,
+    Manifest, ManifestSignatureVerify, PublicKeyManifest, DEFAULT_FINGERPRINT_BITS,
+    FILTTER_VERSION, SIG_SCHEME_MULTISIG,
 };
+use zeroize::Zeroizing;
 
 #[derive(clap::Args, Debug)]
 pub struct Cmd {
@@ -24,6 +39,10 @@ impl Cmd {
 pub enum ManifestCommand {
     Generate(Generate),
     Verify(Verify),
+    Batch(Batch),
+    RotateKeys(RotateKeys),
+    Sign(Sign),
+    DiffProof(DiffProof),
 }
 
 impl ManifestCommand {
@@ -31,6 +50,10 @@ impl ManifestCommand {
         match self {
             Self::Generate(cmd) => cmd.run(),
             Self::Verify(cmd) => cmd.run(),
+            Self::Batch(cmd) => cmd.run(),
+            Self::RotateKeys(cmd) => cmd.run(),
+            Self::Sign(cmd) => cmd.run(),
+            Self::DiffProof(cmd) => cmd.run(),
         }
     }
 }
@@ -43,101 +66,664 @@ impl ManifestCommand {
 #[derive(Debug, clap::Args)]
 
 pub struct Generate {
-    /// The signing data to generate a manifest for
-    #[arg(long, short, default_value = "data.bin")]
-    data: PathBuf,
+    /// The signing data to generate a manifest for [default: data.bin]
+    #[arg(long, short)]
+    data: Option<PathBuf>,
 
     /// The public key file to use
     #[arg(long, short, default_value = "public_key.json")]
     key: PathBuf,
 
-    /// The file to write the resulting manifest file to
-    #[arg(long, short, default_value = "manifest.json")]
-    manifest: PathBuf,
+    /// Reuse the member keys of the manifest embedded in this filter instead
+    /// of the public key file. The embedded manifest must verify the filter
+    #[arg(long, conflicts_with_all = ["key", "signers_from_manifest"])]
+    signers_from_filter: Option<PathBuf>,
+
+    /// Reuse the members of the signature slots of this manifest instead of
+    /// the public key file
+    #[arg(long, conflicts_with = "key", requires = "threshold")]
+    signers_from_manifest: Option<PathBuf>,
+
+    /// The number of signatures required from the members of
+    /// --signers-from-manifest
+    #[arg(long, requires = "signers_from_manifest")]
+    threshold: Option<u8>,
+
+    /// The file to write the resulting manifest file to [default: manifest.json]
+    #[arg(long, short)]
+    manifest: Option<PathBuf>,
+
+    #[command(flatten)]
+    naming: OutputName,
+
+    /// The encoding to write the manifest in
+    #[arg(long, value_enum, default_value_t = ManifestFormat::Json)]
+    format: ManifestFormat,
 
-    /// Whether to force overwrite an existing manifest file
+    /// Whether to force overwrite existing manifest and signing bytes files.
+    /// The data file is rewritten in place when its serial changes either
+    /// way
     #[arg(long, short)]
     force: bool,
+
+    /// Passphrase to encrypt or decrypt the manifest file with
+    #[arg(long, env = "XORF_MANIFEST_PASSPHRASE", hide_env_values = true)]
+    passphrase: Option<String>,
+
+    /// The base64 filter hash expected from another tool. Fails if the
+    /// generated hash differs
+    #[arg(long)]
+    compat_hash: Option<String>,
+
+    /// A prior manifest whose filter hash and serial to reuse, for example
+    /// when only the signing members changed. Fails if the filter no longer
+    /// produces that hash
+    #[arg(long)]
+    from_existing: Option<PathBuf>,
+
+    /// The serial to generate the manifest for instead of the serial of the
+    /// signing data. The data file is rewritten with it
+    #[arg(long, conflicts_with_all = ["from_existing", "bump_from"])]
+    serial: Option<u32>,
+
+    /// Use the serial after the one of this previous filter, rewriting the
+    /// data file with it, so serials keep increasing across regenerations
+    #[arg(long, conflicts_with = "from_existing")]
+    bump_from: Option<PathBuf>,
+
+    /// With --from-existing, rebuild the signing data from this descriptor
+    /// and write it to the data file instead of reading the data file
+    #[arg(long, requires = "from_existing")]
+    descriptor: Option<PathBuf>,
+
+    /// The format of the descriptor. Auto detects csv, event logs and
+    /// protobuf descriptors from the content
+    #[arg(long, value_enum, default_value_t = InputFormat::Auto, requires = "descriptor")]
+    descriptor_format: InputFormat,
+
+    /// The number of threads to hash the descriptor entries with, defaults to
+    /// the number of cpus
+    #[arg(long, default_value_t = 0, requires = "descriptor")]
+    threads: usize,
+
+    /// The descriptor entries the filter was built with
+    #[arg(long, value_enum, default_value_t = EntriesArg::Both)]
+    entries: EntriesArg,
+
+    /// The filter implementation the filter was built with
+    #[arg(long, value_enum, default_value_t = BackendArg::Xor, requires = "descriptor")]
+    backend: BackendArg,
+
+    /// The width in bits of the fingerprints the filter was built with
+    #[arg(long, default_value_t = DEFAULT_FINGERPRINT_BITS, value_parser = parse_fingerprint_bits, requires = "descriptor")]
+    fingerprint_bits: u8,
+
+    /// Also write the exact bytes the manifest signatures are made over to
+    /// this file
+    #[arg(long)]
+    signing_bytes_out: Option<PathBuf>,
+
+    /// Keep the output files written before an error instead of removing
+    /// them
+    #[arg(long)]
+    keep_partial: bool,
+
+    /// Also store a merkle tree over chunks of this many signing bytes in the
+    /// manifest, for `manifest diff-proof`
+    #[arg(long)]
+    chunk_size: Option<u32>,
+
+    /// Report progress as json lines of `{phase, processed, total}` on stderr
+    #[arg(long)]
+    progress_json: bool,
+
+    /// Do not report progress or entry counts
+    #[arg(long, short)]
+    quiet: bool,
 }
 
 impl Generate {
     pub fn run(&self) -> Result<()> {
-        let filter = Filter::from_signing_path(&self.data, FILTTER_VERSION)
-            .context(format!("reading filter {}", self.data.display()))?;
+        let data = self.naming.path(&self.data, ".data.bin", "data.bin");
+        let manifest_path = self
+            .naming
+            .path(&self.manifest, ".manifest.json", "manifest.json");
+        let existing = self
+            .from_existing
+            .as_ref()
+            .map(|path| read_manifest(path, self.passphrase.as_deref()))
+            .transpose()?;
+        let progress = Progress::new(self.progress_json, self.quiet);
+        let mut filter = match (&existing, &self.descriptor) {
+            (Some(existing), Some(descriptor_path)) => {
+                let descriptor = self
+                    .descriptor_format
+                    .source(descriptor_path)?
+                    .load()
+                    .context(format!("reading descriptor {}", descriptor_path.display()))?;
+                check_descriptor(&descriptor, descriptor_path)?;
+                progress.message(&format!(
+                    "loaded {} hotspots and {} edges from {}",
+                    descriptor.key_count(),
+                    descriptor.edge_count(),
+                    descriptor_path.display()
+                ));
+                let entries = Entries::from(self.entries);
+                let total = usize::from(entries.includes_keys()) * descriptor.key_count()
+                    + usize::from(entries.includes_edges()) * descriptor.edge_count();
+                progress.report("filter", 0, total);
+                let options = FilterOptions {
+                    entries,
+                    entries_hash: existing.entries_hash.is_some(),
+                    backend: self.backend.into(),
+                    fingerprint_bits: self.fingerprint_bits,
+                    salt: Some(existing.salt),
+                    ..Default::default()
+                };
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(self.threads)
+                    .build()?;
+                let (filter, _) = pool.install(|| {
+                    Filter::from_descriptor_with_progress(
+                        existing.serial,
+                        &descriptor,
+                        &options,
+                        &|hashed| progress.report("filter", hashed, total),
+                    )
+                })?;
+                filter
+            }
+            _ => {
+                progress.report("filter", 0, 1);
+                let filter = Filter::from_signing_path(&data, FILTTER_VERSION)
+                    .context(format!("reading filter {}", data.display()))?;
+                progress.report("filter", 1, 1);
+                filter
+            }
+        };
+        let reserialed = self.apply_serial(&mut filter)?;
+        let key_manifest = self.key_manifest()?;
+        let mut manifest = Manifest::from_filter(&filter, &key_manifest)?;
+        if let Some(chunk_size) = self.chunk_size {
+            manifest.chunk_tree = Some(ChunkTree::new(&filter.to_signing_bytes()?, chunk_size)?);
+        }
+        progress.report(
+            "manifest",
+            manifest.signatures.len(),
+            manifest.signatures.len(),
+        );
+        let mut outputs = Outputs::new(self.keep_partial);
+        let write_total = 1
+            + usize::from(existing.is_some() && self.descriptor.is_some())
+            + usize::from(reserialed)
+            + usize::from(self.signing_bytes_out.is_some());
+        let mut written = 0;
+        progress.report("write", written, write_total);
+        if let Some(existing) = &existing {
+            if manifest.hash != existing.hash || manifest.serial != existing.serial {
+                anyhow::bail!(
+                    "Filter hash {} for serial {} does not match existing hash {} for serial {}",
+                    manifest.hash,
+                    manifest.serial,
+                    existing.hash,
+                    existing.serial
+                );
+            }
+            if self.descriptor.is_some() {
+                // the data file is an input that is rewritten in place
+                let mut data_file = AtomicFile::create(&data, false)?;
+                data_file.write_all(&filter.to_signing_bytes()?)?;
+                outputs.add(data_file)?;
+                written += 1;
+                progress.report("write", written, write_total);
+            }
+        }
+        if reserialed {
+            let mut data_file = AtomicFile::create(&data, false)?;
+            data_file.write_all(&filter.to_signing_bytes()?)?;
+            outputs.add(data_file)?;
+            written += 1;
+            progress.report("write", written, write_total);
+        }
+        if let Some(path) = &self.signing_bytes_out {
+            let mut file = AtomicFile::create(path, !self.force)?;
+            file.write_all(&filter.to_signing_bytes()?)?;
+            outputs.add(file)?;
+            written += 1;
+            progress.report("write", written, write_total);
+        }
+        if let Some(compat_hash) = &self.compat_hash {
+            let expected = base64_serde::decode(compat_hash).context("decoding compat hash")?;
+            if expected != filter.hash()? {
+                anyhow::bail!(
+                    "Filter hash {} does not match compat hash {compat_hash}",
+                    manifest.hash
+                );
+            }
+        }
 
-        let filter_hash = filter.hash()?;
+        let mut manifest_file = AtomicFile::create(&manifest_path, !self.force)?;
+        write_manifest(
+            &mut manifest_file,
+            &manifest,
+            self.passphrase.as_deref(),
+            self.format,
+        )?;
+        outputs.add(manifest_file)?;
+        outputs.commit()?;
+        progress.report("write", write_total, write_total);
+        progress.message(&format!(
+            "filter holds {} fingerprints, signing data {} is {}",
+            filter.len(),
+            data.display(),
+            human_size(filter.to_signing_bytes()?.len())
+        ));
+        Ok(())
+    }
+
+    /// Sets the serial from --serial or --bump-from on the filter and returns
+    /// whether it changed. Without either, warns when the previous filter in
+    /// the output directory does not have a lower serial.
+    fn apply_serial(&self, filter: &mut Filter) -> Result<bool> {
+        let serial = match (self.serial, &self.bump_from) {
+            (Some(serial), _) => serial,
+            (None, Some(path)) => {
+                let previous = Filter::from_path(path)
+                    .context(format!("reading filter {}", path.display()))?;
+                previous.serial.checked_add(1).context(format!(
+                    "serial {} of {} can not be bumped",
+                    previous.serial,
+                    path.display()
+                ))?
+            }
+            (None, None) => {
+                if self.from_existing.is_some() {
+                    return Ok(false);
+                }
+                let previous_path = self.naming.path(&None, ".bin", "filter.bin");
+                if let Ok(previous) = Filter::from_path(&previous_path) {
+                    if previous.serial >= filter.serial {
+                        eprintln!(
+                            "warning: {} already has serial {}, pass --bump-from {} to generate serial {}",
+                            previous_path.display(),
+                            previous.serial,
+                            previous_path.display(),
+                            previous.serial.saturating_add(1)
+                        );
+                    }
+                }
+                return Ok(false);
+            }
+        };
+        let changed = serial != filter.serial;
+        filter.serial = serial;
+        Ok(changed)
+    }
+
+    /// The member keys to create signature slots for, from a prior filter or
+    /// manifest when given and from the public key file otherwise
+    fn key_manifest(&self) -> Result<PublicKeyManifest> {
+        if let Some(path) = &self.signers_from_filter {
+            let filter =
+                Filter::from_path(path).context(format!("reading filter {}", path.display()))?;
+            filter
+                .verify_embedded()
+                .context(format!("verifying embedded manifest of {}", path.display()))?;
+            let embedded = filter.embedded.context("filter has no embedded manifest")?;
+            return Ok(embedded.public_key);
+        }
+        if let (Some(path), Some(threshold)) = (&self.signers_from_manifest, self.threshold) {
+            let manifest = read_manifest(path, self.passphrase.as_deref())?;
+            return PublicKeyManifest::from_signers(&manifest, threshold)
+                .context(format!("reading signers of {}", path.display()));
+        }
+        PublicKeyManifest::from_path(&self.key)
+            .context(format!("reading public key {}", self.key.display()))
+    }
+}
+
+/// Generate manifests for a number of signing data files at once
+///
+/// A manifest named `<name>.manifest.json` is written to the output directory
+/// for every `<name>.bin` data file. An `index.json` mapping each serial to
+/// the hash, data file and manifest file is written alongside them so
+/// downstream tooling does not have to scan the directory.
+#[derive(Debug, clap::Args)]
+pub struct Batch {
+    /// The signing data files to generate manifests for
+    #[arg(required = true)]
+    data: Vec<PathBuf>,
+
+    /// The public key file to use
+    #[arg(long, short, default_value = "public_key.json")]
+    key: PathBuf,
+
+    /// The directory to write the manifests and index to
+    #[arg(long, short, default_value = ".")]
+    output_dir: PathBuf,
+
+    /// Whether to force overwrite existing manifest and index files
+    #[arg(long, short)]
+    force: bool,
+
+    /// Passphrase to encrypt the manifest files with
+    #[arg(long, env = "XORF_MANIFEST_PASSPHRASE", hide_env_values = true)]
+    passphrase: Option<String>,
+
+    /// Record completed manifests in this file and skip them when the batch
+    /// is run again. The file is removed once the batch completes
+    #[arg(long)]
+    state_file: Option<PathBuf>,
+
+    #[command(flatten)]
+    errors: ErrorMode,
+}
+
+#[derive(Debug, Serialize)]
+struct IndexEntry {
+    hash: String,
+    data: PathBuf,
+    manifest: PathBuf,
+}
+
+/// The progress of a batch, by the hash of each completed data file
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BatchState {
+    address: String,
+    completed: BTreeMap<String, CompletedEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CompletedEntry {
+    serial: u32,
+    manifest: PathBuf,
+}
+
+impl Batch {
+    pub fn run(&self) -> Result<()> {
         let key_manifest = PublicKeyManifest::from_path(&self.key)
             .context(format!("reading public key {}", self.key.display()))?;
-        let signatures = key_manifest
-            .public_keys
-            .iter()
-            .map(ManifestSignature::from)
-            .collect();
+        let address = key_manifest.public_key()?.to_string();
+        let mut state = match &self.state_file {
+            Some(path) if path.exists() => read_batch_state(path, &address)?,
+            _ => BatchState {
+                address,
+                ..Default::default()
+            },
+        };
 
-        let mut manifest_file = open_output_file(&self.manifest, !self.force)?;
-        let manifest = Manifest {
-            serial: filter.serial,
-            hash: base64_serde::encode(&filter_hash),
-            signatures,
+        let mut index: BTreeMap<u32, IndexEntry> = BTreeMap::new();
+        let mut errors = vec![];
+        for data in &self.data {
+            if let Err(err) = self.add_data(data, &key_manifest, &mut state, &mut index) {
+                if !self.errors.collect() {
+                    return Err(err);
+                }
+                errors.push(json!({
+                    "data": data,
+                    "error": format!("{err:#}"),
+                }));
+            }
+        }
+        if !errors.is_empty() {
+            print_result(&errors)?;
+            anyhow::bail!(
+                "{} of {} data files failed, index not written",
+                errors.len(),
+                self.data.len()
+            );
+        }
+
+        let mut index_file = AtomicFile::create(&self.output_dir.join("index.json"), !self.force)?;
+        serde_json::to_writer_pretty(&mut index_file, &index)?;
+        index_file.commit()?;
+        if let Some(path) = &self.state_file {
+            if path.exists() {
+                fs::remove_file(path).context(format!("removing state file {}", path.display()))?;
+            }
+        }
+        print_result(&index)
+    }
+
+    /// Generates the manifest for a data file, unless the state file records
+    /// it as completed, and adds it to the index
+    fn add_data(
+        &self,
+        data: &Path,
+        key_manifest: &PublicKeyManifest,
+        state: &mut BatchState,
+        index: &mut BTreeMap<u32, IndexEntry>,
+    ) -> Result<()> {
+        let filter = Filter::from_signing_path(data, FILTTER_VERSION)
+            .context(format!("reading filter {}", data.display()))?;
+        if let Some(existing) = index.get(&filter.serial) {
+            anyhow::bail!(
+                "serial {} of {} already used by {}",
+                filter.serial,
+                data.display(),
+                existing.data.display()
+            );
+        }
+        let Some(name) = data.file_stem() else {
+            anyhow::bail!("invalid data file name {}", data.display());
         };
-        serde_json::to_writer_pretty(&mut manifest_file, &manifest)?;
+        let manifest_path = self
+            .output_dir
+            .join(format!("{}.manifest.json", name.to_string_lossy()));
+
+        let hash = base64_serde::encode(&filter.hash()?);
+        let completed = state.completed.get(&hash).is_some_and(|entry| {
+            entry.serial == filter.serial
+                && entry.manifest == manifest_path
+                && manifest_path.exists()
+        });
+        if completed {
+            index.insert(
+                filter.serial,
+                IndexEntry {
+                    hash,
+                    data: data.to_path_buf(),
+                    manifest: manifest_path,
+                },
+            );
+            return Ok(());
+        }
+
+        let manifest = Manifest::from_filter(&filter, key_manifest)?;
+        let mut manifest_file = AtomicFile::create(&manifest_path, !self.force)?;
+        write_manifest(
+            &mut manifest_file,
+            &manifest,
+            self.passphrase.as_deref(),
+            ManifestFormat::Json,
+        )?;
+        manifest_file.commit()?;
+        if let Some(path) = &self.state_file {
+            state.completed.insert(
+                hash,
+                CompletedEntry {
+                    serial: manifest.serial,
+                    manifest: manifest_path.clone(),
+                },
+            );
+            let mut state_file = AtomicFile::create(path, false)?;
+            serde_json::to_writer_pretty(&mut state_file, state)?;
+            state_file.commit()?;
+        }
 
+        index.insert(
+            manifest.serial,
+            IndexEntry {
+                hash: manifest.hash,
+                data: data.to_path_buf(),
+                manifest: manifest_path,
+            },
+        );
         Ok(())
     }
 }
 
+/// Reads the state of an interrupted batch, which must have been run with the
+/// same public key
+fn read_batch_state(path: &Path, address: &str) -> Result<BatchState> {
+    let data = fs::read(path).context(format!("reading state file {}", path.display()))?;
+    let state: BatchState =
+        serde_json::from_slice(&data).context(format!("reading state file {}", path.display()))?;
+    if state.address != address {
+        anyhow::bail!(
+            "state file {} was written for public key {}, not {address}",
+            path.display(),
+            state.address
+        );
+    }
+    Ok(state)
+}
+
 /// Verify the manifest for a given datafile, public key and manifest file
 ///
 /// This takes a a filename of a binary filter data file as well as the manifest
 ///  file and public multisig key, and validates whether the manifest verifies
 ///  the filter hash. If so it prints out signature status for each multisig
 ///  member.
+///
+/// By default incomplete or invalid signatures are only reported. Use
+/// --fail-on-missing and --fail-on-invalid to turn them into errors.
 #[derive(Debug, clap::Args)]
 
 pub struct Verify {
-    /// The file with the data bytes that were signed
+    /// The file with the data bytes that were signed. Given more than once,
+    /// the parts are joined in order
     #[arg(long, short, default_value = "data.bin")]
-    data: PathBuf,
+    data: Vec<PathBuf>,
+
+    /// The base64 hash of the signing data, to verify without the data file.
+    /// Signatures can then only be checked for being present from members,
+    /// not for being valid
+    #[arg(long, conflicts_with_all = ["data", "descriptor", "fail_on_invalid"])]
+    data_hash: Option<String>,
 
     /// The public key file to use
     #[arg(long, short, default_value = "public_key.json")]
     key: PathBuf,
 
+    #[command(flatten)]
+    inline_key: InlineKey,
+
     /// The manifest file to verify
     #[arg(long, short, default_value = "manifest.json")]
     manifest: PathBuf,
+
+    /// Exit with an error if fewer than the required number of signatures
+    /// are present
+    #[arg(long)]
+    fail_on_missing: bool,
+
+    /// Exit with an error if any present signature does not verify
+    #[arg(long)]
+    fail_on_invalid: bool,
+
+    /// Exit with an error if fewer than the required number of member
+    /// signatures verify
+    #[arg(long, conflicts_with = "data_hash")]
+    fail_below_threshold: bool,
+
+    /// Exit with an error if the manifest is not for this serial
+    #[arg(long)]
+    require_serial: Option<u32>,
+
+    /// The descriptor to check against the entries hash in the manifest
+    #[arg(long)]
+    descriptor: Option<PathBuf>,
+
+    /// The descriptor entries the filter was built with
+    #[arg(long, value_enum, default_value_t = EntriesArg::Both, requires = "descriptor")]
+    entries: EntriesArg,
+
+    /// Passphrase to encrypt or decrypt the manifest file with
+    #[arg(long, env = "XORF_MANIFEST_PASSPHRASE", hide_env_values = true)]
+    passphrase: Option<String>,
+
+    /// Also write the checks as a JUnit xml report to this file, with a test
+    /// case for the hash, each signature, the threshold and the descriptor
+    #[arg(long, conflicts_with = "data_hash")]
+    output_junit: Option<PathBuf>,
 }
 
 impl Verify {
     pub fn run(&self) -> Result<()> {
-        let manifest = Manifest::from_path(&self.manifest)
-            .context(format!("reading manifest {}", self.manifest.display()))?;
+        let mut manifest = read_manifest(&self.manifest, self.passphrase.as_deref())?;
+        if let Some(serial) = self.require_serial {
+            if manifest.serial != serial {
+                anyhow::bail!(
+                    "Manifest serial {} does not match required serial {serial}",
+                    manifest.serial
+                );
+            }
+        }
+        if manifest.sig_scheme != SIG_SCHEME_MULTISIG {
+            return Err(Error::UnsupportedScheme(manifest.sig_scheme).into());
+        }
+        manifest.sort_signatures();
         let manifest_hash = base64_serde::decode(&manifest.hash)?;
-        let key_manifest = PublicKeyManifest::from_path(&self.key)
-            .context(format!("reading public key {}", self.key.display()))?;
+        let key_manifest = self.inline_key.key_manifest(&self.key)?;
         let key = key_manifest.public_key()?;
+        if let Some(data_hash) = &self.data_hash {
+            return self.verify_detached(&manifest, &key_manifest, data_hash);
+        }
 
-        let filter = Filter::from_signing_path(&self.data, FILTTER_VERSION)
-            .context(format!("reading filter {}", self.data.display()))?;
+        let filter = Filter::from_signing_parts(&self.data, FILTTER_VERSION)
+            .context(format!("reading filter {}", display_paths(&self.data)))?;
         let filter_hash = filter.hash()?;
         let signing_bytes = filter.to_signing_bytes()?;
 
         let hash_verified = manifest_hash == filter_hash;
         if !hash_verified {
-            anyhow::bail!(format!(
+            let message = format!(
                 "Filter hash {} does not match manifest hash {}",
                 base64_serde::encode(&filter_hash),
                 manifest.hash
-            ));
+            );
+            self.write_junit(&[junit::Case::check("hash", false, &message)])?;
+            anyhow::bail!(message);
         }
         let signtatures: Vec<ManifestSignatureVerify> = manifest
             .signatures
             .iter()
             .map(|signature| signature.verify(&signing_bytes))
             .collect();
+        let members = key_manifest.public_keys();
+        let statuses: Vec<SignatureStatus> = manifest
+            .signatures
+            .iter()
+            .zip(&signtatures)
+            .map(|(signature, verify)| {
+                SignatureStatus::new(members.contains(signature.address()), verify)
+            })
+            .collect();
+        let mut cases = vec![junit::Case::check("hash", true, "")];
+        for (signature, status) in manifest.signatures.iter().zip(&statuses) {
+            cases.push(junit::Case::new(
+                format!("signature {}", signature.address()),
+                status.outcome(),
+            ));
+        }
+        let count = |status| statuses.iter().filter(|s| **s == status).count();
+        let valid = count(SignatureStatus::Valid);
+        let threshold_met = valid >= key_manifest.required as usize;
+        cases.push(junit::Case::check(
+            "threshold",
+            threshold_met,
+            format!(
+                "Only {valid} of {} required signatures verify",
+                key_manifest.required
+            ),
+        ));
 
-        let json = json!({
-            "signing_data": self.data,
+        let mut json = json!({
+            "signing_data": match self.data.as_slice() {
+                [data] => json!(data),
+                parts => json!(parts),
+            },
             "hash": {
                 "serial": manifest.serial,
                 "hash": manifest.hash,
@@ -145,7 +731,927 @@ impl Verify {
             },
             "public_key": key,
             "signatures": signtatures,
+            "threshold": {
+                "required": key_manifest.required,
+                "valid_count": valid,
+                "met": threshold_met,
+            },
+        });
+        if let Some(signatures) = json["signatures"].as_array_mut() {
+            for (signature, status) in signatures.iter_mut().zip(&statuses) {
+                signature["status"] = status.name().into();
+            }
+        }
+        let mut descriptor_verified = true;
+        if let Some(path) = &self.descriptor {
+            let Some(entries_hash) = &filter.entries_hash else {
+                anyhow::bail!("Signing data does not include a descriptor entries hash");
+            };
+            let descriptor = Descriptor::from_path(path)
+                .context(format!("reading descriptor {}", path.display()))?;
+            let descriptor_hash = descriptor.entries_hash(self.entries.into());
+            descriptor_verified = &descriptor_hash == entries_hash;
+            json["descriptor"] = json!({
+                "entries_hash": base64_serde::encode(&descriptor_hash),
+                "verified": descriptor_verified,
+            });
+            cases.push(junit::Case::check(
+                "descriptor",
+                descriptor_verified,
+                "Descriptor entries do not match the manifest entries hash",
+            ));
+        }
+        print_result(&json)?;
+        self.write_junit(&cases)?;
+
+        if !descriptor_verified {
+            anyhow::bail!("Descriptor entries do not match the manifest entries hash");
+        }
+
+        if self.fail_on_missing {
+            let present = valid + count(SignatureStatus::Invalid);
+            if present < key_manifest.required as usize {
+                anyhow::bail!(
+                    "Only {present} of {} required signatures present",
+                    key_manifest.required
+                );
+            }
+        }
+        if self.fail_on_invalid {
+            let invalid = count(SignatureStatus::Invalid);
+            if invalid > 0 {
+                anyhow::bail!("{invalid} signatures do not verify");
+            }
+        }
+        if self.fail_below_threshold && !threshold_met {
+            anyhow::bail!(
+                "Only {valid} of {} required signatures verify",
+                key_manifest.required
+            );
+        }
+        Ok(())
+    }
+
+    fn write_junit(&self, cases: &[junit::Case]) -> Result<()> {
+        match &self.output_junit {
+            Some(path) => junit::write(path, "manifest verify", cases),
+            None => Ok(()),
+        }
+    }
+
+    /// Verifies a manifest against a detached hash of the signing data. The
+    /// signatures can not be checked against the data, so they are only
+    /// reported as present and from a member or not.
+    fn verify_detached(
+        &self,
+        manifest: &Manifest,
+        key_manifest: &PublicKeyManifest,
+        data_hash: &str,
+    ) -> Result<()> {
+        let expected = base64_serde::decode(data_hash).context("decoding data hash")?;
+        let manifest_hash = base64_serde::decode(&manifest.hash)?;
+        if expected != manifest_hash {
+            anyhow::bail!(
+                "Data hash {data_hash} does not match manifest hash {}",
+                manifest.hash
+            );
+        }
+        let members = key_manifest.public_keys();
+        let signatures: Vec<_> = manifest
+            .signatures
+            .iter()
+            .map(|signature| {
+                json!({
+                    "address": signature.address().to_string(),
+                    "signed": signature.is_signed(),
+                    "member": members.contains(signature.address()),
+                })
+            })
+            .collect();
+        let json = json!({
+            "hash": {
+                "serial": manifest.serial,
+                "hash": manifest.hash,
+                "verified": true,
+            },
+            "public_key": key_manifest.public_key()?,
+            "signatures": signatures,
+            "note": "signatures were not verified, verifying them requires the signing data",
+        });
+        print_result(&json)?;
+
+        if self.fail_on_missing {
+            let present = manifest
+                .signatures
+                .iter()
+                .filter(|signature| signature.is_signed() && members.contains(signature.address()))
+                .count();
+            if present < key_manifest.required as usize {
+                anyhow::bail!(
+                    "Only {present} of {} required signatures present",
+                    key_manifest.required
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How a manifest signature verifies against the current public key. A
+/// signature from a key that is not a member, for example after a key
+/// rotation, is an unknown signer whether or not it verifies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SignatureStatus {
+    Valid,
+    Invalid,
+    Unsigned,
+    UnknownSigner,
+}
+
+impl SignatureStatus {
+    fn new(member: bool, verify: &ManifestSignatureVerify) -> Self {
+        match (member, verify.is_signed(), verify.is_verified()) {
+            (false, true, _) => Self::UnknownSigner,
+            (_, false, _) => Self::Unsigned,
+            (true, true, true) => Self::Valid,
+            (true, true, false) => Self::Invalid,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Valid => "valid",
+            Self::Invalid => "invalid",
+            Self::Unsigned => "unsigned",
+            Self::UnknownSigner => "unknown_signer",
+        }
+    }
+
+    fn outcome(&self) -> junit::Outcome {
+        match self {
+            Self::Valid => junit::Outcome::Passed,
+            Self::Invalid => junit::Outcome::Failed("signature does not verify".to_string()),
+            Self::Unsigned => junit::Outcome::Skipped("not signed".to_string()),
+            Self::UnknownSigner => {
+                junit::Outcome::Skipped("signer is not a member of the public key".to_string())
+            }
+        }
+    }
+}
+
+/// Replace the signers of a manifest with the members of a new public key
+///
+/// The serial and hash are kept, so the filter data does not need to be
+/// regenerated. All signatures are reset and need to be collected again from
+/// the new members.
+#[derive(Debug, clap::Args)]
+pub struct RotateKeys {
+    /// The manifest file to update
+    #[arg(long, short, default_value = "manifest.json")]
+    manifest: PathBuf,
+
+    /// The public key file with the new multisig members
+    #[arg(long)]
+    new_key: PathBuf,
+
+    /// The encoding to write the manifest in
+    #[arg(long, value_enum, default_value_t = ManifestFormat::Json)]
+    format: ManifestFormat,
+
+    /// Passphrase to encrypt or decrypt the manifest file with
+    #[arg(long, env = "XORF_MANIFEST_PASSPHRASE", hide_env_values = true)]
+    passphrase: Option<String>,
+}
+
+impl RotateKeys {
+    pub fn run(&self) -> Result<()> {
+        let mut manifest = read_manifest(&self.manifest, self.passphrase.as_deref())?;
+        let key_manifest = PublicKeyManifest::from_path(&self.new_key)
+            .context(format!("reading public key {}", self.new_key.display()))?;
+        manifest.signatures = key_manifest.signature_slots();
+
+        let mut manifest_file = AtomicFile::create(&self.manifest, false)?;
+        write_manifest(
+            &mut manifest_file,
+            &manifest,
+            self.passphrase.as_deref(),
+            self.format,
+        )?;
+        manifest_file.commit()?;
+
+        let json = json!({
+            "serial": manifest.serial,
+            "hash": manifest.hash,
+            "public_key": key_manifest.public_key()?,
+            "signers": manifest.signatures.len(),
         });
-        print_json(&json)
+        print_result(&json)
+    }
+}
+
+/// Add a signature made outside of this tool to a manifest
+///
+/// For air-gapped signers that receive the signing data, sign it with their
+/// own hardware and hand back the raw signature. The signature is verified
+/// against the signing data before it is added to the slot of the signer.
+///
+/// A member holding their keypair file can sign with `--keypair` instead.
+/// Coordinators holding some of the member keys can pass `--keys-dir` to sign
+/// every empty slot those keys match, leaving the rest for the other members.
+/// Keypair files are read into buffers that are zeroed once the keys are
+/// parsed, and the parsed keys are dropped right after signing.
+///
+/// A slot that already holds a signature is only replaced with `--force`.
+#[derive(Debug, clap::Args)]
+pub struct Sign {
+    /// The manifest file to add the signature to
+    #[arg(long, short, default_value = "manifest.json")]
+    manifest: PathBuf,
+
+    /// The signing data the signature was made over
+    #[arg(long, short, default_value = "data.bin")]
+    data: PathBuf,
+
+    /// The public key of the member that made the signature
+    #[arg(
+        long,
+        requires = "signature_file",
+        required_unless_present_any = ["keys_dir", "keypair"]
+    )]
+    signer: Option<PublicKey>,
+
+    /// The file with the raw signature bytes
+    #[arg(long, requires = "signer")]
+    signature_file: Option<PathBuf>,
+
+    /// Directory of unencrypted keypair files to sign every slot they match with
+    #[arg(long, conflicts_with_all = ["signer", "signature_file"])]
+    keys_dir: Option<PathBuf>,
+
+    /// An unencrypted keypair file of one member to sign its slot with
+    #[arg(long, conflicts_with_all = ["signer", "signature_file", "keys_dir"])]
+    keypair: Option<PathBuf>,
+
+    /// Replace the signature of a slot that is already signed
+    #[arg(long)]
+    force: bool,
+
+    /// RFC3339 time the signature was made, recorded as audit metadata
+    #[arg(long)]
+    signed_at: Option<String>,
+
+    /// The encoding to write the manifest in
+    #[arg(long, value_enum, default_value_t = ManifestFormat::Json)]
+    format: ManifestFormat,
+
+    /// Passphrase to encrypt or decrypt the manifest file with
+    #[arg(long, env = "XORF_MANIFEST_PASSPHRASE", hide_env_values = true)]
+    passphrase: Option<String>,
+
+    /// Seconds to wait for another signer to finish updating the manifest
+    #[arg(long, default_value_t = 30)]
+    lock_timeout: u64,
+}
+
+impl Sign {
+    pub fn run(&self) -> Result<()> {
+        // held until the updated manifest is in place, so signatures added
+        // concurrently are never lost
+        let _lock = FileLock::acquire(&self.manifest, Duration::from_secs(self.lock_timeout))?;
+        let mut manifest = read_manifest(&self.manifest, self.passphrase.as_deref())?;
+        let filter = Filter::from_signing_path(&self.data, FILTTER_VERSION)
+            .context(format!("reading filter {}", self.data.display()))?;
+        let hash = base64_serde::encode(&filter.hash()?);
+        if hash != manifest.hash {
+            anyhow::bail!(
+                "Signing data hash {hash} does not match manifest hash {}",
+                manifest.hash
+            );
+        }
+        let signing_bytes = filter.to_signing_bytes()?;
+        let signed = match (
+            &self.keys_dir,
+            &self.keypair,
+            &self.signer,
+            &self.signature_file,
+        ) {
+            (Some(keys_dir), _, _, _) => {
+                let keys = read_keys_dir(keys_dir)?;
+                let filled = manifest.sign_with(&signing_bytes, &keys)?;
+                let count = keys.len();
+                // the keys are not needed past signing, so they are not kept
+                // around while the manifest is written
+                drop(keys);
+                json!({ "keys": count, "filled": filled })
+            }
+            (None, Some(keypair), _, _) => {
+                let keypair = read_keypair(keypair)?;
+                let signer = keypair.public_key().clone();
+                self.check_slot(&manifest, &signer)?;
+                let signature = keypair.sign(&signing_bytes)?;
+                drop(keypair);
+                manifest.add_signature(
+                    &signer,
+                    signature,
+                    &signing_bytes,
+                    self.signed_at.clone(),
+                )?;
+                json!({ "signer": signer.to_string() })
+            }
+            (None, None, Some(signer), Some(signature_file)) => {
+                self.check_slot(&manifest, signer)?;
+                let signature = fs::read(signature_file)
+                    .context(format!("reading signature {}", signature_file.display()))?;
+                manifest.add_signature(
+                    signer,
+                    signature,
+                    &signing_bytes,
+                    self.signed_at.clone(),
+                )?;
+                json!({ "signer": signer.to_string() })
+            }
+            _ => anyhow::bail!(
+                "either --keys-dir, --keypair or --signer with --signature-file is required"
+            ),
+        };
+
+        let mut manifest_file = AtomicFile::create(&self.manifest, false)?;
+        write_manifest(
+            &mut manifest_file,
+            &manifest,
+            self.passphrase.as_deref(),
+            self.format,
+        )?;
+        manifest_file.commit()?;
+
+        let mut json = json!({
+            "serial": manifest.serial,
+            "signatures": manifest
+                .signatures
+                .iter()
+                .filter(|signature| signature.is_signed())
+                .count(),
+        });
+        if let (Some(json), Some(signed)) = (json.as_object_mut(), signed.as_object()) {
+            json.extend(signed.clone());
+        }
+        print_result(&json)
+    }
+
+    /// Fails unless the signer has a slot in the manifest that is empty, or
+    /// may be replaced with --force
+    fn check_slot(&self, manifest: &Manifest, signer: &PublicKey) -> Result<()> {
+        let Some(slot) = manifest
+            .signatures
+            .iter()
+            .find(|signature| &signature.address.0 == signer)
+        else {
+            anyhow::bail!(
+                "{signer} is not an expected signer of {}",
+                self.manifest.display()
+            );
+        };
+        if slot.is_signed() && !self.force {
+            anyhow::bail!("{signer} has already signed, use --force to replace the signature");
+        }
+        Ok(())
+    }
+}
+
+/// Report the chunks of the signing data that changed between two manifests
+///
+/// Both manifests must have been generated with the same `--chunk-size`. The
+/// chunk tree of each is checked against its root, and the index and hashes
+/// of every chunk that differs are reported, so an incremental update can be
+/// checked to touch only those chunks.
+#[derive(Debug, clap::Args)]
+pub struct DiffProof {
+    /// The manifest of the older serial
+    old: PathBuf,
+    /// The manifest of the newer serial
+    new: PathBuf,
+    /// Passphrase to decrypt the manifest files with
+    #[arg(long, env = "XORF_MANIFEST_PASSPHRASE", hide_env_values = true)]
+    passphrase: Option<String>,
+}
+
+impl DiffProof {
+    pub fn run(&self) -> Result<()> {
+        let old = read_manifest(&self.old, self.passphrase.as_deref())?;
+        let new = read_manifest(&self.new, self.passphrase.as_deref())?;
+        let chunk_tree = |manifest: &Manifest, path: &Path| -> Result<ChunkTree> {
+            let Some(tree) = manifest.chunk_tree.clone() else {
+                anyhow::bail!("manifest {} has no chunk tree", path.display());
+            };
+            tree.verify()
+                .context(format!("checking chunk tree of {}", path.display()))?;
+            Ok(tree)
+        };
+        let old_tree = chunk_tree(&old, &self.old)?;
+        let new_tree = chunk_tree(&new, &self.new)?;
+        let changed = old_tree.diff(&new_tree)?;
+        let json = json!({
+            "chunk_size": old_tree.chunk_size,
+            "old": {
+                "serial": old.serial,
+                "root": old_tree.root,
+                "chunks": old_tree.leaves.len(),
+            },
+            "new": {
+                "serial": new.serial,
+                "root": new_tree.root,
+                "chunks": new_tree.leaves.len(),
+            },
+            "changed": changed,
+        });
+        print_result(&json)
+    }
+}
+
+/// Lists paths for error messages
+fn display_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Reads every file in the given directory as a binary keypair. The raw key
+/// bytes are zeroed once they have been parsed.
+fn read_keys_dir(dir: &Path) -> Result<Vec<Keypair>> {
+    let mut paths = fs::read_dir(dir)
+        .context(format!("reading keys directory {}", dir.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    paths.retain(|path| path.is_file());
+    paths.sort();
+    paths.iter().map(|path| read_keypair(path)).collect()
+}
+
+fn read_keypair(path: &Path) -> Result<Keypair> {
+    let bytes = read_keypair_bytes(path)?;
+    Keypair::try_from(&bytes[..]).context(format!("decoding keypair {}", path.display()))
+}
+
+/// Reads the raw bytes of a keypair file into a buffer that is zeroed when
+/// dropped
+fn read_keypair_bytes(path: &Path) -> Result<Zeroizing<Vec<u8>>> {
+    Ok(Zeroizing::new(
+        fs::read(path).context(format!("reading keypair {}", path.display()))?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::test_util::{
+        arg, capture_results, descriptor, filter, keypair, parse, test_dir, write_data,
+        write_descriptor, write_key_manifest, write_signed_manifest,
+    };
+    use xorf_generator::EmbeddedManifest;
+
+    #[test]
+    fn keypair_bytes_are_zeroizing() {
+        let keypair = keypair();
+        let path = test_dir("keypair-bytes").join("keypair.bin");
+        fs::write(&path, keypair.to_vec()).unwrap();
+        let bytes: Zeroizing<Vec<u8>> = read_keypair_bytes(&path).unwrap();
+        assert_eq!(bytes.as_slice(), keypair.to_vec().as_slice());
+        let decoded = Keypair::try_from(&bytes[..]).unwrap();
+        assert_eq!(decoded.public_key(), keypair.public_key());
+    }
+
+    #[test]
+    fn rotate_keys_preserves_the_hash() {
+        let dir = test_dir("rotate-keys");
+        let old_key = dir.join("old_key.json");
+        let new_key = dir.join("new_key.json");
+        write_key_manifest(&old_key, 3, 2);
+        let new_members = write_key_manifest(&new_key, 2, 1);
+        let manifest = Manifest {
+            serial: 7,
+            hash: base64_serde::encode(&[7; 32]),
+            signatures: PublicKeyManifest::from_path(&old_key)
+                .unwrap()
+                .signature_slots(),
+            entries_hash: Some(base64_serde::encode(&[9; 32])),
+            sig_scheme: SIG_SCHEME_MULTISIG,
+            chunk_tree: None,
+        };
+        let path = dir.join("manifest.json");
+        fs::write(&path, serde_json::to_vec(&manifest).unwrap()).unwrap();
+
+        RotateKeys {
+            manifest: path.clone(),
+            new_key,
+            format: ManifestFormat::Json,
+            passphrase: None,
+        }
+        .run()
+        .unwrap();
+
+        let rotated = Manifest::from_path(&path).unwrap();
+        assert_eq!(rotated.serial, manifest.serial);
+        assert_eq!(rotated.hash, manifest.hash);
+        assert_eq!(rotated.entries_hash, manifest.entries_hash);
+        let mut members: Vec<String> = new_members
+            .iter()
+            .map(|keypair| keypair.public_key().to_string())
+            .collect();
+        members.sort();
+        let slots: Vec<String> = rotated
+            .signatures
+            .iter()
+            .map(|signature| signature.address().to_string())
+            .collect();
+        assert_eq!(slots, members);
+        assert!(rotated.signatures.iter().all(|slot| !slot.is_signed()));
+    }
+
+    #[test]
+    fn batch_index_matches_generated_manifests() {
+        let dir = test_dir("batch-index");
+        let key = dir.join("public_key.json");
+        write_key_manifest(&key, 2, 1);
+        let data: Vec<PathBuf> = (1..=3)
+            .map(|serial| {
+                let path = dir.join(format!("filter-{serial}.bin"));
+                write_data(&path, serial);
+                path
+            })
+            .collect();
+        let mut args = vec!["--key", arg(&key), "--output-dir", arg(&dir)];
+        args.extend(data.iter().map(|path| arg(path)));
+        parse::<Batch>(&args).run().unwrap();
+
+        let index: serde_json::Value =
+            serde_json::from_slice(&fs::read(dir.join("index.json")).unwrap()).unwrap();
+        assert_eq!(index.as_object().unwrap().len(), data.len());
+        for path in &data {
+            let filter = Filter::from_signing_path(path, FILTTER_VERSION).unwrap();
+            let hash = base64_serde::encode(&filter.hash().unwrap());
+            let entry = &index[filter.serial.to_string()];
+            assert_eq!(entry["hash"], hash);
+            assert_eq!(entry["data"], arg(path));
+            let manifest = Manifest::from_path(entry["manifest"].as_str().unwrap()).unwrap();
+            assert_eq!(manifest.serial, filter.serial);
+            assert_eq!(manifest.hash, hash);
+        }
+    }
+
+    #[test]
+    fn verify_checks_the_descriptor_entries_hash() {
+        let dir = test_dir("verify-entries-hash");
+        let key = dir.join("public_key.json");
+        let keypairs = write_key_manifest(&key, 2, 1);
+        let signed = descriptor(100, 20);
+        let options = FilterOptions {
+            entries_hash: true,
+            ..Default::default()
+        };
+        let filter = Filter::from_descriptor(3, &signed, &options).unwrap();
+        let data = dir.join("data.bin");
+        fs::write(&data, filter.to_signing_bytes().unwrap()).unwrap();
+        let manifest = dir.join("manifest.json");
+        write_signed_manifest(&manifest, &filter, &key, &keypairs);
+
+        let verify = |descriptor: &Path| {
+            parse::<Verify>(&[
+                "--data",
+                arg(&data),
+                "--key",
+                arg(&key),
+                "--manifest",
+                arg(&manifest),
+                "--descriptor",
+                arg(descriptor),
+            ])
+            .run()
+        };
+        let matching = dir.join("matching.bin");
+        write_descriptor(&matching, &signed);
+        verify(&matching).unwrap();
+        let mismatching = dir.join("mismatching.bin");
+        write_descriptor(&mismatching, &descriptor(100, 20));
+        let err = verify(&mismatching).unwrap_err();
+        assert!(err.to_string().contains("Descriptor entries do not match"));
+    }
+
+    #[test]
+    fn serial_rewrites_the_data_file_without_force() {
+        let dir = test_dir("generate-serial");
+        let key = dir.join("public_key.json");
+        write_key_manifest(&key, 2, 1);
+        let data = dir.join("data.bin");
+        write_data(&data, 7);
+        let manifest = dir.join("manifest.json");
+
+        parse::<Generate>(&[
+            "--data",
+            arg(&data),
+            "--key",
+            arg(&key),
+            "--manifest",
+            arg(&manifest),
+            "--serial",
+            "8",
+        ])
+        .run()
+        .unwrap();
+
+        let rewritten = Filter::from_signing_path(&data, FILTTER_VERSION).unwrap();
+        assert_eq!(rewritten.serial, 8);
+        let generated = Manifest::from_path(&manifest).unwrap();
+        assert_eq!(generated.serial, 8);
+        assert_eq!(
+            generated.hash,
+            base64_serde::encode(&rewritten.hash().unwrap())
+        );
+    }
+
+    #[test]
+    fn failed_generate_leaves_no_partial_outputs() {
+        let dir = test_dir("generate-partial");
+        let key = dir.join("public_key.json");
+        write_key_manifest(&key, 2, 1);
+        let data = dir.join("data.bin");
+        write_data(&data, 7);
+        let original = fs::read(&data).unwrap();
+        let signing_bytes = dir.join("signing_bytes.bin");
+        let manifest = dir.join("manifest.json");
+        let wrong_hash = base64_serde::encode(&[0; 32]);
+        let generate = |keep_partial: bool| {
+            let mut args = vec![
+                "--data",
+                arg(&data),
+                "--key",
+                arg(&key),
+                "--manifest",
+                arg(&manifest),
+                "--serial",
+                "8",
+                "--signing-bytes-out",
+                arg(&signing_bytes),
+                "--compat-hash",
+                wrong_hash.as_str(),
+                "--quiet",
+            ];
+            if keep_partial {
+                args.push("--keep-partial");
+            }
+            parse::<Generate>(&args).run()
+        };
+
+        // the data and signing bytes are written before the compat hash fails
+        let err = generate(false).unwrap_err();
+        assert!(err.to_string().contains("does not match compat hash"));
+        assert_eq!(fs::read(&data).unwrap(), original);
+        assert!(!signing_bytes.exists());
+        assert!(!manifest.exists());
+        let mut files: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["data.bin", "public_key.json"]);
+
+        generate(true).unwrap_err();
+        assert_ne!(fs::read(&data).unwrap(), original);
+        assert!(signing_bytes.exists());
+        assert!(!manifest.exists());
+    }
+
+    #[test]
+    fn resumed_batch_skips_completed_manifests() {
+        let dir = test_dir("batch-resume");
+        let key = dir.join("public_key.json");
+        write_key_manifest(&key, 2, 1);
+        let data: Vec<PathBuf> = (1..=3)
+            .map(|serial| dir.join(format!("filter-{serial}.bin")))
+            .collect();
+        write_data(&data[0], 1);
+        write_data(&data[1], 2);
+        // the batch is interrupted by the unreadable third data file
+        fs::write(&data[2], b"not signing data").unwrap();
+        let state = dir.join("state.json");
+        let mut args = vec![
+            "--key",
+            arg(&key),
+            "--output-dir",
+            arg(&dir),
+            "--state-file",
+            arg(&state),
+        ];
+        args.extend(data.iter().map(|path| arg(path)));
+        assert!(parse::<Batch>(&args).run().is_err());
+        assert!(state.exists());
+        let first = dir.join("filter-1.manifest.json");
+        let written = fs::read(&first).unwrap();
+
+        // without --force the completed manifests could not be written again
+        write_data(&data[2], 3);
+        parse::<Batch>(&args).run().unwrap();
+        assert_eq!(fs::read(&first).unwrap(), written);
+        assert!(dir.join("filter-3.manifest.json").exists());
+        assert!(!state.exists());
+        let index: serde_json::Value =
+            serde_json::from_slice(&fs::read(dir.join("index.json")).unwrap()).unwrap();
+        assert_eq!(index.as_object().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn junit_report_parses() {
+        let dir = test_dir("verify-junit");
+        let key = dir.join("public_key.json");
+        let keypairs = write_key_manifest(&key, 2, 2);
+        let data = dir.join("data.bin");
+        let filter = write_data(&data, 3);
+        let manifest = dir.join("manifest.json");
+        write_signed_manifest(&manifest, &filter, &key, &keypairs[..1]);
+        let report = dir.join("report.xml");
+        parse::<Verify>(&[
+            "--data",
+            arg(&data),
+            "--key",
+            arg(&key),
+            "--manifest",
+            arg(&manifest),
+            "--output-junit",
+            arg(&report),
+        ])
+        .run()
+        .unwrap();
+
+        let xml = fs::read_to_string(&report).unwrap();
+        let document = roxmltree::Document::parse(&xml).unwrap();
+        let suite = document
+            .descendants()
+            .find(|node| node.has_tag_name("testsuite"))
+            .unwrap();
+        assert_eq!(suite.attribute("tests"), Some("4"));
+        assert_eq!(suite.attribute("failures"), Some("1"));
+        assert_eq!(suite.attribute("skipped"), Some("1"));
+        // the element a case holds, if any, is its failure or skip
+        let outcome = |name: &str| {
+            let case = suite
+                .children()
+                .find(|node| node.attribute("name") == Some(name))
+                .unwrap();
+            case.first_element_child()
+                .map(|outcome| outcome.tag_name().name().to_string())
+        };
+        let signature = |keypair: &Keypair| format!("signature {}", keypair.public_key());
+        assert_eq!(outcome("hash"), None);
+        assert_eq!(outcome(&signature(&keypairs[0])), None);
+        assert_eq!(
+            outcome(&signature(&keypairs[1])).as_deref(),
+            Some("skipped")
+        );
+        assert_eq!(outcome("threshold").as_deref(), Some("failure"));
+    }
+
+    #[test]
+    fn signers_from_filter_round_trip() {
+        let dir = test_dir("signers-from-filter");
+        let key = dir.join("public_key.json");
+        let keypairs = write_key_manifest(&key, 3, 2);
+        let key_manifest = PublicKeyManifest::from_path(&key).unwrap();
+        let mut previous = filter(1);
+        let mut manifest = Manifest::from_filter(&previous, &key_manifest).unwrap();
+        manifest
+            .sign_with(&previous.to_signing_bytes().unwrap(), &keypairs[..2])
+            .unwrap();
+        previous.apply_manifest(&manifest, &key_manifest).unwrap();
+        previous.embedded = Some(EmbeddedManifest {
+            manifest,
+            public_key: PublicKeyManifest::from_path(&key).unwrap(),
+        });
+        let previous_path = dir.join("previous.bin");
+        fs::write(&previous_path, previous.to_bytes().unwrap()).unwrap();
+        let data = dir.join("data.bin");
+        let mut next = write_data(&data, 2);
+        let manifest = dir.join("manifest.json");
+
+        parse::<Generate>(&[
+            "--data",
+            arg(&data),
+            "--signers-from-filter",
+            arg(&previous_path),
+            "--manifest",
+            arg(&manifest),
+            "--quiet",
+        ])
+        .run()
+        .unwrap();
+
+        let mut generated = Manifest::from_path(&manifest).unwrap();
+        let mut slots: Vec<String> = generated
+            .signatures
+            .iter()
+            .map(|signature| signature.address().to_string())
+            .collect();
+        slots.sort();
+        let mut members: Vec<String> = keypairs
+            .iter()
+            .map(|keypair| keypair.public_key().to_string())
+            .collect();
+        members.sort();
+        assert_eq!(slots, members);
+        // the same members and threshold sign the next filter
+        generated
+            .sign_with(&next.to_signing_bytes().unwrap(), &keypairs[1..])
+            .unwrap();
+        next.apply_manifest(&generated, &key_manifest).unwrap();
+        next.verify(&key_manifest.public_key().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn concurrent_signs_all_land() {
+        let dir = test_dir("concurrent-sign");
+        let key = dir.join("public_key.json");
+        let keypairs = write_key_manifest(&key, 4, 4);
+        let data = dir.join("data.bin");
+        let filter = write_data(&data, 6);
+        let manifest = dir.join("manifest.json");
+        write_signed_manifest(&manifest, &filter, &key, &[]);
+        let keypair_paths: Vec<PathBuf> = keypairs
+            .iter()
+            .enumerate()
+            .map(|(index, keypair)| {
+                let path = dir.join(format!("keypair-{index}.bin"));
+                fs::write(&path, keypair.to_vec()).unwrap();
+                path
+            })
+            .collect();
+
+        std::thread::scope(|scope| {
+            for keypair in &keypair_paths {
+                let (data, manifest) = (&data, &manifest);
+                scope.spawn(move || {
+                    parse::<Sign>(&[
+                        "--manifest",
+                        arg(manifest),
+                        "--data",
+                        arg(data),
+                        "--keypair",
+                        arg(keypair),
+                    ])
+                    .run()
+                    .unwrap();
+                });
+            }
+        });
+
+        let signed = Manifest::from_path(&manifest).unwrap();
+        assert!(signed.signatures.iter().all(|slot| slot.is_signed()));
+        let key_manifest = PublicKeyManifest::from_path(&key).unwrap();
+        assert_eq!(signed.signers(&filter, &key_manifest).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn signatures_from_removed_members_are_unknown_signers() {
+        let dir = test_dir("unknown-signer");
+        let key = dir.join("public_key.json");
+        let keypairs = write_key_manifest(&key, 3, 2);
+        let data = dir.join("data.bin");
+        let filter = write_data(&data, 9);
+        let manifest = dir.join("manifest.json");
+        write_signed_manifest(&manifest, &filter, &key, &keypairs);
+        // the last member is removed from the public key after signing
+        let rotated = PublicKeyManifest::new(
+            keypairs[..2]
+                .iter()
+                .map(|keypair| keypair.public_key().clone())
+                .collect(),
+            2,
+        );
+        fs::write(&key, serde_json::to_vec(&rotated).unwrap()).unwrap();
+
+        let (result, printed) = capture_results(|| {
+            parse::<Verify>(&[
+                "--data",
+                arg(&data),
+                "--key",
+                arg(&key),
+                "--manifest",
+                arg(&manifest),
+                "--fail-on-invalid",
+                "--fail-below-threshold",
+            ])
+            .run()
+        });
+        result.unwrap();
+        let removed = keypairs[2].public_key().to_string();
+        let signatures = printed[0]["signatures"].as_array().unwrap();
+        assert_eq!(signatures.len(), 3);
+        for signature in signatures {
+            let expected = match signature["address"].as_str() {
+                Some(address) if address == removed => "unknown_signer",
+                _ => "valid",
+            };
+            assert_eq!(signature["status"], expected);
+        }
+        assert_eq!(printed[0]["threshold"]["valid_count"], 2);
     }
 }
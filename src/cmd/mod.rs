@@ -1,23 +1,777 @@
-use std::{fs, path::Path};
+use fs2::FileExt;
+use helium_crypto::PublicKey;
+use std::{
+    fs,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use xorf_generator::{Manifest, PublicKeyManifest};
 
+pub mod config;
 pub mod data;
 pub mod descriptor;
+pub mod examples;
 pub mod filter;
+pub mod junit;
 pub mod key;
 pub mod manifest;
+pub mod selftest;
+#[cfg(test)]
+pub mod test_util;
+#[cfg(feature = "schema")]
+pub mod validate;
 
-pub fn print_json<T: ?Sized + serde::Serialize>(value: &T) -> anyhow::Result<()> {
-    println!("{}", serde_json::to_string_pretty(value)?);
+/// The file that everything printed with [`print_result`] is also written to
+static JSON_OUTPUT: OnceLock<Mutex<fs::File>> = OnceLock::new();
+
+/// Creates the given file and writes everything later printed with
+/// [`print_result`] to it as well
+pub fn tee_json_output(path: &Path) -> anyhow::Result<()> {
+    let file = open_output_file(path, false)?;
+    if JSON_OUTPUT.set(Mutex::new(file)).is_err() {
+        anyhow::bail!("json output file already set");
+    }
+    Ok(())
+}
+
+/// Held while a document or progress event is written, so output from
+/// parallel workers is never interleaved and the json output file sees the
+/// documents in the same order as stdout
+static OUTPUT_LOCK: Mutex<()> = Mutex::new(());
+
+fn output_lock() -> std::sync::MutexGuard<'static, ()> {
+    OUTPUT_LOCK.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Set once an output file is written to stdout, after which json output
+/// moves to stderr so it does not mix with the file content
+static STDOUT_TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Whether the path stands for stdin or stdout
+pub fn is_stdio(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
+
+/// The format results are printed in by [`print_result`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Json,
+    Yaml,
+}
+
+static OUTPUT_FORMAT: OnceLock<OutputFormat> = OnceLock::new();
+
+/// Sets the format of everything printed with [`print_result`] afterwards
+pub fn set_output_format(format: OutputFormat) -> anyhow::Result<()> {
+    if OUTPUT_FORMAT.set(format).is_err() {
+        anyhow::bail!("output format already set");
+    }
+    Ok(())
+}
+
+/// Prints a command result in the selected output format. Yaml results are
+/// printed as separate documents, so commands that print several results
+/// still produce a valid stream. The json output file always gets json.
+pub fn print_result<T: ?Sized + serde::Serialize>(value: &T) -> anyhow::Result<()> {
+    let (text, json) = format_result(value, output_format(), false)?;
+    write_result(&text, &json)
+}
+
+/// Prints a result as a single line of json, for commands that stream one
+/// result per entry. Like [`print_result`] it honors the output format, with
+/// yaml results printed as separate documents, and the json output file.
+pub fn print_result_line<T: ?Sized + serde::Serialize>(value: &T) -> anyhow::Result<()> {
+    let (text, json) = format_result(value, output_format(), true)?;
+    write_result(&text, &json)
+}
+
+fn output_format() -> OutputFormat {
+    OUTPUT_FORMAT.get().copied().unwrap_or_default()
+}
+
+/// Formats a result for stdout in the given format, and as json for the json
+/// output file. Compact results are json on a single line.
+fn format_result<T: ?Sized + serde::Serialize>(
+    value: &T,
+    format: OutputFormat,
+    compact: bool,
+) -> anyhow::Result<(String, String)> {
+    let json = if compact {
+        serde_json::to_string(value)?
+    } else {
+        serde_json::to_string_pretty(value)?
+    };
+    let text = match format {
+        OutputFormat::Json => json.clone(),
+        OutputFormat::Yaml => format!("---\n{}", serde_yaml::to_string(value)?.trim_end()),
+    };
+    Ok((text, json))
+}
+
+#[cfg(test)]
+thread_local! {
+    /// The json of the results printed on this thread, while captured by
+    /// [`test_util::capture_results`]
+    static CAPTURED_RESULTS: std::cell::RefCell<Option<Vec<String>>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+fn write_result(text: &str, json: &str) -> anyhow::Result<()> {
+    #[cfg(test)]
+    CAPTURED_RESULTS.with_borrow_mut(|captured| {
+        if let Some(captured) = captured {
+            captured.push(json.to_string());
+        }
+    });
+    let json_output = JSON_OUTPUT.get();
+    if STDOUT_TAKEN.load(Ordering::Relaxed) {
+        write_result_to(&OUTPUT_LOCK, &mut io::stderr(), json_output, text, json)
+    } else {
+        write_result_to(&OUTPUT_LOCK, &mut io::stdout(), json_output, text, json)
+    }
+}
+
+/// Writes a result to the output, and its json to the json output if any,
+/// while holding the lock, so results written from parallel workers are
+/// never interleaved
+fn write_result_to<J: Write>(
+    lock: &Mutex<()>,
+    output: &mut impl Write,
+    json_output: Option<&Mutex<J>>,
+    text: &str,
+    json: &str,
+) -> anyhow::Result<()> {
+    let _guard = lock.lock().unwrap_or_else(|err| err.into_inner());
+    writeln!(output, "{text}")?;
+    if let Some(json_output) = json_output {
+        let mut json_output = json_output.lock().unwrap_or_else(|err| err.into_inner());
+        writeln!(json_output, "{json}")?;
+    }
+    Ok(())
+}
+
+/// The file mode applied to every output file, if set
+static OUTPUT_PERMISSIONS: OnceLock<u32> = OnceLock::new();
+
+/// Sets the file mode of all output files opened afterwards. Only honored on
+/// unix, elsewhere a warning is printed and the mode is ignored.
+pub fn set_output_permissions(mode: u32) -> anyhow::Result<()> {
+    if !cfg!(unix) {
+        eprintln!("warning: --output-permissions is ignored on this platform");
+        return Ok(());
+    }
+    if OUTPUT_PERMISSIONS.set(mode).is_err() {
+        anyhow::bail!("output permissions already set");
+    }
     Ok(())
 }
 
+/// Parses an octal file mode like "640" or "0o640"
+pub fn parse_permissions(value: &str) -> Result<u32, String> {
+    let digits = value.strip_prefix("0o").unwrap_or(value);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!("invalid octal file mode \"{value}\"")),
+    }
+}
+
+/// Reports a failed download that is about to be retried on stderr
+#[cfg(feature = "remote")]
+pub fn log_retry(url: &str, attempt: u32, err: &io::Error, delay: Duration) {
+    eprintln!(
+        "attempt {attempt} to fetch {url} failed: {err}, retrying in {:.1}s",
+        delay.as_secs_f64()
+    );
+}
+
+/// Parses a hex encoded sha256 checksum
+#[cfg(feature = "remote")]
+pub fn parse_sha256(value: &str) -> Result<[u8; 32], String> {
+    let invalid = || format!("invalid sha256 checksum \"{value}\", expected 64 hex digits");
+    if value.len() != 64 || !value.is_ascii() {
+        return Err(invalid());
+    }
+    let mut digest = [0u8; 32];
+    for (byte, chunk) in digest.iter_mut().zip(value.as_bytes().chunks(2)) {
+        let digits = std::str::from_utf8(chunk).map_err(|_| invalid())?;
+        *byte = u8::from_str_radix(digits, 16).map_err(|_| invalid())?;
+    }
+    Ok(digest)
+}
+
 pub fn open_output_file(filename: &Path, create_new: bool) -> anyhow::Result<fs::File> {
+    open_output_file_with_mode(filename, create_new, OUTPUT_PERMISSIONS.get().copied())
+}
+
+/// Opens an output file like [`open_output_file`], with the given file mode
+/// rather than the one set by [`set_output_permissions`]
+fn open_output_file_with_mode(
+    filename: &Path,
+    create_new: bool,
+    mode: Option<u32>,
+) -> anyhow::Result<fs::File> {
     use anyhow::Context;
-    fs::OpenOptions::new()
+    let mut options = fs::OpenOptions::new();
+    options
         .write(true)
         .create(true)
         .create_new(create_new)
-        .truncate(true)
-        .open(filename)
-        .context(format!("opening output file {}", filename.display()))
+        .truncate(true);
+    // new files are created with the mode, so they are never readable with
+    // wider permissions, not even briefly
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    let file = match options.open(filename) {
+        Err(err) if create_new && err.kind() == io::ErrorKind::AlreadyExists => {
+            return Err(refuse_overwrite(filename))
+        }
+        result => result.context(format!("opening output file {}", filename.display()))?,
+    };
+    // the mode is also set on existing files, and on new files since the
+    // creation mode is masked by the umask
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(mode))
+            .context(format!("setting permissions of {}", filename.display()))?;
+    }
+    Ok(file)
+}
+
+/// Opens an output file like [`open_output_file`], or stdout for a path of
+/// `-`, which is never refused as existing
+pub fn open_output(filename: &Path, create_new: bool) -> anyhow::Result<Box<dyn Write>> {
+    if is_stdio(filename) {
+        STDOUT_TAKEN.store(true, Ordering::Relaxed);
+        return Ok(Box::new(io::stdout()));
+    }
+    Ok(Box::new(open_output_file(filename, create_new)?))
+}
+
+/// The error for an existing output file that is only replaced with --force
+fn refuse_overwrite(filename: &Path) -> anyhow::Error {
+    anyhow::anyhow!("refusing to overwrite {}; pass --force", filename.display())
+}
+
+/// Reads a manifest, decrypting it first when a passphrase is given
+pub fn read_manifest(path: &Path, passphrase: Option<&str>) -> anyhow::Result<Manifest> {
+    use anyhow::Context;
+    let manifest = match passphrase {
+        None => Manifest::from_path(path),
+        #[cfg(feature = "encryption")]
+        Some(passphrase) => Manifest::from_encrypted_path(path, passphrase),
+        #[cfg(not(feature = "encryption"))]
+        Some(_) => anyhow::bail!("manifest encryption requires the encryption feature"),
+    };
+    manifest.context(format!("reading manifest {}", path.display()))
+}
+
+/// The encodings a manifest can be written in
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum ManifestFormat {
+    Json,
+    Cbor,
+}
+
+/// Writes a manifest in the given format, encrypting it when a passphrase is
+/// given. Encrypted manifests are always json.
+pub fn write_manifest<W: Write>(
+    writer: W,
+    manifest: &Manifest,
+    passphrase: Option<&str>,
+    format: ManifestFormat,
+) -> anyhow::Result<()> {
+    match (passphrase, format) {
+        (None, ManifestFormat::Json) => serde_json::to_writer_pretty(writer, manifest)?,
+        #[cfg(feature = "cbor")]
+        (None, ManifestFormat::Cbor) => {
+            let mut writer = writer;
+            writer.write_all(&manifest.to_cbor()?)?
+        }
+        #[cfg(not(feature = "cbor"))]
+        (None, ManifestFormat::Cbor) => anyhow::bail!("cbor manifests require the cbor feature"),
+        (Some(_), ManifestFormat::Cbor) => anyhow::bail!("encrypted manifests can not be cbor"),
+        #[cfg(feature = "encryption")]
+        (Some(passphrase), ManifestFormat::Json) => {
+            let mut writer = writer;
+            writer.write_all(&manifest.to_encrypted_bytes(passphrase)?)?
+        }
+        #[cfg(not(feature = "encryption"))]
+        (Some(_), ManifestFormat::Json) => {
+            anyhow::bail!("manifest encryption requires the encryption feature")
+        }
+    }
+    Ok(())
+}
+
+/// Output location arguments shared by the generate commands
+///
+/// With a --name the related files of a filter are derived as
+/// `<name>.data.bin`, `<name>.manifest.json` and `<name>.bin` in the output
+/// directory. Paths given with the individual file arguments take precedence.
+#[derive(Debug, clap::Args)]
+pub struct OutputName {
+    /// The directory to read and write the derived files in
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+    /// The base name to derive file names from
+    #[arg(long)]
+    name: Option<String>,
+}
+
+impl OutputName {
+    /// Returns the explicit path if given, the path derived from the name with
+    /// the given suffix, or the default file name.
+    pub fn path(&self, explicit: &Option<PathBuf>, suffix: &str, default: &str) -> PathBuf {
+        if let Some(path) = explicit {
+            return path.clone();
+        }
+        let file_name = match &self.name {
+            Some(name) => format!("{name}{suffix}"),
+            None => default.to_string(),
+        };
+        match &self.output_dir {
+            Some(dir) => dir.join(file_name),
+            None => PathBuf::from(file_name),
+        }
+    }
+}
+
+/// An output file that is written to a temporary sibling path and only moved
+/// into place by [`AtomicFile::commit`]. Dropping it without committing removes
+/// the temporary file, leaving any previous output untouched.
+///
+/// A path of `-` writes to stdout instead. The content is buffered and only
+/// written on commit, so a failed command writes nothing there either.
+pub struct AtomicFile {
+    path: PathBuf,
+    target: AtomicTarget,
+    committed: bool,
+}
+
+enum AtomicTarget {
+    File { tmp_path: PathBuf, file: fs::File },
+    Stdout(Vec<u8>),
+}
+
+impl AtomicFile {
+    pub fn create(filename: &Path, create_new: bool) -> anyhow::Result<Self> {
+        if is_stdio(filename) {
+            STDOUT_TAKEN.store(true, Ordering::Relaxed);
+            return Ok(Self {
+                path: filename.to_path_buf(),
+                target: AtomicTarget::Stdout(vec![]),
+                committed: false,
+            });
+        }
+        if create_new && filename.exists() {
+            return Err(refuse_overwrite(filename));
+        }
+        let mut tmp_name = filename.as_os_str().to_owned();
+        tmp_name.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_name);
+        let file = open_output_file(&tmp_path, false)?;
+        Ok(Self {
+            path: filename.to_path_buf(),
+            target: AtomicTarget::File { tmp_path, file },
+            committed: false,
+        })
+    }
+
+    pub fn commit(mut self) -> anyhow::Result<()> {
+        use anyhow::Context;
+        match &mut self.target {
+            AtomicTarget::File { tmp_path, file } => {
+                file.sync_all()?;
+                fs::rename(&tmp_path, &self.path)
+                    .context(format!("writing output file {}", self.path.display()))?;
+            }
+            AtomicTarget::Stdout(buffer) => {
+                let _guard = output_lock();
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(buffer).context("writing to stdout")?;
+                stdout.flush().context("writing to stdout")?;
+            }
+        }
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.target {
+            AtomicTarget::File { file, .. } => file.write(buf),
+            AtomicTarget::Stdout(buffer) => buffer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.target {
+            AtomicTarget::File { file, .. } => file.flush(),
+            AtomicTarget::Stdout(_) => Ok(()),
+        }
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if let (false, AtomicTarget::File { tmp_path, .. }) = (self.committed, &self.target) {
+            let _ = fs::remove_file(tmp_path);
+        }
+    }
+}
+
+/// How a batch command handles an input it can not process
+#[derive(Debug, clap::Args)]
+pub struct ErrorMode {
+    /// Stop at the first input that can not be processed. This is the default
+    #[arg(long, conflicts_with = "collect_errors")]
+    fail_fast: bool,
+    /// Report every input that can not be processed and continue, failing
+    /// once all inputs are done
+    #[arg(long)]
+    collect_errors: bool,
+}
+
+impl ErrorMode {
+    pub fn collect(&self) -> bool {
+        self.collect_errors && !self.fail_fast
+    }
+}
+
+/// Members of a multisig key given on the command line, for ad hoc checks
+/// without a public key file
+#[derive(Debug, clap::Args)]
+pub struct InlineKey {
+    /// A member public key, given once per member, instead of the public key
+    /// file
+    #[arg(long = "pubkey", requires = "threshold", conflicts_with = "key")]
+    pubkeys: Vec<PublicKey>,
+    /// The number of member signatures the inline key requires
+    #[arg(long, requires = "pubkeys")]
+    threshold: Option<u8>,
+}
+
+impl InlineKey {
+    /// Returns the inline key, if one was given
+    pub fn inline(&self) -> anyhow::Result<Option<PublicKeyManifest>> {
+        let Some(required) = self.threshold.filter(|_| !self.pubkeys.is_empty()) else {
+            return Ok(None);
+        };
+        if required == 0 || required as usize > self.pubkeys.len() {
+            anyhow::bail!(
+                "threshold {required} must be between 1 and the {} given keys",
+                self.pubkeys.len()
+            );
+        }
+        Ok(Some(PublicKeyManifest::new(self.pubkeys.clone(), required)))
+    }
+
+    /// Returns the inline key when given, and reads the given public key file
+    /// otherwise
+    pub fn key_manifest(&self, path: &Path) -> anyhow::Result<PublicKeyManifest> {
+        use anyhow::Context;
+        match self.inline()? {
+            Some(key_manifest) => Ok(key_manifest),
+            None => PublicKeyManifest::from_path(path)
+                .context(format!("reading public key {}", path.display())),
+        }
+    }
+}
+
+/// The output files of a command, moved into place together by
+/// [`Outputs::commit`] once all of them are written. When dropped before that,
+/// say on an error, none of them are left behind. With `keep_partial` every
+/// file is moved into place as soon as it is added instead.
+pub struct Outputs {
+    files: Vec<AtomicFile>,
+    keep_partial: bool,
+}
+
+impl Outputs {
+    pub fn new(keep_partial: bool) -> Self {
+        Self {
+            files: vec![],
+            keep_partial,
+        }
+    }
+
+    /// Adds a fully written output file
+    pub fn add(&mut self, file: AtomicFile) -> anyhow::Result<()> {
+        if self.keep_partial {
+            return file.commit();
+        }
+        self.files.push(file);
+        Ok(())
+    }
+
+    pub fn commit(self) -> anyhow::Result<()> {
+        for file in self.files {
+            file.commit()?;
+        }
+        Ok(())
+    }
+}
+
+/// Reports the progress of a command on stderr, either as a counter for
+/// people or as json lines of `{phase, processed, total}` events for tools
+/// that track a build. Stdout is left to the command output, and nothing is
+/// reported when quiet.
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    json: bool,
+    human: bool,
+}
+
+impl Progress {
+    /// Reports json events when requested, a counter otherwise, and nothing
+    /// when silenced by `quiet`
+    pub fn new(progress_json: bool, quiet: bool) -> Self {
+        Self {
+            json: progress_json && !quiet,
+            human: !progress_json && !quiet,
+        }
+    }
+
+    pub fn report(&self, phase: &str, processed: usize, total: usize) {
+        let _guard = output_lock();
+        let mut stderr = io::stderr().lock();
+        // progress is best effort and must not fail the command
+        if self.json {
+            let event = serde_json::json!({
+                "phase": phase,
+                "processed": processed,
+                "total": total,
+            });
+            let _ = writeln!(stderr, "{event}");
+        } else if self.human {
+            let done = processed >= total;
+            // redirected stderr only gets the finished phases, without the
+            // carriage return redraws
+            if stderr.is_terminal() {
+                let _ = write!(stderr, "\r{phase}: {processed}/{total}");
+                if done {
+                    let _ = writeln!(stderr);
+                }
+            } else if done {
+                let _ = writeln!(stderr, "{phase}: {processed}/{total}");
+            }
+        }
+    }
+
+    /// Prints a line for people following the command, unless reporting
+    /// json events or quiet
+    pub fn message(&self, message: &str) {
+        if self.human {
+            let _guard = output_lock();
+            let _ = writeln!(io::stderr().lock(), "{message}");
+        }
+    }
+}
+
+/// Formats a byte count with a binary unit, for example "1.5 MiB"
+pub fn human_size(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+/// An advisory lock on a `<path>.lock` sibling file, held until dropped. Used
+/// to serialize read-modify-write edits of a shared file between processes.
+pub struct FileLock {
+    file: fs::File,
+}
+
+impl FileLock {
+    /// Waits up to the given timeout to acquire the lock for the given path
+    pub fn acquire(filename: &Path, timeout: Duration) -> anyhow::Result<Self> {
+        let mut lock_name = filename.as_os_str().to_owned();
+        lock_name.push(".lock");
+        let lock_path = PathBuf::from(lock_name);
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&lock_path)?;
+        let started = Instant::now();
+        while file.try_lock_exclusive().is_err() {
+            if started.elapsed() >= timeout {
+                anyhow::bail!(
+                    "timed out after {}s waiting for lock {}, another process is editing {}",
+                    timeout.as_secs(),
+                    lock_path.display(),
+                    filename.display()
+                );
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        Ok(Self { file })
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{test_util::test_dir, *};
+
+    #[test]
+    fn uncommitted_atomic_file_leaves_target_untouched() {
+        let dir = test_dir("atomic-file");
+        let target = dir.join("data.bin");
+        fs::write(&target, b"previous").unwrap();
+
+        let mut file = AtomicFile::create(&target, false).unwrap();
+        file.write_all(b"partial").unwrap();
+        // a failure before commit drops the file
+        drop(file);
+
+        assert_eq!(fs::read(&target).unwrap(), b"previous");
+        assert!(!dir.join("data.bin.tmp").exists());
+
+        let mut file = AtomicFile::create(&target, false).unwrap();
+        file.write_all(b"next").unwrap();
+        file.commit().unwrap();
+        assert_eq!(fs::read(&target).unwrap(), b"next");
+        assert!(!dir.join("data.bin.tmp").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A shared output that takes its lock for every single write, like an
+    /// unlocked stdout handle, so unguarded results would interleave
+    struct SharedOutput<'a>(&'a Mutex<Vec<u8>>);
+
+    impl Write for SharedOutput<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn concurrent_results_are_not_interleaved() {
+        let lock = Mutex::new(());
+        let output = Mutex::new(vec![]);
+        let json_output = Mutex::new(vec![]);
+        thread::scope(|scope| {
+            for worker in 0..8 {
+                let (lock, output, json_output) = (&lock, &output, &json_output);
+                scope.spawn(move || {
+                    for result in 0..100 {
+                        let value = serde_json::json!({
+                            "worker": worker,
+                            "result": result,
+                            "padding": "x".repeat(4096),
+                        });
+                        let (text, json) =
+                            format_result(&value, OutputFormat::Json, result % 2 == 1).unwrap();
+                        write_result_to(
+                            lock,
+                            &mut SharedOutput(output),
+                            Some(json_output),
+                            &text,
+                            &json,
+                        )
+                        .unwrap();
+                    }
+                });
+            }
+        });
+
+        for written in [output, json_output] {
+            let written = written.into_inner().unwrap();
+            let mut printed = std::collections::HashSet::new();
+            for value in
+                serde_json::Deserializer::from_slice(&written).into_iter::<serde_json::Value>()
+            {
+                let value = value.unwrap();
+                printed.insert((
+                    value["worker"].as_u64().unwrap(),
+                    value["result"].as_u64().unwrap(),
+                ));
+            }
+            assert_eq!(printed.len(), 800);
+        }
+    }
+
+    #[test]
+    fn results_format_as_json_or_yaml() {
+        let value = serde_json::json!({
+            "address": "key",
+            "in_filter": true,
+            "signatures": [{ "status": "valid" }, { "status": "unsigned" }],
+        });
+        let parse = |json: &str| serde_json::from_str::<serde_json::Value>(json).unwrap();
+
+        let (text, json) = format_result(&value, OutputFormat::Json, false).unwrap();
+        assert_eq!(text, json);
+        assert_eq!(parse(&json), value);
+        let (text, json) = format_result(&value, OutputFormat::Json, true).unwrap();
+        assert_eq!(text, json);
+        assert!(!json.contains('\n'));
+        assert_eq!(parse(&json), value);
+
+        // the json output file gets json whatever the format
+        let (text, json) = format_result(&value, OutputFormat::Yaml, false).unwrap();
+        assert!(text.starts_with("---\n"));
+        assert_eq!(parse(&json), value);
+        let (line, _) = format_result(&value, OutputFormat::Yaml, true).unwrap();
+        let stream = format!("{text}\n{line}\n");
+        let documents: Vec<serde_json::Value> = serde_yaml::Deserializer::from_str(&stream)
+            .map(|document| serde::Deserialize::deserialize(document).unwrap())
+            .collect();
+        assert_eq!(documents, [value.clone(), value]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn output_files_are_created_with_the_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = test_dir("output-permissions");
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+
+        let target = dir.join("data.bin");
+        let mut file = open_output_file_with_mode(&target, true, Some(0o640)).unwrap();
+        assert_eq!(mode(&target), 0o640);
+        file.write_all(b"data").unwrap();
+        assert!(open_output_file_with_mode(&target, true, Some(0o640)).is_err());
+
+        let existing = dir.join("existing.json");
+        fs::write(&existing, b"{}").unwrap();
+        fs::set_permissions(&existing, fs::Permissions::from_mode(0o666)).unwrap();
+        open_output_file_with_mode(&existing, false, Some(0o640)).unwrap();
+        assert_eq!(mode(&existing), 0o640);
+
+        // without a mode existing files keep theirs
+        open_output_file_with_mode(&existing, false, None).unwrap();
+        assert_eq!(mode(&existing), 0o640);
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
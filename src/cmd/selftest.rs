@@ -0,0 +1,371 @@
+use crate::cmd::{filter::contains_descriptor_edge, print_result};
+use anyhow::{Context, Result};
+use helium_crypto::{KeyTag, KeyType, Keypair, Network, PublicKeyBinary};
+use rand::{rngs::OsRng, Rng};
+use serde_json::json;
+use std::{path::PathBuf, thread};
+use xorf_generator::{
+    base64_serde, edge_hash, public_key_hash, Descriptor, DescriptorBuilder, Entries, Filter,
+    FilterBackend, FilterOptions, FILTTER_VERSION,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct Cmd {
+    #[command(subcommand)]
+    pub cmd: SelftestCommand,
+}
+
+impl Cmd {
+    pub fn run(&self) -> Result<()> {
+        self.cmd.run()
+    }
+}
+
+/// Self checks of the filter tooling
+#[derive(clap::Subcommand, Debug)]
+pub enum SelftestCommand {
+    Reproduce(Reproduce),
+    Vectors(Vectors),
+    Roundtrip(Roundtrip),
+}
+
+impl SelftestCommand {
+    pub fn run(&self) -> Result<()> {
+        match self {
+            Self::Reproduce(cmd) => cmd.run(),
+            Self::Vectors(cmd) => cmd.run(),
+            Self::Roundtrip(cmd) => cmd.run(),
+        }
+    }
+}
+
+/// Check that building the filter for a descriptor is reproducible
+///
+/// Builds the filter for the given descriptor and serial more than once and
+/// fails unless every build produces identical signing bytes and hash. Run
+/// this before a signing ceremony to make sure every participant ends up with
+/// the same data to sign.
+#[derive(Debug, clap::Args)]
+pub struct Reproduce {
+    /// The descriptor file to build the filter from
+    #[arg(long, short, default_value = "descriptor.bin.gz")]
+    descriptor: PathBuf,
+    /// The serial number embedded in the signing bytes
+    #[arg(long, short)]
+    serial: u32,
+    /// The number of builds to run concurrently. At least two builds are
+    /// always run, sequentially if this is 1
+    #[arg(long, default_value_t = 1)]
+    threads: usize,
+}
+
+impl Reproduce {
+    pub fn run(&self) -> Result<()> {
+        let descriptor = Descriptor::from_path(&self.descriptor)
+            .context(format!("reading descriptor {}", self.descriptor.display()))?;
+        let options = FilterOptions::default();
+        let build = || -> xorf_generator::Result<(Vec<u8>, Vec<u8>)> {
+            let filter = Filter::from_descriptor(self.serial, &descriptor, &options)?;
+            Ok((filter.to_signing_bytes()?, filter.hash()?))
+        };
+
+        let count = self.threads.max(2);
+        let builds = if self.threads > 1 {
+            thread::scope(|scope| {
+                let handles: Vec<_> = (0..count).map(|_| scope.spawn(&build)).collect();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().expect("build thread panicked"))
+                    .collect::<xorf_generator::Result<Vec<_>>>()
+            })?
+        } else {
+            (0..count)
+                .map(|_| build())
+                .collect::<xorf_generator::Result<Vec<_>>>()?
+        };
+
+        let (signing_bytes, hash) = &builds[0];
+        let reproducible = builds
+            .iter()
+            .all(|(other_bytes, other_hash)| other_bytes == signing_bytes && other_hash == hash);
+        let json = json!({
+            "builds": count,
+            "hash": base64_serde::encode(hash),
+            "reproducible": reproducible,
+        });
+        print_result(&json)?;
+        if !reproducible {
+            anyhow::bail!("Filter builds are not reproducible");
+        }
+        Ok(())
+    }
+}
+
+const VECTOR_KEY_A: &str = "14HZVR4bdF9QMowYxWrumcFBNfWnhDdD5XXA5za1fWwUhHxxFS1";
+const VECTOR_KEY_B: &str = "14MRZY2jc2ABDq1faCCMmXrkm2PXY9UBRTP1j9PWnFTKnCb7Hyn";
+
+/// Check the built in test vectors
+///
+/// Rebuilds a fixed set of key and edge hashes, descriptor entries hashes and
+/// the signing and filter bytes of an empty filter, and fails if any of them
+/// differ from the expected values baked into this command. The expected
+/// values document the exact encoding for other implementations. Fingerprint
+/// construction itself is covered by `selftest reproduce`.
+#[derive(Debug, clap::Args)]
+pub struct Vectors {}
+
+impl Vectors {
+    pub fn run(&self) -> Result<()> {
+        let key_a: PublicKeyBinary = VECTOR_KEY_A.parse()?;
+        let key_b: PublicKeyBinary = VECTOR_KEY_B.parse()?;
+
+        let mut node_builder = DescriptorBuilder::default();
+        node_builder.add_node(key_a.clone(), None, 0);
+        let node_descriptor = node_builder.build();
+        let mut edge_builder = DescriptorBuilder::default();
+        edge_builder.add_edge(&key_a, &key_b, None, 0);
+        let edge_descriptor = edge_builder.build();
+        let empty = Filter::new_empty(7);
+
+        let vectors = [
+            (
+                "key_hash",
+                public_key_hash(&key_a).to_string(),
+                "12933776757726074941",
+            ),
+            (
+                "edge_hash",
+                edge_hash(&key_a, &key_b).to_string(),
+                "18226953701957413708",
+            ),
+            (
+                "edge_hash_reversed",
+                edge_hash(&key_b, &key_a).to_string(),
+                "18226953701957413708",
+            ),
+            (
+                "entries_hash_node",
+                base64_serde::encode(&node_descriptor.entries_hash(Entries::Both)),
+                "U/vAgwifIymhWTPfKhs+acRE2Rlx9BBcC0AOO6QWAc0=",
+            ),
+            (
+                "entries_hash_edge",
+                base64_serde::encode(&edge_descriptor.entries_hash(Entries::Both)),
+                "BTuxrc/NsHEUhiUCkK6uUwvvDLJ8rFib0X8zraxy4ZA=",
+            ),
+            (
+                "empty_signing_bytes",
+                hex(&empty.to_signing_bytes()?),
+                "0700000002000000",
+            ),
+            (
+                "empty_hash",
+                base64_serde::encode(&empty.hash()?),
+                "KLreZXPr35r6axxP0iMVsRoPsxxgRyncd+tDE1VEpTA=",
+            ),
+            (
+                "empty_filter_bytes",
+                hex(&empty.to_bytes()?),
+                "584f524601000200000700000002000000",
+            ),
+        ];
+
+        let results: Vec<_> = vectors
+            .iter()
+            .map(|(name, actual, expected)| {
+                json!({
+                    "name": name,
+                    "expected": expected,
+                    "actual": actual,
+                    "matches": actual.as_str() == *expected,
+                })
+            })
+            .collect();
+        let failed = vectors
+            .iter()
+            .filter(|(_, actual, expected)| actual.as_str() != *expected)
+            .count();
+        print_result(&json!({
+            "vectors": results,
+            "failed": failed,
+        }))?;
+        if failed > 0 {
+            anyhow::bail!("{failed} test vectors do not match");
+        }
+        Ok(())
+    }
+}
+
+/// Check that filters survive serialization unchanged
+///
+/// Builds filters for a number of random descriptors, alternating the backend
+/// and whether an entries hash is included, and gives each a random serial
+/// and signature. Every filter is encoded with `to_bytes` and decoded again,
+/// and its signing bytes are decoded as signing data. The command fails
+/// unless the serial, hash, signature where it is carried, re-encoded bytes
+/// and every descriptor entry survive each round trip.
+#[derive(Debug, clap::Args)]
+pub struct Roundtrip {
+    /// The number of random descriptors to check
+    #[arg(long, default_value_t = 8)]
+    rounds: usize,
+    /// The number of keys in each random descriptor. Half as many edges are
+    /// added between them
+    #[arg(long, default_value_t = 100)]
+    keys: usize,
+}
+
+impl Roundtrip {
+    pub fn run(&self) -> Result<()> {
+        let mut rng = rand::thread_rng();
+        let key_tag = KeyTag {
+            network: Network::MainNet,
+            key_type: KeyType::Ed25519,
+        };
+        let mut results = vec![];
+        let mut failed = 0;
+        for round in 0..self.rounds {
+            let keys: Vec<PublicKeyBinary> = (0..self.keys.max(2))
+                .map(|_| {
+                    Keypair::generate(key_tag, &mut OsRng)
+                        .public_key()
+                        .clone()
+                        .into()
+                })
+                .collect();
+            let (nodes, edge_keys) = keys.split_at(keys.len() / 2);
+            let mut builder = DescriptorBuilder::default();
+            for key in nodes {
+                builder.add_node(key.clone(), None, 0);
+            }
+            for pair in edge_keys.chunks(2).filter(|pair| pair.len() == 2) {
+                builder.add_edge(&pair[0], &pair[1], None, 0);
+            }
+            let descriptor = builder.build();
+            let options = FilterOptions {
+                entries_hash: round % 2 == 1,
+                backend: match round % 2 {
+                    0 => FilterBackend::Xor,
+                    _ => FilterBackend::BinaryFuse,
+                },
+                ..Default::default()
+            };
+            let mut filter = Filter::from_descriptor(rng.gen(), &descriptor, &options)?;
+            filter.signature = (0..64).map(|_| rng.gen()).collect();
+
+            let failures = roundtrip_failures(&filter, &descriptor)?;
+            if !failures.is_empty() {
+                failed += 1;
+            }
+            results.push(json!({
+                "round": round,
+                "backend": filter.filter.kind(),
+                "entries_hash": options.entries_hash,
+                "serial": filter.serial,
+                "fingerprints": filter.len(),
+                "failures": failures,
+            }));
+        }
+        print_result(&json!({
+            "rounds": results,
+            "failed": failed,
+        }))?;
+        if failed > 0 {
+            anyhow::bail!("{failed} of {} filters do not round trip", self.rounds);
+        }
+        Ok(())
+    }
+}
+
+/// The invariants that do not hold for a filter after decoding its filter
+/// bytes and its signing bytes
+fn roundtrip_failures(filter: &Filter, descriptor: &Descriptor) -> Result<Vec<String>> {
+    let mut failures = vec![];
+    let hash = filter.hash()?;
+    let bytes = filter.to_bytes()?;
+    let signing_bytes = filter.to_signing_bytes()?;
+    let decoded = [
+        ("filter bytes", Filter::from_bytes(&bytes)),
+        (
+            "signing bytes",
+            Filter::from_signing_bytes(&signing_bytes, FILTTER_VERSION),
+        ),
+    ];
+    for (name, decoded) in decoded {
+        let decoded = match decoded {
+            Ok(decoded) => decoded,
+            Err(err) => {
+                failures.push(format!("{name} do not decode: {err}"));
+                continue;
+            }
+        };
+        let mut check = |passed: bool, what: &str| {
+            if !passed {
+                failures.push(format!("{what} changed through the {name}"));
+            }
+        };
+        check(decoded.serial == filter.serial, "serial");
+        check(decoded.hash()? == hash, "hash");
+        check(
+            decoded.to_signing_bytes()? == signing_bytes,
+            "signing bytes",
+        );
+        if name == "filter bytes" {
+            check(decoded.signature == filter.signature, "signature");
+            check(decoded.to_bytes()? == bytes, "filter bytes");
+        }
+        check(
+            descriptor.iter_keys().all(|key| decoded.contains(&key)),
+            "key membership",
+        );
+        check(
+            descriptor
+                .iter_edges_directed()
+                .all(|(source, target, directed)| {
+                    contains_descriptor_edge(&decoded, &source, &target, directed)
+                }),
+            "edge membership",
+        );
+    }
+    Ok(failures)
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cmd::test_util::{capture_results, descriptor, parse};
+
+    #[test]
+    fn vectors_match() {
+        let (result, printed) = capture_results(|| parse::<Vectors>(&[]).run());
+        result.unwrap();
+        assert_eq!(printed[0]["failed"], 0);
+    }
+
+    #[test]
+    fn roundtrip_passes_and_reports_lost_entries() {
+        let (result, printed) =
+            capture_results(|| parse::<Roundtrip>(&["--rounds", "2", "--keys", "20"]).run());
+        result.unwrap();
+        assert_eq!(printed[0]["failed"], 0);
+        assert_eq!(printed[0]["rounds"].as_array().unwrap().len(), 2);
+
+        let built_from = descriptor(20, 5);
+        let filter = Filter::from_descriptor(3, &built_from, &FilterOptions::default()).unwrap();
+        assert!(roundtrip_failures(&filter, &built_from).unwrap().is_empty());
+        let failures = roundtrip_failures(&filter, &descriptor(1, 1)).unwrap();
+        assert_eq!(
+            failures,
+            [
+                "key membership changed through the filter bytes",
+                "edge membership changed through the filter bytes",
+                "key membership changed through the signing bytes",
+                "edge membership changed through the signing bytes",
+            ]
+        );
+    }
+}
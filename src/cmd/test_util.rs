@@ -0,0 +1,146 @@
+//! Fixtures shared by the command tests
+
+use super::CAPTURED_RESULTS;
+use helium_crypto::{KeyTag, KeyType, Keypair, Network, PublicKeyBinary};
+use rand::rngs::OsRng;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use xorf_generator::{
+    Descriptor, DescriptorBuilder, Filter, FilterOptions, Manifest, PublicKeyManifest,
+};
+
+/// Parses the given arguments of a command the way the command line would
+pub fn parse<T: clap::Args>(args: &[&str]) -> T {
+    #[derive(clap::Parser)]
+    struct Cli<T: clap::Args> {
+        #[command(flatten)]
+        args: T,
+    }
+    <Cli<T> as clap::Parser>::parse_from(
+        std::iter::once("xorf-generator").chain(args.iter().copied()),
+    )
+    .args
+}
+
+/// Runs a command and returns its result along with the results it printed
+/// on this thread
+pub fn capture_results<R>(run: impl FnOnce() -> R) -> (R, Vec<serde_json::Value>) {
+    CAPTURED_RESULTS.set(Some(vec![]));
+    let result = run();
+    let printed = CAPTURED_RESULTS
+        .take()
+        .unwrap_or_default()
+        .iter()
+        .map(|json| serde_json::from_str(json).unwrap())
+        .collect();
+    (result, printed)
+}
+
+/// The given path as a command line argument
+pub fn arg(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+/// A fresh directory in the temporary directory for the calling test
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("xorf-generator-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub fn keypair() -> Keypair {
+    let key_tag = KeyTag {
+        network: Network::MainNet,
+        key_type: KeyType::Ed25519,
+    };
+    Keypair::generate(key_tag, &mut OsRng)
+}
+
+pub fn key() -> PublicKeyBinary {
+    keypair().public_key().clone().into()
+}
+
+/// Writes a public key of the given number of fresh members requiring the
+/// given number of signatures to the given path, and returns the members
+pub fn write_key_manifest(path: &Path, members: usize, required: u8) -> Vec<Keypair> {
+    let keypairs: Vec<Keypair> = (0..members).map(|_| keypair()).collect();
+    let public_keys = keypairs
+        .iter()
+        .map(|keypair| keypair.public_key().clone())
+        .collect();
+    let key_manifest = PublicKeyManifest::new(public_keys, required);
+    fs::write(path, serde_json::to_vec_pretty(&key_manifest).unwrap()).unwrap();
+    keypairs
+}
+
+/// Writes the keys as a json array of public keys, the format of allowlists
+/// and candidate lists
+pub fn write_key_list(path: &Path, keys: &[PublicKeyBinary]) {
+    let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+    fs::write(path, serde_json::to_vec(&keys).unwrap()).unwrap();
+}
+
+/// A descriptor with the given number of full nodes and undirected edges
+/// between fresh keys
+pub fn descriptor(nodes: usize, edges: usize) -> Descriptor {
+    let mut builder = DescriptorBuilder::default();
+    for _ in 0..nodes {
+        builder.add_node(key(), None, 0);
+    }
+    for _ in 0..edges {
+        builder.add_edge(&key(), &key(), None, 0);
+    }
+    builder.build()
+}
+
+pub fn write_descriptor(path: &Path, descriptor: &Descriptor) {
+    descriptor.to_path(fs::File::create(path).unwrap()).unwrap();
+}
+
+/// An unsigned filter with the given serial over fresh keys and edges
+pub fn filter(serial: u32) -> Filter {
+    Filter::from_descriptor(serial, &descriptor(100, 20), &FilterOptions::default()).unwrap()
+}
+
+/// Writes the signing data of an unsigned filter with the given serial to the
+/// given path
+pub fn write_data(path: &Path, serial: u32) -> Filter {
+    let filter = filter(serial);
+    fs::write(path, filter.to_signing_bytes().unwrap()).unwrap();
+    filter
+}
+
+/// Writes a manifest for the filter signed by the given members of the
+/// public key in the file `key` to the given path
+pub fn write_signed_manifest(path: &Path, filter: &Filter, key: &Path, keypairs: &[Keypair]) {
+    let key_manifest = PublicKeyManifest::from_path(key).unwrap();
+    let mut manifest = Manifest::from_filter(filter, &key_manifest).unwrap();
+    manifest
+        .sign_with(&filter.to_signing_bytes().unwrap(), keypairs)
+        .unwrap();
+    fs::write(path, serde_json::to_vec_pretty(&manifest).unwrap()).unwrap();
+}
+
+/// Writes a public key of two members requiring both to `public_key.json` and
+/// a filter with the given serial signed by them to `filter.bin` in the given
+/// directory, and returns the filter
+pub fn write_signed_filter(dir: &Path, serial: u32) -> Filter {
+    sign_filter(dir, filter(serial))
+}
+
+/// Like [`write_signed_filter`] for the given filter
+pub fn sign_filter(dir: &Path, mut filter: Filter) -> Filter {
+    let key = dir.join("public_key.json");
+    let keypairs = write_key_manifest(&key, 2, 2);
+    let key_manifest = PublicKeyManifest::from_path(&key).unwrap();
+    let mut manifest = Manifest::from_filter(&filter, &key_manifest).unwrap();
+    manifest
+        .sign_with(&filter.to_signing_bytes().unwrap(), &keypairs)
+        .unwrap();
+    filter.apply_manifest(&manifest, &key_manifest).unwrap();
+    fs::write(dir.join("filter.bin"), filter.to_bytes().unwrap()).unwrap();
+    filter
+}
@@ -0,0 +1,91 @@
+use crate::cmd::print_result;
+use anyhow::{Context, Result};
+use jsonschema::JSONSchema;
+use serde_json::json;
+use std::{fs, path::PathBuf};
+use xorf_generator::{Descriptor, Manifest, PublicKeyManifest};
+
+/// Validate an input file before running other commands on it
+///
+/// Json files are checked against the schema derived from the types the
+/// commands read them into, and every mismatch is reported with the path of
+/// the offending field. Descriptors are binary and are checked by decoding
+/// them.
+#[derive(Debug, clap::Args)]
+pub struct Cmd {
+    /// The file to validate
+    #[arg(long, short)]
+    file: PathBuf,
+    /// The kind of file to validate it as
+    #[arg(long = "as", value_enum)]
+    kind: FileKind,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum FileKind {
+    Descriptor,
+    Manifest,
+    PublicKey,
+}
+
+impl Cmd {
+    pub fn run(&self) -> Result<()> {
+        let errors = match self.kind {
+            FileKind::Descriptor => match Descriptor::from_path(&self.file) {
+                Ok(_) => vec![],
+                Err(err) => vec![err.to_string()],
+            },
+            FileKind::Manifest => self.schema_errors(schemars::schema_for!(Manifest))?,
+            FileKind::PublicKey => self.schema_errors(schemars::schema_for!(PublicKeyManifest))?,
+        };
+        let valid = errors.is_empty();
+        let json = json!({
+            "file": self.file,
+            "valid": valid,
+            "errors": errors,
+        });
+        print_result(&json)?;
+        if !valid {
+            anyhow::bail!("{} is not valid", self.file.display());
+        }
+        Ok(())
+    }
+
+    fn schema_errors(&self, schema: schemars::schema::RootSchema) -> Result<Vec<String>> {
+        let data = fs::read(&self.file).context(format!("reading {}", self.file.display()))?;
+        let instance: serde_json::Value = match serde_json::from_slice(&data) {
+            Ok(instance) => instance,
+            Err(err) => return Ok(vec![err.to_string()]),
+        };
+        let schema = serde_json::to_value(schema)?;
+        let compiled =
+            JSONSchema::compile(&schema).map_err(|err| anyhow::anyhow!("invalid schema: {err}"))?;
+        let errors = match compiled.validate(&instance) {
+            Ok(()) => vec![],
+            Err(errors) => errors
+                .map(|err| format!("{}: {err}", field_path(&err.instance_path.to_string())))
+                .collect(),
+        };
+        Ok(errors)
+    }
+}
+
+/// Converts a json pointer like "/signatures/2/signature" into
+/// "signatures[2].signature"
+fn field_path(pointer: &str) -> String {
+    let mut path = String::new();
+    for segment in pointer.split('/').skip(1) {
+        if segment.parse::<usize>().is_ok() {
+            path.push_str(&format!("[{segment}]"));
+        } else {
+            if !path.is_empty() {
+                path.push('.');
+            }
+            path.push_str(segment);
+        }
+    }
+    if path.is_empty() {
+        path.push('.');
+    }
+    path
+}
@@ -1,9 +1,19 @@
-use crate::{edge_order, Result};
-use helium_crypto::PublicKeyBinary;
-use indexmap::IndexSet;
+use crate::{
+    edge_order,
+    filter::{directed_edge_bytes_hash, edge_bytes_hash},
+    Entries, Error, Result,
+};
+use helium_crypto::{PublicKey, PublicKeyBinary};
+use indexmap::{IndexMap, IndexSet};
 use prost::Message;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs::File, path::Path};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+};
 
 include!(concat!(env!("OUT_DIR"), "/denylist.descriptor.rs"));
 
@@ -13,6 +23,23 @@ struct CsvRow {
     pub target_key: Option<PublicKeyBinary>,
     pub reason: Option<String>,
     pub carryover: Option<u32>,
+    pub directed: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum LogAction {
+    Block,
+    Unblock,
+}
+
+#[derive(Debug, Deserialize)]
+struct LogEvent {
+    action: LogAction,
+    address: PublicKeyBinary,
+    target: Option<PublicKeyBinary>,
+    reason: Option<String>,
+    carryover: Option<u32>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Eq)]
@@ -72,18 +99,23 @@ pub struct EdgeNode {
     target: PublicKeyBinary,
     reason: Option<String>,
     carryover: u32,
+    #[serde(default)]
+    directed: bool,
 }
 
 impl std::hash::Hash for EdgeNode {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.source.hash(state);
         self.target.hash(state);
+        self.directed.hash(state);
     }
 }
 
 impl PartialEq for EdgeNode {
     fn eq(&self, other: &Self) -> bool {
-        self.source == other.source && self.target == other.target
+        self.source == other.source
+            && self.target == other.target
+            && self.directed == other.directed
     }
 }
 
@@ -111,16 +143,121 @@ impl EdgeNode {
             target,
             reason,
             carryover,
+            directed: false,
+        }
+    }
+
+    /// Creates an edge that only blocks the source from the target
+    pub fn new_directed(
+        source: PublicKeyBinary,
+        target: PublicKeyBinary,
+        reason: Option<String>,
+        carryover: u32,
+    ) -> Self {
+        Self {
+            directed: true,
+            ..Self::new(source, target, reason, carryover)
+        }
+    }
+
+    pub fn is_directed(&self) -> bool {
+        self.directed
+    }
+}
+
+/// A source that descriptors are loaded from.
+///
+/// The file formats this crate reads are available as [`DescriptorFile`].
+/// Implement it for other sources, like a database query, to feed their
+/// entries into [`crate::Filter::from_descriptor`].
+pub trait DescriptorSource {
+    fn load(&self) -> Result<Descriptor>;
+}
+
+/// The descriptor file formats this crate reads
+#[derive(Debug, Clone)]
+pub enum DescriptorFile {
+    /// A protobuf descriptor, as read by [`Descriptor::from_path`]
+    Proto(PathBuf),
+    /// A csv file of keys and edges, as read by [`Descriptor::from_csv`]
+    Csv(PathBuf),
+    /// A json lines event log, as read by [`Descriptor::from_event_log`]
+    EventLog(PathBuf),
+}
+
+impl DescriptorFile {
+    /// Detects the format of a descriptor file from its decompressed content.
+    /// Text that starts with a json object is an event log, other text with a
+    /// comma in its first line is csv, and anything that is not text is a
+    /// protobuf descriptor. Other text is an event log by a `.jsonl` or
+    /// `.ndjson` extension and csv otherwise.
+    pub fn detect(path: &Path) -> Result<Self> {
+        if path.as_os_str() == "-" {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the format of stdin can not be detected, give it explicitly",
+            )
+            .into());
+        }
+        let mut head = Vec::new();
+        open_reader(path)?.take(4096).read_to_end(&mut head)?;
+        let text = match std::str::from_utf8(&head) {
+            Ok(text) => Some(text),
+            // the sample may end in the middle of a character
+            Err(err) if err.error_len().is_none() => {
+                std::str::from_utf8(&head[..err.valid_up_to()]).ok()
+            }
+            Err(_) => None,
+        };
+        let path = path.to_path_buf();
+        let Some(text) = text.map(str::trim_start) else {
+            return Ok(Self::Proto(path));
+        };
+        if text.starts_with('[') {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "json array descriptors are not supported, use csv or a json lines event log",
+            )
+            .into());
+        }
+        if text.starts_with('{') {
+            return Ok(Self::EventLog(path));
+        }
+        if text.lines().next().is_some_and(|line| line.contains(',')) {
+            return Ok(Self::Csv(path));
+        }
+        // single column csv files of keys have no comma
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        Ok(match extension {
+            Some("jsonl" | "ndjson") => Self::EventLog(path),
+            _ => Self::Csv(path),
+        })
+    }
+
+    /// The name of the format, as reported to users
+    pub fn format_name(&self) -> &'static str {
+        match self {
+            Self::Proto(_) => "descriptor",
+            Self::Csv(_) => "csv",
+            Self::EventLog(_) => "event-log",
+        }
+    }
+}
+
+impl DescriptorSource for DescriptorFile {
+    fn load(&self) -> Result<Descriptor> {
+        match self {
+            Self::Proto(path) => Descriptor::from_path(path),
+            Self::Csv(path) => Descriptor::from_csv(path),
+            Self::EventLog(path) => Descriptor::from_event_log(path),
         }
     }
 }
 
 impl Descriptor {
     pub fn from_path(path: &Path) -> Result<Self> {
-        use std::io::Read;
         let mut buf = Vec::new();
-        let mut reader = flate2::read::GzDecoder::new(File::open(path)?);
-        reader.read_to_end(&mut buf)?;
+        open_reader(path)?.read_to_end(&mut buf)?;
         Ok(Self::decode(buf.as_ref())?)
     }
 
@@ -128,9 +265,142 @@ impl Descriptor {
         use std::io::Write;
         let mut file = flate2::write::GzEncoder::new(writer, flate2::Compression::best());
         file.write_all(&self.encode_to_vec())?;
+        file.finish()?;
         Ok(())
     }
 
+    /// Iterates over the public keys of the full nodes in the descriptor
+    pub fn iter_keys(&self) -> impl Iterator<Item = PublicKeyBinary> + '_ {
+        self.nodes
+            .iter()
+            .map(|node| PublicKeyBinary::from(node.key.as_slice()))
+    }
+
+    /// Iterates over the (source, target) public key pairs of the edges in the
+    /// descriptor
+    pub fn iter_edges(&self) -> impl Iterator<Item = (PublicKeyBinary, PublicKeyBinary)> + '_ {
+        self.edges.iter().flat_map(|edges| {
+            edges.edges.iter().map(move |edge| {
+                (
+                    PublicKeyBinary::from(edges.keys[edge.source as usize].as_slice()),
+                    PublicKeyBinary::from(edges.keys[edge.target as usize].as_slice()),
+                )
+            })
+        })
+    }
+
+    /// Iterates over the (source, target, directed) triples of the edges in
+    /// the descriptor
+    pub fn iter_edges_directed(
+        &self,
+    ) -> impl Iterator<Item = (PublicKeyBinary, PublicKeyBinary, bool)> + '_ {
+        self.edges.iter().flat_map(|edges| {
+            edges.edges.iter().map(move |edge| {
+                (
+                    PublicKeyBinary::from(edges.keys[edge.source as usize].as_slice()),
+                    PublicKeyBinary::from(edges.keys[edge.target as usize].as_slice()),
+                    edge.directed,
+                )
+            })
+        })
+    }
+
+    /// Iterates over the raw public key bytes of the full nodes, borrowed from
+    /// the descriptor. Keys are stored as protobuf bytes, so
+    /// [`Descriptor::iter_keys`] builds an owned key for every node.
+    pub fn iter_key_bytes(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        self.nodes.iter().map(|node| node.key.as_slice())
+    }
+
+    /// Iterates over the (source, target, directed) raw public key bytes of
+    /// the edges, borrowed from the descriptor
+    pub fn iter_edge_bytes(&self) -> impl Iterator<Item = (&[u8], &[u8], bool)> + '_ {
+        self.edges.iter().flat_map(|edges| {
+            edges.edges.iter().map(move |edge| {
+                (
+                    edges.keys[edge.source as usize].as_slice(),
+                    edges.keys[edge.target as usize].as_slice(),
+                    edge.directed,
+                )
+            })
+        })
+    }
+
+    /// Iterates over the hashes the edges are inserted into a filter with,
+    /// [`crate::directed_edge_hash`] for directed edges and
+    /// [`crate::edge_hash`] for the others
+    pub fn iter_edge_hashes(&self) -> impl Iterator<Item = u64> + '_ {
+        self.iter_edge_bytes().map(|(source, target, directed)| {
+            if directed {
+                directed_edge_bytes_hash(source, target)
+            } else {
+                edge_bytes_hash(source, target)
+            }
+        })
+    }
+
+    /// A sha256 hash over the sorted public keys and edges of the descriptor
+    /// that the given entries select. The hash identifies the descriptor
+    /// content independent of the order or duplicates of its entries.
+    /// Directed edges are hashed after the others, so descriptors without
+    /// them keep their hash.
+    pub fn entries_hash(&self, entries: Entries) -> Vec<u8> {
+        let mut keys: Vec<Vec<u8>> = vec![];
+        let mut edges: Vec<(Vec<u8>, Vec<u8>)> = vec![];
+        let mut directed_edges: Vec<(Vec<u8>, Vec<u8>)> = vec![];
+        if entries.includes_keys() {
+            keys.extend(self.iter_key_bytes().map(<[u8]>::to_vec));
+        }
+        if entries.includes_edges() {
+            for (source, target, directed) in self.iter_edge_bytes() {
+                if directed {
+                    directed_edges.push((source.to_vec(), target.to_vec()));
+                } else {
+                    // the byte order of public keys is their edge order
+                    let (source, target) = if source < target {
+                        (source, target)
+                    } else {
+                        (target, source)
+                    };
+                    edges.push((source.to_vec(), target.to_vec()));
+                }
+            }
+        }
+        keys.sort_unstable();
+        keys.dedup();
+        edges.sort_unstable();
+        edges.dedup();
+        directed_edges.sort_unstable();
+        directed_edges.dedup();
+
+        let mut hasher = Sha256::new();
+        let mut update = |tag: u8, key: &[u8]| {
+            hasher.update([tag]);
+            hasher.update((key.len() as u16).to_le_bytes());
+            hasher.update(key);
+        };
+        for key in &keys {
+            update(0, key);
+        }
+        for (source, target) in &edges {
+            update(1, source);
+            update(2, target);
+        }
+        for (source, target) in &directed_edges {
+            update(3, source);
+            update(4, target);
+        }
+        hasher.finalize().to_vec()
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn edge_count(&self) -> usize {
+        self.edges.as_ref().map_or(0, |edges| edges.edges.len())
+    }
+
     pub fn edge_counts(&self) -> HashMap<PublicKeyBinary, i32> {
         let mut counts: HashMap<PublicKeyBinary, i32> = HashMap::new();
         for node in &self.nodes {
@@ -163,66 +433,219 @@ impl Descriptor {
     pub fn from_csv(path: &Path) -> Result<Self> {
         let mut rdr = csv::ReaderBuilder::new()
             .has_headers(false)
-            .from_reader(File::open(path)?);
-        let mut full_nodes: IndexSet<FullNode> = IndexSet::new();
-        let mut edge_nodes: IndexSet<EdgeNode> = IndexSet::new();
-        let mut edge_keys: IndexSet<PublicKeyBinary> = IndexSet::new();
+            .flexible(true)
+            .from_reader(open_reader(path)?);
+        let mut builder = DescriptorBuilder::default();
 
-        for record in rdr.deserialize() {
+        for (index, record) in rdr.deserialize().enumerate() {
             let row: CsvRow = record?;
+            let entry = || format!("line {}", index + 1);
+            check_key(entry, row.public_key.as_ref())?;
+            if let Some(target_key) = &row.target_key {
+                check_key(entry, target_key.as_ref())?;
+            }
+            let carryover = row.carryover.unwrap_or(0);
             if let Some(target_key) = row.target_key {
-                // we enforce edge order here to dedupe two way edges.
-                let (source, target) = edge_order(&row.public_key, &target_key);
-                let edge = EdgeNode::new(
-                    source.clone(),
-                    target.clone(),
-                    row.reason,
-                    row.carryover.unwrap_or(0),
-                );
-                if !(full_nodes.contains(&FullNode {
-                    key: edge.source.clone(),
-                    reason: None,
-                    carryover: row.carryover.unwrap_or(0),
-                }) || full_nodes.contains(&FullNode {
-                    key: edge.target.clone(),
-                    reason: None,
-                    carryover: row.carryover.unwrap_or(0),
-                })) {
-                    edge_keys.insert(edge.source.clone());
-                    edge_keys.insert(edge.target.clone());
-                    edge_nodes.insert(edge);
+                if row.directed.unwrap_or(false) {
+                    builder.add_directed_edge(&row.public_key, &target_key, row.reason, carryover);
+                } else {
+                    builder.add_edge(&row.public_key, &target_key, row.reason, carryover);
                 }
             } else {
-                full_nodes.insert(FullNode {
-                    key: row.public_key,
-                    reason: row.reason,
-                    carryover: row.carryover.unwrap_or(0),
-                });
+                builder.add_node(row.public_key, row.reason, carryover);
             }
         }
 
-        full_nodes.sort_unstable();
-        edge_nodes.sort_unstable();
-        let edges = edge_nodes
-            .into_iter()
-            .map(|node| {
-                let source = edge_keys.get_index_of(&node.source).unwrap() as u32;
-                let target = edge_keys.get_index_of(&node.target).unwrap() as u32;
-                Edge {
-                    source,
-                    target,
-                    reason: node.reason.unwrap_or_default(),
-                    carryover: node.carryover,
+        Ok(builder.build())
+    }
+
+    /// Writes the descriptor as csv rows in the format read by
+    /// [`Descriptor::from_csv`]: the key, the target key (blank for full
+    /// nodes), the reason and the carryover. Directed edges have a fifth
+    /// `true` column.
+    pub fn to_csv<W: std::io::Write>(&self, writer: W) -> Result<()> {
+        let mut wtr = csv::WriterBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_writer(writer);
+        for node in &self.nodes {
+            let key = PublicKeyBinary::from(node.key.as_slice()).to_string();
+            wtr.serialize((key, "", &node.reason, node.carryover))?;
+        }
+        for edges in &self.edges {
+            for edge in &edges.edges {
+                let source = PublicKeyBinary::from(edges.keys[edge.source as usize].as_slice());
+                let target = PublicKeyBinary::from(edges.keys[edge.target as usize].as_slice());
+                let (source, target) = (source.to_string(), target.to_string());
+                if edge.directed {
+                    wtr.serialize((source, target, &edge.reason, edge.carryover, true))?;
+                } else {
+                    wtr.serialize((source, target, &edge.reason, edge.carryover))?;
                 }
-            })
-            .collect();
+            }
+        }
+        wtr.flush()?;
+        Ok(())
+    }
 
-        Ok(Self {
-            nodes: full_nodes.into_iter().map(Into::into).collect(),
-            edges: Some(Edges {
-                keys: edge_keys.into_iter().map(Into::into).collect(),
-                edges,
-            }),
+    /// Replays a json lines log of block and unblock events into a descriptor.
+    ///
+    /// Each line has an `action` of `block` or `unblock`, an `address` and an
+    /// optional edge `target`, `reason` and `carryover`. The descriptor holds
+    /// the entries that are blocked after the last event.
+    pub fn from_event_log(path: &Path) -> Result<Self> {
+        let reader = BufReader::new(open_reader(path)?);
+        let mut nodes: IndexMap<PublicKeyBinary, (Option<String>, u32)> = IndexMap::new();
+        let mut edges: IndexMap<(PublicKeyBinary, PublicKeyBinary), (Option<String>, u32)> =
+            IndexMap::new();
+
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event: LogEvent = serde_json::from_str(&line)?;
+            let entry = || format!("line {}", index + 1);
+            check_key(entry, event.address.as_ref())?;
+            if let Some(target) = &event.target {
+                check_key(entry, target.as_ref())?;
+            }
+            let entry = (event.reason, event.carryover.unwrap_or(0));
+            match (event.action, event.target) {
+                (LogAction::Block, None) => {
+                    nodes.insert(event.address, entry);
+                }
+                (LogAction::Unblock, None) => {
+                    nodes.shift_remove(&event.address);
+                }
+                (action, Some(target)) => {
+                    let (source, target) = edge_order(&event.address, &target);
+                    let edge = (source.clone(), target.clone());
+                    if let LogAction::Block = action {
+                        edges.insert(edge, entry);
+                    } else {
+                        edges.shift_remove(&edge);
+                    }
+                }
+            }
+        }
+
+        let mut builder = DescriptorBuilder::default();
+        for (key, (reason, carryover)) in nodes {
+            builder.add_node(key, reason, carryover);
+        }
+        for ((source, target), (reason, carryover)) in edges {
+            builder.add_edge(&source, &target, reason, carryover);
+        }
+        Ok(builder.build())
+    }
+
+    /// Checks the descriptor entries and reports every problem found, in
+    /// descriptor order. Keys that do not decode and edges that refer to
+    /// missing keys are errors, see [`DescriptorDiagnostic::is_error`];
+    /// duplicates and self edges are only reported, since building a filter
+    /// tolerates them.
+    pub fn validate(&self) -> Vec<DescriptorDiagnostic> {
+        let mut diagnostics = vec![];
+        let mut nodes: HashMap<&[u8], usize> = HashMap::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            if PublicKey::try_from(node.key.as_slice()).is_err() {
+                diagnostics.push(DescriptorDiagnostic::InvalidNodeKey {
+                    index,
+                    key: key_string(&node.key),
+                });
+            }
+            if let Some(&first) = nodes.get(node.key.as_slice()) {
+                diagnostics.push(DescriptorDiagnostic::DuplicateNode {
+                    index,
+                    first,
+                    address: key_string(&node.key),
+                });
+            } else {
+                nodes.insert(&node.key, index);
+            }
+        }
+        let Some(edges) = &self.edges else {
+            return diagnostics;
+        };
+        for (index, key) in edges.keys.iter().enumerate() {
+            if PublicKey::try_from(key.as_slice()).is_err() {
+                diagnostics.push(DescriptorDiagnostic::InvalidEdgeKey {
+                    index,
+                    key: key_string(key),
+                });
+            }
+        }
+        let mut seen: HashMap<(&[u8], &[u8], bool), usize> = HashMap::new();
+        for (index, edge) in edges.edges.iter().enumerate() {
+            let key = |key_index: u32| {
+                edges
+                    .keys
+                    .get(key_index as usize)
+                    .ok_or(DescriptorDiagnostic::MissingEdgeKey { index, key_index })
+            };
+            let (source, target) = match (key(edge.source), key(edge.target)) {
+                (Ok(source), Ok(target)) => (source.as_slice(), target.as_slice()),
+                (source, target) => {
+                    diagnostics.extend(source.err());
+                    diagnostics.extend(target.err());
+                    continue;
+                }
+            };
+            if source == target {
+                diagnostics.push(DescriptorDiagnostic::SelfEdge {
+                    index,
+                    address: key_string(source),
+                });
+            }
+            let pair = if source < target || edge.directed {
+                (source, target, edge.directed)
+            } else {
+                (target, source, edge.directed)
+            };
+            if let Some(&first) = seen.get(&pair) {
+                diagnostics.push(DescriptorDiagnostic::DuplicateEdge {
+                    index,
+                    first,
+                    source: key_string(source),
+                    target: key_string(target),
+                });
+            } else {
+                seen.insert(pair, index);
+            }
+        }
+        diagnostics
+    }
+
+    /// Whether the key is a full node of the descriptor. Unlike a filter
+    /// lookup this is exact.
+    pub fn contains(&self, key: &PublicKeyBinary) -> bool {
+        self.nodes
+            .iter()
+            .any(|node| node.key.as_slice() == key.as_ref())
+    }
+
+    /// Whether the descriptor has an edge between the given keys, in either
+    /// order for undirected edges and from source to target for directed
+    /// ones. Edges covered by a full node are not stored as edges and are not
+    /// reported; check the keys with [`Descriptor::contains`] for those.
+    pub fn contains_edge(&self, source: &PublicKeyBinary, target: &PublicKeyBinary) -> bool {
+        let Some(edges) = &self.edges else {
+            return false;
+        };
+        let index = |key: &PublicKeyBinary| {
+            edges
+                .keys
+                .iter()
+                .position(|entry| entry.as_slice() == key.as_ref())
+                .map(|index| index as u32)
+        };
+        let (Some(source), Some(target)) = (index(source), index(target)) else {
+            return false;
+        };
+        edges.edges.iter().any(|edge| {
+            (edge.source, edge.target) == (source, target)
+                || (!edge.directed && (edge.source, edge.target) == (target, source))
         })
     }
 
@@ -250,12 +673,11 @@ impl Descriptor {
                         if edge.source == key_index || edge.target == key_index {
                             let source = edges.keys[edge.source as usize].clone().into();
                             let target = edges.keys[edge.target as usize].clone().into();
-                            let reason = if edge.reason.is_empty() {
-                                None
-                            } else {
-                                Some(edge.reason.clone())
-                            };
-                            Some(EdgeNode::new(source, target, reason, edge.carryover))
+                            let reason = non_empty(&edge.reason);
+                            Some(EdgeNode {
+                                directed: edge.directed,
+                                ..EdgeNode::new(source, target, reason, edge.carryover)
+                            })
                         } else {
                             None
                         }
@@ -269,3 +691,690 @@ impl Descriptor {
         }
     }
 }
+
+/// A problem with a descriptor entry found by [`Descriptor::validate`].
+/// Indexes are positions in the descriptor nodes, edge keys or edges.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DescriptorDiagnostic {
+    /// A full node key that does not decode as a helium public key
+    InvalidNodeKey { index: usize, key: String },
+    /// An edge key that does not decode as a helium public key
+    InvalidEdgeKey { index: usize, key: String },
+    /// An edge that refers to an index past the end of the edge keys
+    MissingEdgeKey { index: usize, key_index: u32 },
+    /// A full node listed again after its first listing at `first`
+    DuplicateNode {
+        index: usize,
+        first: usize,
+        address: String,
+    },
+    /// An edge listed again, in either order, after its first listing at
+    /// `first`
+    DuplicateEdge {
+        index: usize,
+        first: usize,
+        source: String,
+        target: String,
+    },
+    /// An edge from a key to itself
+    SelfEdge { index: usize, address: String },
+}
+
+impl DescriptorDiagnostic {
+    /// Whether the descriptor can not be used to build a filter because of
+    /// this problem
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            Self::InvalidNodeKey { .. } | Self::InvalidEdgeKey { .. } | Self::MissingEdgeKey { .. }
+        )
+    }
+}
+
+impl std::fmt::Display for DescriptorDiagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidNodeKey { index, key } => {
+                write!(f, "invalid public key {key} at node {index}")
+            }
+            Self::InvalidEdgeKey { index, key } => {
+                write!(f, "invalid public key {key} at edge key {index}")
+            }
+            Self::MissingEdgeKey { index, key_index } => {
+                write!(f, "edge {index} refers to missing edge key {key_index}")
+            }
+            Self::DuplicateNode {
+                index,
+                first,
+                address,
+            } => write!(f, "node {index} duplicates node {first} for {address}"),
+            Self::DuplicateEdge {
+                index,
+                first,
+                source,
+                target,
+            } => write!(
+                f,
+                "edge {index} duplicates edge {first} from {source} to {target}"
+            ),
+            Self::SelfEdge { index, address } => {
+                write!(f, "edge {index} is a self edge of {address}")
+            }
+        }
+    }
+}
+
+/// Which descriptor's reason and carryover are kept when merged descriptors
+/// disagree on them for the same entry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MergeStrategy {
+    /// Keep the metadata of the descriptor merged first
+    #[default]
+    First,
+    /// Keep the metadata of the descriptor merged last
+    Last,
+    /// Fail the merge
+    Error,
+}
+
+/// Collects full nodes and edges into a canonical descriptor.
+///
+/// Duplicate nodes and edges are dropped, edges are stored in a fixed order
+/// regardless of the order of their keys, and edges touching an already added
+/// full node are skipped since the node already covers them.
+#[derive(Debug, Default)]
+pub struct DescriptorBuilder {
+    full_nodes: IndexSet<FullNode>,
+    edge_nodes: IndexSet<EdgeNode>,
+    edge_keys: IndexSet<PublicKeyBinary>,
+}
+
+impl DescriptorBuilder {
+    pub fn from_descriptor(descriptor: &Descriptor) -> Self {
+        let mut builder = Self::default();
+        builder.add_descriptor(descriptor);
+        builder
+    }
+
+    /// Adds all nodes and edges of the given descriptor
+    pub fn add_descriptor(&mut self, descriptor: &Descriptor) {
+        for node in &descriptor.nodes {
+            self.add_node(
+                node.key.clone().into(),
+                non_empty(&node.reason),
+                node.carryover,
+            );
+        }
+        if let Some(edges) = &descriptor.edges {
+            for edge in &edges.edges {
+                let source: PublicKeyBinary = edges.keys[edge.source as usize].clone().into();
+                let target: PublicKeyBinary = edges.keys[edge.target as usize].clone().into();
+                let reason = non_empty(&edge.reason);
+                self.insert_edge(&source, &target, reason, edge.carryover, edge.directed);
+            }
+        }
+    }
+
+    /// Adds all nodes and edges of the given descriptor, resolving entries
+    /// that are already present with a different reason or carryover by the
+    /// given strategy. Returns the number of such conflicts.
+    pub fn merge_descriptor(
+        &mut self,
+        descriptor: &Descriptor,
+        strategy: MergeStrategy,
+    ) -> Result<usize> {
+        let mut conflicts = 0;
+        for node in &descriptor.nodes {
+            let node = FullNode {
+                key: node.key.clone().into(),
+                reason: non_empty(&node.reason),
+                carryover: node.carryover,
+            };
+            let Some(existing) = self.full_nodes.get(&node) else {
+                self.full_nodes.insert(node);
+                continue;
+            };
+            if (&existing.reason, existing.carryover) == (&node.reason, node.carryover) {
+                continue;
+            }
+            conflicts += 1;
+            match strategy {
+                MergeStrategy::First => (),
+                MergeStrategy::Last => {
+                    self.full_nodes.replace(node);
+                }
+                MergeStrategy::Error => {
+                    return Err(Error::MergeConflict(format!(
+                        "key {} has conflicting reason or carryover",
+                        node.key
+                    )))
+                }
+            }
+        }
+        if let Some(edges) = &descriptor.edges {
+            for edge in &edges.edges {
+                let source: PublicKeyBinary = edges.keys[edge.source as usize].clone().into();
+                let target: PublicKeyBinary = edges.keys[edge.target as usize].clone().into();
+                let directed = edge.directed;
+                let (source, target) = if directed {
+                    (&source, &target)
+                } else {
+                    edge_order(&source, &target)
+                };
+                let edge = EdgeNode {
+                    directed,
+                    ..EdgeNode::new(
+                        source.clone(),
+                        target.clone(),
+                        non_empty(&edge.reason),
+                        edge.carryover,
+                    )
+                };
+                let Some(existing) = self.edge_nodes.get(&edge) else {
+                    let (source, target) = (&edge.source, &edge.target);
+                    self.insert_edge(source, target, edge.reason, edge.carryover, directed);
+                    continue;
+                };
+                if (&existing.reason, existing.carryover) == (&edge.reason, edge.carryover) {
+                    continue;
+                }
+                conflicts += 1;
+                match strategy {
+                    MergeStrategy::First => (),
+                    MergeStrategy::Last => {
+                        self.edge_nodes.replace(edge);
+                    }
+                    MergeStrategy::Error => {
+                        return Err(Error::MergeConflict(format!(
+                            "edge {} to {} has conflicting reason or carryover",
+                            edge.source, edge.target
+                        )))
+                    }
+                }
+            }
+        }
+        Ok(conflicts)
+    }
+
+    /// Adds a full node. Returns false if the key was already present.
+    pub fn add_node(
+        &mut self,
+        key: PublicKeyBinary,
+        reason: Option<String>,
+        carryover: u32,
+    ) -> bool {
+        self.full_nodes.insert(FullNode {
+            key,
+            reason,
+            carryover,
+        })
+    }
+
+    /// Adds an undirected edge between two keys. Returns false if the edge
+    /// was already present or is covered by a full node.
+    pub fn add_edge(
+        &mut self,
+        a: &PublicKeyBinary,
+        b: &PublicKeyBinary,
+        reason: Option<String>,
+        carryover: u32,
+    ) -> bool {
+        self.insert_edge(a, b, reason, carryover, false)
+    }
+
+    /// Adds an edge that only blocks the source from the target. Returns
+    /// false if the edge was already present or is covered by a full node.
+    pub fn add_directed_edge(
+        &mut self,
+        source: &PublicKeyBinary,
+        target: &PublicKeyBinary,
+        reason: Option<String>,
+        carryover: u32,
+    ) -> bool {
+        self.insert_edge(source, target, reason, carryover, true)
+    }
+
+    fn insert_edge(
+        &mut self,
+        a: &PublicKeyBinary,
+        b: &PublicKeyBinary,
+        reason: Option<String>,
+        carryover: u32,
+        directed: bool,
+    ) -> bool {
+        // we enforce edge order here to dedupe two way edges.
+        let (source, target) = if directed { (a, b) } else { edge_order(a, b) };
+        if self.has_node(source) || self.has_node(target) {
+            return false;
+        }
+        self.edge_keys.insert(source.clone());
+        self.edge_keys.insert(target.clone());
+        self.edge_nodes.insert(EdgeNode {
+            directed,
+            ..EdgeNode::new(source.clone(), target.clone(), reason, carryover)
+        })
+    }
+
+    /// Removes the full node for a key and all edges touching it. Returns the
+    /// number of removed entries.
+    pub fn remove_key(&mut self, key: &PublicKeyBinary) -> usize {
+        let mut removed = 0;
+        if self.full_nodes.shift_remove(&FullNode {
+            key: key.clone(),
+            reason: None,
+            carryover: 0,
+        }) {
+            removed += 1;
+        }
+        let edges = self.edge_nodes.len();
+        self.edge_nodes
+            .retain(|edge| &edge.source != key && &edge.target != key);
+        removed += edges - self.edge_nodes.len();
+        self.edge_keys.shift_remove(key);
+        removed
+    }
+
+    fn has_node(&self, key: &PublicKeyBinary) -> bool {
+        self.full_nodes.contains(&FullNode {
+            key: key.clone(),
+            reason: None,
+            carryover: 0,
+        })
+    }
+
+    pub fn build(mut self) -> Descriptor {
+        self.full_nodes.sort_unstable();
+        self.edge_nodes.sort_unstable();
+        let edge_keys = self.edge_keys;
+        let edges = self
+            .edge_nodes
+            .into_iter()
+            .map(|node| {
+                let source = edge_keys.get_index_of(&node.source).unwrap() as u32;
+                let target = edge_keys.get_index_of(&node.target).unwrap() as u32;
+                Edge {
+                    source,
+                    target,
+                    reason: node.reason.unwrap_or_default(),
+                    carryover: node.carryover,
+                    directed: node.directed,
+                }
+            })
+            .collect();
+
+        Descriptor {
+            nodes: self.full_nodes.into_iter().map(Into::into).collect(),
+            edges: Some(Edges {
+                keys: edge_keys.into_iter().map(Into::into).collect(),
+                edges,
+            }),
+        }
+    }
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+pub(crate) const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Opens a file for reading, or stdin for a path of `-`. With the `remote`
+/// feature, an `http://` or `https://` url is downloaded into memory.
+pub fn open_input(path: &Path) -> std::io::Result<Box<dyn Read>> {
+    if path.as_os_str() == "-" {
+        return Ok(Box::new(std::io::stdin().lock()));
+    }
+    if let Some(url) = input_url(path) {
+        #[cfg(feature = "remote")]
+        return Ok(Box::new(std::io::Cursor::new(
+            crate::remote::fetch_configured(url)?,
+        )));
+        #[cfg(not(feature = "remote"))]
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("reading {url} requires the remote feature"),
+        ));
+    }
+    Ok(Box::new(File::open(path)?))
+}
+
+/// The url of an input given as an `http://` or `https://` url
+pub fn input_url(path: &Path) -> Option<&str> {
+    path.to_str()
+        .filter(|path| path.starts_with("http://") || path.starts_with("https://"))
+}
+
+/// Reads a file, or stdin for a path of `-`, to the end
+pub fn read_input(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    open_input(path)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Opens a file, or stdin for a path of `-`, for reading, transparently
+/// decompressing gzip or zstd content based on its magic bytes.
+pub fn open_reader(path: &Path) -> Result<Box<dyn Read>> {
+    let mut reader = BufReader::new(open_input(path)?);
+    let magic = reader.fill_buf()?;
+    let (is_gzip, is_zstd) = (
+        magic.starts_with(&GZIP_MAGIC),
+        magic.starts_with(&ZSTD_MAGIC),
+    );
+    if is_gzip {
+        return Ok(Box::new(flate2::bufread::GzDecoder::new(reader)));
+    }
+    if is_zstd {
+        #[cfg(feature = "zstd")]
+        return Ok(Box::new(zstd::Decoder::with_buffer(reader)?));
+        #[cfg(not(feature = "zstd"))]
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "reading zstd compressed input requires the zstd feature",
+        )
+        .into());
+    }
+    Ok(Box::new(reader))
+}
+
+fn check_key(entry: impl FnOnce() -> String, key: &[u8]) -> Result {
+    PublicKey::try_from(key)
+        .map(|_| ())
+        .map_err(|_| Error::invalid_key(entry(), key))
+}
+
+fn key_string(key: &[u8]) -> String {
+    PublicKeyBinary::from(key).to_string()
+}
+
+fn non_empty(reason: &str) -> Option<String> {
+    if reason.is_empty() {
+        None
+    } else {
+        Some(reason.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{directed_edge_hash, edge_hash, test_util};
+
+    #[test]
+    fn borrowed_entries_match_owned_entries() {
+        let keys = test_util::keys(4);
+        let mut builder = DescriptorBuilder::default();
+        builder.add_node(keys[0].clone(), None, 0);
+        builder.add_edge(&keys[1], &keys[2], None, 0);
+        builder.add_directed_edge(&keys[3], &keys[1], None, 0);
+        let descriptor = builder.build();
+
+        let owned: Vec<Vec<u8>> = descriptor
+            .iter_keys()
+            .map(|key| AsRef::<[u8]>::as_ref(&key).to_vec())
+            .collect();
+        let borrowed: Vec<Vec<u8>> = descriptor.iter_key_bytes().map(<[u8]>::to_vec).collect();
+        assert_eq!(owned, borrowed);
+
+        let expected: Vec<u64> = descriptor
+            .iter_edges_directed()
+            .map(|(source, target, directed)| {
+                if directed {
+                    directed_edge_hash(&source, &target)
+                } else {
+                    edge_hash(&source, &target)
+                }
+            })
+            .collect();
+        let hashes: Vec<u64> = descriptor.iter_edge_hashes().collect();
+        assert_eq!(hashes, expected);
+        assert_eq!(
+            descriptor.iter_edge_bytes().count(),
+            descriptor.edge_count()
+        );
+    }
+
+    #[test]
+    fn event_log_replays_interleaved_events() {
+        let keys = test_util::keys(5);
+        let event = |action: &str, address: usize, target: Option<usize>, reason: &str| {
+            let mut event = serde_json::json!({
+                "action": action,
+                "address": keys[address].to_string(),
+                "reason": reason,
+            });
+            if let Some(target) = target {
+                event["target"] = keys[target].to_string().into();
+            }
+            event.to_string()
+        };
+        let log = [
+            event("block", 0, None, "first"),
+            event("block", 1, None, "first"),
+            event("block", 2, Some(3), "first"),
+            event("unblock", 0, None, ""),
+            event("block", 0, None, "again"),
+            String::new(),
+            event("unblock", 3, Some(2), ""),
+            event("block", 2, Some(4), "first"),
+            event("unblock", 1, None, ""),
+        ]
+        .join("\n");
+        let path = test_util::temp_path("event-log.jsonl");
+        std::fs::write(&path, log).unwrap();
+        let descriptor = Descriptor::from_event_log(&path);
+        std::fs::remove_file(&path).unwrap();
+        let descriptor = descriptor.unwrap();
+
+        assert_eq!(descriptor.key_count(), 1);
+        assert_eq!(
+            descriptor.find_node(&keys[0]).unwrap().reason.as_deref(),
+            Some("again")
+        );
+        assert!(!descriptor.contains(&keys[1]));
+        assert_eq!(descriptor.edge_count(), 1);
+        assert!(!descriptor.contains_edge(&keys[2], &keys[3]));
+        assert!(descriptor.contains_edge(&keys[4], &keys[2]));
+    }
+
+    #[test]
+    fn compressed_descriptors_load() {
+        use std::io::Write;
+        let descriptor = test_util::descriptor(10, 5);
+        let mut csv = vec![];
+        descriptor.to_csv(&mut csv).unwrap();
+        let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gzip.write_all(&csv).unwrap();
+        let mut inputs = vec![("plain", csv.clone()), ("gzip", gzip.finish().unwrap())];
+        #[cfg(feature = "zstd")]
+        inputs.push(("zstd", zstd::encode_all(csv.as_slice(), 0).unwrap()));
+        for (name, data) in inputs {
+            let path = test_util::temp_path(&format!("descriptor-{name}.csv"));
+            std::fs::write(&path, data).unwrap();
+            let loaded = Descriptor::from_csv(&path);
+            std::fs::remove_file(&path).unwrap();
+            let mut loaded_csv = vec![];
+            loaded.unwrap().to_csv(&mut loaded_csv).unwrap();
+            assert_eq!(loaded_csv, csv, "{name}");
+        }
+
+        let mut gzip = vec![];
+        descriptor.to_path(&mut gzip).unwrap();
+        assert!(gzip.starts_with(&GZIP_MAGIC));
+        for (name, data) in [("plain", descriptor.encode_to_vec()), ("gzip", gzip)] {
+            let path = test_util::temp_path(&format!("descriptor-{name}.bin"));
+            std::fs::write(&path, data).unwrap();
+            let loaded = Descriptor::from_path(&path);
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.unwrap(), descriptor, "{name}");
+        }
+    }
+
+    #[test]
+    fn formats_are_detected_from_the_content() {
+        let descriptor = test_util::descriptor(10, 5);
+        let mut csv = vec![];
+        descriptor.to_csv(&mut csv).unwrap();
+        let mut proto = vec![];
+        descriptor.to_path(&mut proto).unwrap();
+        let key = test_util::key().to_string();
+        let event = format!("{{\"action\":\"block\",\"address\":\"{key}\"}}\n");
+        let inputs = [
+            ("descriptor.bin", proto, "descriptor"),
+            ("descriptor.csv", csv.clone(), "csv"),
+            // the content wins over a misleading extension
+            ("csv.jsonl", csv, "csv"),
+            ("keys.txt", format!("{key}\n").into_bytes(), "csv"),
+            ("events.txt", event.into_bytes(), "event-log"),
+            ("keys.ndjson", format!("{key}\n").into_bytes(), "event-log"),
+        ];
+        for (name, data, expected) in inputs {
+            let path = test_util::temp_path(&format!("detect-{name}"));
+            std::fs::write(&path, data).unwrap();
+            let detected = DescriptorFile::detect(&path);
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(detected.unwrap().format_name(), expected, "{name}");
+        }
+
+        let path = test_util::temp_path("detect-array.json");
+        std::fs::write(&path, format!("[\"{key}\"]")).unwrap();
+        let detected = DescriptorFile::detect(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(detected.is_err());
+        assert!(DescriptorFile::detect(Path::new("-")).is_err());
+    }
+
+    #[test]
+    fn merge_strategies_resolve_conflicting_entries() {
+        let keys = test_util::keys(3);
+        let fragment = |reason: &str, carryover| {
+            let mut builder = DescriptorBuilder::default();
+            builder.add_node(keys[0].clone(), Some(reason.to_string()), carryover);
+            builder.add_edge(&keys[1], &keys[2], Some(reason.to_string()), carryover);
+            builder.build()
+        };
+        // the second fragment lists the same undirected edge the other way
+        // round
+        let mut second = DescriptorBuilder::default();
+        second.add_node(keys[0].clone(), Some("second".to_string()), 1);
+        second.add_edge(&keys[2], &keys[1], Some("second".to_string()), 1);
+        let (first, second) = (fragment("first", 0), second.build());
+
+        let merged = |strategy| {
+            let mut builder = DescriptorBuilder::default();
+            assert_eq!(builder.merge_descriptor(&first, strategy).unwrap(), 0);
+            builder
+                .merge_descriptor(&second, strategy)
+                .map(|conflicts| (conflicts, builder.build()))
+        };
+        for (strategy, expected) in [
+            (MergeStrategy::First, &first),
+            (MergeStrategy::Last, &second),
+        ] {
+            let (conflicts, descriptor) = merged(strategy).unwrap();
+            assert_eq!(conflicts, 2, "{strategy:?}");
+            assert_eq!(&descriptor, expected, "{strategy:?}");
+        }
+        assert!(matches!(
+            merged(MergeStrategy::Error),
+            Err(Error::MergeConflict(_))
+        ));
+        // agreeing entries are not conflicts under any strategy
+        let mut builder = DescriptorBuilder::default();
+        builder
+            .merge_descriptor(&first, MergeStrategy::Error)
+            .unwrap();
+        assert_eq!(
+            builder
+                .merge_descriptor(&fragment("first", 0), MergeStrategy::Error)
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn malformed_keys_are_reported_with_their_entry() {
+        let malformed = vec![1, 2, 3];
+        let mut descriptor = test_util::descriptor(2, 1);
+        assert!(descriptor.validate().is_empty());
+        descriptor.nodes[1].key = malformed.clone();
+        let diagnostics = descriptor.validate();
+        assert!(matches!(
+            diagnostics.as_slice(),
+            [diagnostic @ DescriptorDiagnostic::InvalidNodeKey { index: 1, .. }]
+                if diagnostic.is_error()
+        ));
+
+        let mut descriptor = test_util::descriptor(0, 1);
+        if let Some(edges) = descriptor.edges.as_mut() {
+            edges.keys[0] = malformed.clone();
+        }
+        let diagnostics = descriptor.validate();
+        assert!(matches!(
+            diagnostics.as_slice(),
+            [diagnostic @ DescriptorDiagnostic::InvalidEdgeKey { index: 0, .. }]
+                if diagnostic.is_error()
+        ));
+
+        let malformed = key_string(&malformed);
+        let csv = format!("{},,,0\n{malformed},,,0\n", test_util::key());
+        let path = test_util::temp_path("malformed.csv");
+        std::fs::write(&path, csv).unwrap();
+        let loaded = Descriptor::from_csv(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(
+            loaded,
+            Err(Error::InvalidKey { entry, key }) if entry == "line 2" && key == malformed
+        ));
+    }
+
+    #[test]
+    fn csv_export_round_trips() {
+        let keys = test_util::keys(4);
+        let mut builder = DescriptorBuilder::default();
+        builder.add_node(keys[0].clone(), Some("gaming".to_string()), 2);
+        builder.add_node(keys[1].clone(), None, 0);
+        builder.add_edge(&keys[1], &keys[2], Some("cluster, east".to_string()), 1);
+        builder.add_directed_edge(&keys[3], &keys[0], None, 0);
+        let mut csv = vec![];
+        builder.build().to_csv(&mut csv).unwrap();
+
+        let csv_path = test_util::temp_path("export.csv");
+        let descriptor_path = test_util::temp_path("export.bin.gz");
+        std::fs::write(&csv_path, &csv).unwrap();
+        let descriptor = Descriptor::from_csv(&csv_path).unwrap();
+        descriptor
+            .to_path(std::fs::File::create(&descriptor_path).unwrap())
+            .unwrap();
+        let loaded = Descriptor::from_path(&descriptor_path).unwrap();
+        std::fs::remove_file(&csv_path).unwrap();
+        std::fs::remove_file(&descriptor_path).unwrap();
+        assert_eq!(loaded, descriptor);
+
+        let mut exported = vec![];
+        loaded.to_csv(&mut exported).unwrap();
+        assert_eq!(
+            String::from_utf8(exported).unwrap(),
+            String::from_utf8(csv).unwrap()
+        );
+    }
+
+    #[test]
+    fn contains_is_exact() {
+        let keys = test_util::keys(5);
+        let mut builder = DescriptorBuilder::default();
+        builder.add_node(keys[0].clone(), None, 0);
+        builder.add_edge(&keys[1], &keys[2], None, 0);
+        builder.add_directed_edge(&keys[3], &keys[1], None, 0);
+        let descriptor = builder.build();
+
+        assert!(descriptor.contains(&keys[0]));
+        // edge keys and unknown keys are not full nodes
+        assert!(!descriptor.contains(&keys[1]));
+        assert!(!descriptor.contains(&keys[4]));
+
+        assert!(descriptor.contains_edge(&keys[1], &keys[2]));
+        assert!(descriptor.contains_edge(&keys[2], &keys[1]));
+        assert!(descriptor.contains_edge(&keys[3], &keys[1]));
+        assert!(!descriptor.contains_edge(&keys[1], &keys[3]));
+        assert!(!descriptor.contains_edge(&keys[2], &keys[3]));
+        assert!(!descriptor.contains_edge(&keys[1], &keys[4]));
+    }
+}
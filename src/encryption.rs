@@ -0,0 +1,107 @@
+//! Passphrase based encryption of manifest files at rest.
+//!
+//! An encrypted file is the magic prefix, a random argon2 salt and AES-GCM
+//! nonce, followed by the ciphertext. Only the file container is protected,
+//! the signed filter data is unchanged.
+
+use crate::{Error, Result};
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Key, Nonce,
+};
+use argon2::Argon2;
+use rand::RngCore;
+use zeroize::Zeroizing;
+
+const MAGIC: &[u8] = b"XORFENC1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+pub fn is_sealed(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+pub fn seal(plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let ciphertext = cipher(passphrase, &salt)?
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|_| Error::encryption("failed to encrypt"))?;
+
+    let mut sealed = Vec::with_capacity(MAGIC.len() + SALT_LEN + NONCE_LEN + ciphertext.len());
+    sealed.extend_from_slice(MAGIC);
+    sealed.extend_from_slice(&salt);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+/// Decrypts a sealed file. The plaintext is zeroed when dropped.
+pub fn open(data: &[u8], passphrase: &str) -> Result<Zeroizing<Vec<u8>>> {
+    let data = data
+        .strip_prefix(MAGIC)
+        .ok_or_else(|| Error::encryption("not an encrypted file"))?;
+    if data.len() < SALT_LEN + NONCE_LEN {
+        return Err(Error::encryption("truncated encrypted file"));
+    }
+    let (salt, data) = data.split_at(SALT_LEN);
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    cipher(passphrase, salt)?
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map(Zeroizing::new)
+        .map_err(|_| Error::encryption("wrong passphrase or corrupted file"))
+}
+
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<Aes256Gcm> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(|err| Error::encryption(&err.to_string()))?;
+    Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key.as_ref())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opened_plaintext_is_zeroizing() {
+        let sealed = seal(b"{}", "passphrase").unwrap();
+        let plaintext: Zeroizing<Vec<u8>> = open(&sealed, "passphrase").unwrap();
+        assert_eq!(plaintext.as_slice(), b"{}");
+    }
+
+    #[test]
+    fn sealed_data_round_trips() {
+        let sealed = seal(b"manifest", "passphrase").unwrap();
+        assert!(is_sealed(&sealed));
+        assert!(!sealed.windows(8).any(|window| window == b"manifest"));
+        assert_eq!(open(&sealed, "passphrase").unwrap().as_slice(), b"manifest");
+        assert!(open(&sealed, "wrong").is_err());
+        assert!(open(&sealed[..MAGIC.len() + SALT_LEN], "passphrase").is_err());
+    }
+
+    #[test]
+    fn encrypted_manifest_round_trips() {
+        use crate::{test_util, Filter, FilterOptions, Manifest};
+        let descriptor = test_util::descriptor(3, 1);
+        let filter = Filter::from_descriptor(5, &descriptor, &FilterOptions::default()).unwrap();
+        let (_, key_manifest) = test_util::signers(2, 1);
+        let manifest = Manifest::from_filter(&filter, &key_manifest).unwrap();
+
+        let path = test_util::temp_path("encrypted-manifest");
+        std::fs::write(&path, manifest.to_encrypted_bytes("passphrase").unwrap()).unwrap();
+        let decrypted = Manifest::from_encrypted_path(&path, "passphrase");
+        let wrong = Manifest::from_encrypted_path(&path, "wrong");
+        std::fs::remove_file(&path).unwrap();
+
+        let decrypted = decrypted.unwrap();
+        assert_eq!(decrypted.serial, manifest.serial);
+        assert_eq!(decrypted.hash, manifest.hash);
+        assert_eq!(decrypted.signatures.len(), 2);
+        assert!(wrong.is_err());
+    }
+}
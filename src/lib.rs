@@ -1,3 +1,28 @@
+//! Generates and verifies signed xor filters of denied hotspots and edges.
+//!
+//! The library does not write any files itself, every step produces a value
+//! that the caller can store wherever it likes:
+//!
+//! 1. A [`Descriptor`] lists the denied keys and edges. It is read with
+//!    [`Descriptor::from_csv`], built in memory with a [`DescriptorBuilder`],
+//!    or loaded from any [`DescriptorSource`], such as a [`DescriptorFile`]
+//!    or a source of your own.
+//! 2. [`Filter::from_descriptor`] builds the unsigned filter for a serial. Its
+//!    [`Filter::to_signing_bytes`] are the data the multisig members sign.
+//! 3. [`Manifest::from_filter`] creates the manifest with an empty signature
+//!    slot for every member of a [`PublicKeyManifest`].
+//! 4. Once enough signatures are collected, [`Filter::apply_manifest`] adds
+//!    the combined signature and [`Filter::to_bytes`] returns the final
+//!    filter.
+//!
+//! When the signatures are collected before the filter is built, as in a
+//! service that holds the descriptor and the signed manifest,
+//! [`build_signed_filter`] does steps 2 and 4 in one go.
+//!
+//! Consumers verify a received filter with [`Filter::verify`]. Services that
+//! verify many filters keep a [`FilterVerifier`], which derives the multisig
+//! key of a [`PublicKeyManifest`] once.
+
 pub type Result<T = ()> = std::result::Result<T, Error>;
 
 #[derive(Debug, thiserror::Error)]
@@ -18,27 +43,108 @@ pub enum Error {
     Crypto(#[from] helium_crypto::Error),
     #[error("filter: {0}")]
     Filter(String),
+    #[error("encryption: {0}")]
+    Encryption(String),
+    #[error("manifest: {0}")]
+    Manifest(String),
+    #[error(
+        "unsupported filter version {0}, this build supports versions 1 to {max}; \
+         upgrade xorf-generator to read it",
+        max = FILTTER_VERSION
+    )]
+    UnsupportedVersion(u8),
+    #[error(
+        "unsupported filter format version {0}, this build supports format versions 0 to {max}; \
+         upgrade xorf-generator to read it",
+        max = FILTER_FORMAT_VERSION
+    )]
+    UnsupportedFormat(u8),
+    #[error(
+        "unsupported signature scheme {0}, this build only supports the multisig scheme {multisig}; \
+         upgrade xorf-generator to verify it",
+        multisig = SIG_SCHEME_MULTISIG
+    )]
+    UnsupportedScheme(u8),
+    #[error("cbor: {0}")]
+    Cbor(String),
+    #[error("invalid public key {key} at {entry}")]
+    InvalidKey { entry: String, key: String },
+    #[error("signature is well formed but does not match public key {0}")]
+    SignatureMismatch(String),
+    #[error("malformed signature: {0}")]
+    MalformedSignature(String),
+    #[error("merge conflict: {0}")]
+    MergeConflict(String),
 }
 
 impl Error {
     pub fn filter(err: &str) -> Self {
         Self::Filter(err.to_string())
     }
+
+    pub fn encryption(err: &str) -> Self {
+        Self::Encryption(err.to_string())
+    }
+
+    pub fn manifest(err: &str) -> Self {
+        Self::Manifest(err.to_string())
+    }
+
+    pub fn invalid_key(entry: String, key: &[u8]) -> Self {
+        Self::InvalidKey {
+            entry,
+            key: helium_crypto::PublicKeyBinary::from(key).to_string(),
+        }
+    }
 }
 
 mod filter;
-pub use filter::{edge_hash, edge_order, public_key_hash, Filter, FILTTER_VERSION};
+pub use filter::{
+    build_signed_filter, build_signed_filter_with_stats, directed_edge_hash, edge_hash, edge_order,
+    public_key_hash, Entries, Filter, FilterBackend, FilterCompression, FilterData, FilterOptions,
+    FilterParams, FilterStats, FilterVerifier, MembershipFilter, DEFAULT_FINGERPRINT_BITS,
+    DEFAULT_MIN_ENTRIES, ENTRIES_HASH_LEN, FILTER_FORMAT_VERSION, FILTER_MAGIC, FILTTER_VERSION,
+    PROGRESS_INTERVAL, SIG_SCHEME_MULTISIG,
+};
+
+mod chunk_tree;
+pub use chunk_tree::{ChunkChange, ChunkTree};
 
 mod manifest;
 pub use manifest::{
-    Manifest, ManifestAddres, ManifestSignature, ManifestSignatureVerify, PublicKeyManifest,
+    EmbeddedManifest, Manifest, ManifestAddres, ManifestSignature, ManifestSignatureVerify,
+    PublicKeyManifest,
+};
+
+#[cfg(feature = "encryption")]
+mod encryption;
+
+#[cfg(feature = "remote")]
+mod remote;
+#[cfg(feature = "remote")]
+pub use remote::{
+    fetch, set_remote_options, RemoteOptions, DEFAULT_REMOTE_TIMEOUT, DEFAULT_RETRY_DELAY,
 };
 
+#[cfg(feature = "cache")]
+mod cache;
+#[cfg(feature = "cache")]
+pub use cache::CachedFilter;
+
 mod descriptor;
-pub use descriptor::{Descriptor, Edges};
+pub use descriptor::{
+    input_url, open_input, open_reader, read_input, Descriptor, DescriptorBuilder,
+    DescriptorDiagnostic, DescriptorFile, DescriptorSource, Edges, MergeStrategy,
+};
 
 pub use xorf;
 
+#[cfg(test)]
+mod test_util;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
 pub mod base64_serde {
     use base64::{engine::general_purpose::STANDARD, Engine};
     use serde::{de, Deserialize, Deserializer, Serializer};
@@ -1,11 +1,43 @@
 use anyhow::Result;
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use std::path::PathBuf;
 mod cmd;
 
 #[derive(Debug, Parser)]
 #[command(version = env!("CARGO_PKG_VERSION"))]
 #[command(name = env!("CARGO_BIN_NAME"))]
 pub struct Cli {
+    /// Also write all json results to this file
+    #[arg(long, global = true)]
+    json_output_file: Option<PathBuf>,
+    /// The octal file mode to create output files with, for example 640.
+    /// Only honored on unix
+    #[arg(long, global = true, value_parser = cmd::parse_permissions)]
+    output_permissions: Option<u32>,
+    /// The format to print results in
+    #[arg(long, global = true, value_enum, default_value_t = cmd::OutputFormat::Json)]
+    output_format: cmd::OutputFormat,
+    /// The timeout in seconds of downloading an input given as an http or
+    /// https url
+    #[cfg(feature = "remote")]
+    #[arg(long, global = true, default_value_t = 30)]
+    timeout: u64,
+    /// The hex sha256 checksum inputs downloaded from a url must match.
+    /// Given more than once, every download must match one of them
+    #[cfg(feature = "remote")]
+    #[arg(long, global = true, value_parser = cmd::parse_sha256)]
+    sha256: Vec<[u8; 32]>,
+    /// How often to retry a download that failed with a connection error,
+    /// a server error or a truncated body. Client errors such as a 404 fail
+    /// right away
+    #[cfg(feature = "remote")]
+    #[arg(long, global = true, default_value_t = 0)]
+    retries: u32,
+    /// The delay in seconds before the first retry of a download, doubled
+    /// for every further retry
+    #[cfg(feature = "remote")]
+    #[arg(long, global = true, default_value_t = 1)]
+    retry_delay: u64,
     #[command(subcommand)]
     cmd: Cmd,
 }
@@ -13,23 +45,48 @@ pub struct Cli {
 #[derive(Debug, clap::Subcommand)]
 pub enum Cmd {
     Descriptor(cmd::descriptor::Cmd),
+    Examples(cmd::examples::Cmd),
     Data(cmd::data::Cmd),
     Filter(cmd::filter::Cmd),
+    #[command(alias = "public-key")]
     Key(cmd::key::Cmd),
     Manifest(cmd::manifest::Cmd),
+    Selftest(cmd::selftest::Cmd),
+    #[cfg(feature = "schema")]
+    Validate(cmd::validate::Cmd),
 }
 
 fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+    let command = cmd::config::apply_defaults(Cli::command())?;
+    let cli = Cli::from_arg_matches(&command.get_matches()).unwrap_or_else(|err| err.exit());
     run(cli)
 }
 
 fn run(cli: Cli) -> Result<()> {
+    if let Some(mode) = cli.output_permissions {
+        cmd::set_output_permissions(mode)?;
+    }
+    cmd::set_output_format(cli.output_format)?;
+    #[cfg(feature = "remote")]
+    xorf_generator::set_remote_options(xorf_generator::RemoteOptions {
+        timeout: std::time::Duration::from_secs(cli.timeout),
+        sha256: cli.sha256,
+        retries: cli.retries,
+        retry_delay: std::time::Duration::from_secs(cli.retry_delay),
+        on_retry: Some(cmd::log_retry),
+    })?;
+    if let Some(path) = &cli.json_output_file {
+        cmd::tee_json_output(path)?;
+    }
     match cli.cmd {
         Cmd::Data(cmd) => cmd.run(),
         Cmd::Descriptor(cmd) => cmd.run(),
+        Cmd::Examples(cmd) => cmd.run(),
         Cmd::Filter(cmd) => cmd.run(),
         Cmd::Key(cmd) => cmd.run(),
         Cmd::Manifest(cmd) => cmd.run(),
+        Cmd::Selftest(cmd) => cmd.run(),
+        #[cfg(feature = "schema")]
+        Cmd::Validate(cmd) => cmd.run(),
     }
 }
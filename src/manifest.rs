@@ -1,23 +1,164 @@
-use crate::{base64_serde, Result};
-use helium_crypto::{multihash, multisig, Network, PublicKey, Verify};
+use crate::{
+    base64_serde, open_input, read_input, ChunkTree, Error, Filter, Result, SIG_SCHEME_MULTISIG,
+};
+use helium_crypto::{multihash, multisig, Keypair, Network, PublicKey, Sign, Verify};
 use serde::{Deserialize, Serialize};
-use std::{fs::File, io::BufReader, ops::Deref, path::Path};
+use sha2::{Digest, Sha256};
+use std::{io::BufReader, ops::Deref, path::Path};
 
 #[derive(Deserialize, Serialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Manifest {
     pub serial: u32,
     pub hash: String,
     pub signatures: Vec<ManifestSignature>,
+    /// Base64 hash of the descriptor entries, for filters that include one in
+    /// their signing bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entries_hash: Option<String>,
+    /// The signature scheme the members sign with, the multisig scheme for
+    /// manifests that predate the field
+    #[serde(default)]
+    pub sig_scheme: u8,
+    /// Merkle tree over chunks of the signing bytes, for manifests generated
+    /// with a chunk size
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_tree: Option<ChunkTree>,
 }
 
 impl Manifest {
+    /// Creates an unsigned manifest for the given filter with a signature slot
+    /// for each member of the given public key.
+    pub fn from_filter(filter: &Filter, key_manifest: &PublicKeyManifest) -> Result<Self> {
+        Ok(Self {
+            serial: filter.serial,
+            hash: base64_serde::encode(&filter.hash()?),
+            signatures: key_manifest.signature_slots(),
+            entries_hash: filter.entries_hash.as_deref().map(base64_serde::encode),
+            sig_scheme: filter.sig_scheme,
+            chunk_tree: None,
+        })
+    }
+
     pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let file = File::open(path)?;
-        let manifest = serde_json::from_reader(BufReader::new(file))?;
-        Ok(manifest)
+        Self::from_slice(&read_input(path.as_ref())?)
+    }
+
+    /// Decodes a json manifest, or a cbor manifest when built with the cbor
+    /// feature. Json manifests are recognized by their leading `{`.
+    pub fn from_slice(data: &[u8]) -> Result<Self> {
+        #[cfg(feature = "cbor")]
+        if !is_json(data) {
+            return ciborium::from_reader(data).map_err(|err| Error::Cbor(err.to_string()));
+        }
+        Ok(serde_json::from_slice(data)?)
+    }
+
+    #[cfg(feature = "cbor")]
+    pub fn to_cbor(&self) -> Result<Vec<u8>> {
+        let mut data = vec![];
+        ciborium::into_writer(self, &mut data).map_err(|err| Error::Cbor(err.to_string()))?;
+        Ok(data)
+    }
+
+    /// Reads a manifest that may have been encrypted with the given passphrase.
+    /// Plaintext manifests are read as is.
+    #[cfg(feature = "encryption")]
+    pub fn from_encrypted_path<P: AsRef<Path>>(path: P, passphrase: &str) -> Result<Self> {
+        let data = read_input(path.as_ref())?;
+        let data = if crate::encryption::is_sealed(&data) {
+            crate::encryption::open(&data, passphrase)?
+        } else {
+            zeroize::Zeroizing::new(data)
+        };
+        Self::from_slice(&data)
+    }
+
+    #[cfg(feature = "encryption")]
+    pub fn to_encrypted_bytes(&self, passphrase: &str) -> Result<Vec<u8>> {
+        let json = serde_json::to_vec_pretty(self)?;
+        crate::encryption::seal(&json, passphrase)
+    }
+
+    /// Sorts the signatures by signer address, so a manifest serializes the
+    /// same regardless of the member order in the public key manifest
+    pub fn sort_signatures(&mut self) {
+        sort_signatures(&mut self.signatures);
+    }
+
+    /// Adds a signature made outside of this crate to the slot of the given
+    /// signer. The signature must verify against the given signing bytes.
+    pub fn add_signature(
+        &mut self,
+        signer: &PublicKey,
+        signature: Vec<u8>,
+        signing_bytes: &[u8],
+        signed_at: Option<String>,
+    ) -> Result {
+        let slot = self
+            .signatures
+            .iter_mut()
+            .find(|ms| &ms.address.0 == signer)
+            .ok_or_else(|| Error::Manifest(format!("no signature slot for {signer}")))?;
+        let candidate = ManifestSignature {
+            address: slot.address.clone(),
+            signature,
+            signed_at,
+        };
+        if !candidate.verify(signing_bytes).is_verified() {
+            return Err(Error::Manifest(format!(
+                "signature does not verify for {signer}"
+            )));
+        }
+        *slot = candidate;
+        Ok(())
+    }
+
+    /// Signs the given signing bytes with each of the given keys that has an
+    /// empty slot in this manifest, leaving the other slots as they are.
+    /// Returns the number of slots that were filled.
+    pub fn sign_with(&mut self, signing_bytes: &[u8], keys: &[Keypair]) -> Result<usize> {
+        let hash = base64_serde::encode(&Sha256::digest(signing_bytes));
+        if hash != self.hash {
+            return Err(Error::Manifest(format!(
+                "signing bytes hash {hash} does not match manifest hash {}",
+                self.hash
+            )));
+        }
+        let mut filled = 0;
+        for slot in self.signatures.iter_mut().filter(|ms| !ms.is_signed()) {
+            let Some(keypair) = keys.iter().find(|k| k.public_key() == &slot.address.0) else {
+                continue;
+            };
+            slot.signature = keypair.sign(signing_bytes)?;
+            filled += 1;
+        }
+        Ok(filled)
+    }
+
+    /// Returns the members of the given public key whose signatures in this
+    /// manifest verify against the signing bytes of the given filter
+    pub fn signers(
+        &self,
+        filter: &Filter,
+        key_manifest: &PublicKeyManifest,
+    ) -> Result<Vec<PublicKey>> {
+        let signing_bytes = filter.to_signing_bytes()?;
+        let members = key_manifest.public_keys();
+        let signers = self
+            .signatures
+            .iter()
+            .filter(|ms| members.contains(&ms.address.0))
+            .filter(|ms| ms.verify(&signing_bytes).is_verified())
+            .map(|ms| ms.address.0.clone())
+            .collect();
+        Ok(signers)
     }
 
     pub fn sign(&self, key_manifest: &PublicKeyManifest) -> Result<Vec<u8>> {
+        if self.sig_scheme != SIG_SCHEME_MULTISIG {
+            return Err(Error::UnsupportedScheme(self.sig_scheme));
+        }
         let public_key = key_manifest.public_key()?;
         let keys = key_manifest.public_keys();
         let signatures: Vec<(PublicKey, Vec<u8>)> = self
@@ -32,19 +173,59 @@ impl Manifest {
     }
 }
 
+/// A manifest together with the public key of its signers, as embedded in a
+/// filter for single file distribution
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmbeddedManifest {
+    pub manifest: Manifest,
+    pub public_key: PublicKeyManifest,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct PublicKeyManifest {
     pub public_keys: Vec<ManifestAddres>,
     pub required: u8,
 }
 
 impl PublicKeyManifest {
+    /// A public key of the given members that requires the given number of
+    /// signatures
+    pub fn new(public_keys: Vec<PublicKey>, required: u8) -> Self {
+        Self {
+            public_keys: public_keys.into_iter().map(ManifestAddres).collect(),
+            required,
+        }
+    }
+
     pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let file = File::open(path)?;
-        let manifest = serde_json::from_reader(BufReader::new(file))?;
+        let manifest = serde_json::from_reader(BufReader::new(open_input(path.as_ref())?))?;
         Ok(manifest)
     }
 
+    /// The members of the signature slots of a prior manifest, requiring the
+    /// given number of signatures. Manifests do not record the threshold, so
+    /// it has to be given.
+    pub fn from_signers(manifest: &Manifest, required: u8) -> Result<Self> {
+        let mut public_keys: Vec<PublicKey> = manifest
+            .signatures
+            .iter()
+            .map(|signature| signature.address().clone())
+            .collect();
+        let members = public_keys.len();
+        public_keys.sort_by_cached_key(|key| key.to_string());
+        public_keys.dedup();
+        if public_keys.len() != members {
+            return Err(Error::manifest("manifest lists a member more than once"));
+        }
+        if required == 0 || required as usize > members {
+            return Err(Error::Manifest(format!(
+                "threshold {required} must be between 1 and the {members} manifest members"
+            )));
+        }
+        Ok(Self::new(public_keys, required))
+    }
+
     pub fn public_key(&self) -> Result<PublicKey> {
         let public_keys: Vec<PublicKey> =
             self.public_keys.iter().map(|k| k.deref().clone()).collect();
@@ -60,16 +241,46 @@ impl PublicKeyManifest {
     pub fn public_keys(&self) -> Vec<PublicKey> {
         self.public_keys.iter().map(|addr| addr.0.clone()).collect()
     }
+
+    /// Unsigned manifest signature entries for each of the member keys, in
+    /// canonical order
+    pub fn signature_slots(&self) -> Vec<ManifestSignature> {
+        let mut slots: Vec<ManifestSignature> = self
+            .public_keys
+            .iter()
+            .map(ManifestSignature::from)
+            .collect();
+        sort_signatures(&mut slots);
+        slots
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ManifestSignature {
     address: ManifestAddres,
     #[serde(with = "base64_serde")]
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
     signature: Vec<u8>,
+    /// RFC3339 time the signature was added. This is audit metadata only and
+    /// is not covered by any signature.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signed_at: Option<String>,
 }
 
 impl ManifestSignature {
+    pub fn address(&self) -> &PublicKey {
+        &self.address.0
+    }
+
+    pub fn is_signed(&self) -> bool {
+        !self.signature.is_empty()
+    }
+
+    pub fn signed_at(&self) -> Option<&str> {
+        self.signed_at.as_deref()
+    }
+
     pub fn verify(&self, msg: &[u8]) -> ManifestSignatureVerify {
         ManifestSignatureVerify {
             signature: self.clone(),
@@ -78,8 +289,24 @@ impl ManifestSignature {
     }
 }
 
+#[cfg(feature = "cbor")]
+fn is_json(data: &[u8]) -> bool {
+    data.iter()
+        .find(|byte| !byte.is_ascii_whitespace())
+        .map_or(true, |byte| *byte == b'{')
+}
+
+fn sort_signatures(signatures: &mut [ManifestSignature]) {
+    signatures.sort_by_cached_key(|signature| signature.address.to_string());
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct ManifestAddres(#[serde(with = "public_key")] PublicKey);
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ManifestAddres(
+    #[serde(with = "public_key")]
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
+    PublicKey,
+);
 
 impl Deref for ManifestAddres {
     type Target = PublicKey;
@@ -99,6 +326,7 @@ impl From<&ManifestAddres> for ManifestSignature {
         Self {
             address: val.clone(),
             signature: vec![],
+            signed_at: None,
         }
     }
 }
@@ -110,6 +338,16 @@ pub struct ManifestSignatureVerify {
     verified: bool,
 }
 
+impl ManifestSignatureVerify {
+    pub fn is_signed(&self) -> bool {
+        self.signature.is_signed()
+    }
+
+    pub fn is_verified(&self) -> bool {
+        self.verified
+    }
+}
+
 mod public_key {
     use helium_crypto::PublicKey;
     use serde::{de, Deserialize, Deserializer, Serializer};
@@ -135,3 +373,129 @@ mod public_key {
         s.serialize_str(&public_key.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+
+    #[test]
+    fn signed_at_round_trips() {
+        let filter = test_util::filter(1);
+        let (keypairs, key_manifest) = test_util::signers(2, 1);
+        let signing_bytes = filter.to_signing_bytes().unwrap();
+        let signer = keypairs[0].public_key();
+        let mut manifest = Manifest::from_filter(&filter, &key_manifest).unwrap();
+        manifest
+            .add_signature(
+                signer,
+                keypairs[0].sign(&signing_bytes).unwrap(),
+                &signing_bytes,
+                Some("2024-05-01T12:00:00Z".to_string()),
+            )
+            .unwrap();
+
+        let json = serde_json::to_vec(&manifest).unwrap();
+        let decoded = Manifest::from_slice(&json).unwrap();
+        for signature in &decoded.signatures {
+            if signature.address() == signer {
+                assert_eq!(signature.signed_at(), Some("2024-05-01T12:00:00Z"));
+                assert!(signature.verify(&signing_bytes).is_verified());
+            } else {
+                assert_eq!(signature.signed_at(), None);
+            }
+        }
+        assert_eq!(serde_json::to_vec(&decoded).unwrap(), json);
+    }
+
+    #[test]
+    fn signature_order_does_not_matter() {
+        let mut filter = test_util::filter(1);
+        let (keypairs, key_manifest) = test_util::signers(3, 2);
+        let manifest = test_util::sign(&mut filter, &keypairs, &key_manifest);
+        let json = serde_json::to_vec(&manifest).unwrap();
+        let mut reordered = Manifest::from_slice(&json).unwrap();
+        reordered.signatures.reverse();
+        assert_ne!(serde_json::to_vec(&reordered).unwrap(), json);
+
+        let mut signers = manifest.signers(&filter, &key_manifest).unwrap();
+        let mut reordered_signers = reordered.signers(&filter, &key_manifest).unwrap();
+        signers.sort_by_cached_key(|key| key.to_string());
+        reordered_signers.sort_by_cached_key(|key| key.to_string());
+        assert_eq!(signers, reordered_signers);
+        filter.apply_manifest(&reordered, &key_manifest).unwrap();
+        filter.verify(&key_manifest.public_key().unwrap()).unwrap();
+
+        reordered.sort_signatures();
+        assert_eq!(serde_json::to_vec(&reordered).unwrap(), json);
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn cbor_round_trips() {
+        let mut filter = test_util::filter(2);
+        let (keypairs, key_manifest) = test_util::signers(2, 2);
+        let manifest = test_util::sign(&mut filter, &keypairs, &key_manifest);
+        let cbor = manifest.to_cbor().unwrap();
+        assert!(!is_json(&cbor));
+        let decoded = Manifest::from_slice(&cbor).unwrap();
+        assert_eq!(
+            serde_json::to_vec(&decoded).unwrap(),
+            serde_json::to_vec(&manifest).unwrap()
+        );
+        assert_eq!(decoded.signers(&filter, &key_manifest).unwrap().len(), 2);
+
+        // filters are carried in cbor as a byte string of their encoding
+        let mut carried = vec![];
+        ciborium::into_writer(
+            &ciborium::Value::Bytes(filter.to_bytes().unwrap()),
+            &mut carried,
+        )
+        .unwrap();
+        let value: ciborium::Value = ciborium::from_reader(carried.as_slice()).unwrap();
+        let ciborium::Value::Bytes(bytes) = value else {
+            panic!("expected a cbor byte string");
+        };
+        let decoded = Filter::from_bytes(&bytes).unwrap();
+        decoded.verify(&key_manifest.public_key().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn partial_signing_fills_only_the_given_keys() {
+        let mut filter = test_util::filter(4);
+        let (keypairs, key_manifest) = test_util::signers(3, 2);
+        let public_key = key_manifest.public_key().unwrap();
+        let signing_bytes = filter.to_signing_bytes().unwrap();
+        let mut manifest = Manifest::from_filter(&filter, &key_manifest).unwrap();
+
+        assert_eq!(
+            manifest.sign_with(&signing_bytes, &keypairs[..1]).unwrap(),
+            1
+        );
+        let signers = manifest.signers(&filter, &key_manifest).unwrap();
+        assert_eq!(signers, [keypairs[0].public_key().clone()]);
+
+        // already signed slots are kept rather than signed again
+        let signature_of = |manifest: &Manifest, keypair: &Keypair| {
+            manifest
+                .signatures
+                .iter()
+                .find(|ms| ms.address() == keypair.public_key())
+                .map(|ms| ms.signature.clone())
+                .unwrap()
+        };
+        let first = signature_of(&manifest, &keypairs[0]);
+        assert_eq!(
+            manifest.sign_with(&signing_bytes, &keypairs[..2]).unwrap(),
+            1
+        );
+        assert_eq!(signature_of(&manifest, &keypairs[0]), first);
+        assert!(signature_of(&manifest, &keypairs[2]).is_empty());
+        assert_eq!(manifest.signers(&filter, &key_manifest).unwrap().len(), 2);
+        filter.apply_manifest(&manifest, &key_manifest).unwrap();
+        filter.verify(&public_key).unwrap();
+
+        let mut other = Manifest::from_filter(&test_util::filter(4), &key_manifest).unwrap();
+        assert!(other.sign_with(&signing_bytes, &keypairs).is_err());
+    }
+}
@@ -0,0 +1,297 @@
+//! Reading inputs from http and https urls.
+//!
+//! Downloads are read into memory in full. A download that ends before its
+//! announced length, or that does not match any of the configured sha256
+//! checksums, is an error rather than input that happens to parse. Failed
+//! downloads can be retried with exponential backoff, see
+//! [`RemoteOptions::retries`].
+
+use sha2::{Digest, Sha256};
+use std::{
+    io::{self, Read},
+    sync::OnceLock,
+    thread,
+    time::Duration,
+};
+
+/// The timeout of a download when none is configured
+pub const DEFAULT_REMOTE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The delay before the first retry of a failed download when none is
+/// configured
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Options for downloading an input
+#[derive(Debug, Clone)]
+pub struct RemoteOptions {
+    /// The timeout of the whole download
+    pub timeout: Duration,
+    /// Checksums a download must match one of. Downloads are not checked
+    /// when empty.
+    pub sha256: Vec<[u8; 32]>,
+    /// How often a download that failed with a transient error is retried.
+    /// Connection errors, server errors and truncated downloads are
+    /// transient, client errors such as a 404 and checksum mismatches fail
+    /// right away.
+    pub retries: u32,
+    /// The delay before the first retry, doubled for every further retry
+    pub retry_delay: Duration,
+    /// Called before every retry with the url, the number of the failed
+    /// attempt, its error and the delay until the next attempt
+    pub on_retry: Option<fn(&str, u32, &io::Error, Duration)>,
+}
+
+impl Default for RemoteOptions {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_REMOTE_TIMEOUT,
+            sha256: vec![],
+            retries: 0,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            on_retry: None,
+        }
+    }
+}
+
+/// A failed download attempt and whether trying again may succeed
+struct Failure {
+    error: io::Error,
+    transient: bool,
+}
+
+impl Failure {
+    fn transient(error: io::Error) -> Self {
+        Self {
+            error,
+            transient: true,
+        }
+    }
+}
+
+static OPTIONS: OnceLock<RemoteOptions> = OnceLock::new();
+
+/// Sets the options [`crate::open_input`] downloads urls with. They can only
+/// be set once, before the first download.
+pub fn set_remote_options(options: RemoteOptions) -> crate::Result {
+    OPTIONS
+        .set(options)
+        .map_err(|_| crate::Error::Io(io::Error::other("remote options already set")))
+}
+
+/// Downloads the given url into memory, retrying transient failures as
+/// configured in the options
+pub fn fetch(url: &str, options: &RemoteOptions) -> io::Result<Vec<u8>> {
+    let agent = ureq::AgentBuilder::new().timeout(options.timeout).build();
+    let mut delay = options.retry_delay;
+    let mut attempt = 1;
+    loop {
+        match fetch_once(&agent, url, options) {
+            Ok(data) => return Ok(data),
+            Err(failure) if failure.transient && attempt <= options.retries => {
+                if let Some(on_retry) = options.on_retry {
+                    on_retry(url, attempt, &failure.error, delay);
+                }
+                thread::sleep(delay);
+                delay = delay.saturating_mul(2);
+                attempt += 1;
+            }
+            Err(failure) => return Err(failure.error),
+        }
+    }
+}
+
+fn fetch_once(agent: &ureq::Agent, url: &str, options: &RemoteOptions) -> Result<Vec<u8>, Failure> {
+    let response = agent.get(url).call().map_err(|err| {
+        let transient = match &err {
+            ureq::Error::Status(status, _) => matches!(status, 408 | 429 | 500..=599),
+            ureq::Error::Transport(transport) => matches!(
+                transport.kind(),
+                ureq::ErrorKind::Dns | ureq::ErrorKind::ConnectionFailed | ureq::ErrorKind::Io
+            ),
+        };
+        Failure {
+            error: io::Error::other(format!("fetching {url}: {err}")),
+            transient,
+        }
+    })?;
+    let expected_len = response
+        .header("Content-Length")
+        .and_then(|len| len.parse::<usize>().ok());
+    let mut data = Vec::new();
+    response
+        .into_reader()
+        .read_to_end(&mut data)
+        .map_err(Failure::transient)?;
+    if let Some(expected_len) = expected_len {
+        if data.len() != expected_len {
+            return Err(Failure::transient(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "download of {url} is truncated, got {} of {expected_len} bytes",
+                    data.len()
+                ),
+            )));
+        }
+    }
+    if !options.sha256.is_empty() {
+        let digest: [u8; 32] = Sha256::digest(&data).into();
+        if !options.sha256.contains(&digest) {
+            return Err(Failure {
+                error: io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "download of {url} has sha256 {} which matches none of the given checksums",
+                        digest
+                            .iter()
+                            .map(|byte| format!("{byte:02x}"))
+                            .collect::<String>()
+                    ),
+                ),
+                transient: false,
+            });
+        }
+    }
+    Ok(data)
+}
+
+/// Downloads the given url with the options set by [`set_remote_options`]
+pub(crate) fn fetch_configured(url: &str) -> io::Result<Vec<u8>> {
+    fetch(url, OPTIONS.get_or_init(RemoteOptions::default))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    /// Serves the given raw http responses, one per connection and in order,
+    /// and returns the url they are served at and the number of requests
+    /// answered so far
+    fn serve(responses: Vec<String>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/input", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let served = requests.clone();
+        thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                served.fetch_add(1, Ordering::SeqCst);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    fn response(status: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    fn retrying(retries: u32) -> RemoteOptions {
+        RemoteOptions {
+            retries,
+            retry_delay: Duration::from_millis(1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn transient_failures_are_retried() {
+        let (url, requests) = serve(vec![
+            response("503 Service Unavailable", ""),
+            response("500 Internal Server Error", ""),
+            response("200 OK", "filter"),
+        ]);
+        assert_eq!(fetch(&url, &retrying(2)).unwrap(), b"filter");
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn retries_are_reported_with_doubling_delays() {
+        static RETRIES: Mutex<Vec<(u32, Duration)>> = Mutex::new(Vec::new());
+        fn record(_url: &str, attempt: u32, _err: &io::Error, delay: Duration) {
+            RETRIES.lock().unwrap().push((attempt, delay));
+        }
+        let (url, _) = serve(vec![
+            response("503 Service Unavailable", ""),
+            response("429 Too Many Requests", ""),
+            response("503 Service Unavailable", ""),
+            response("200 OK", "filter"),
+        ]);
+        let options = RemoteOptions {
+            on_retry: Some(record),
+            ..retrying(3)
+        };
+        assert_eq!(fetch(&url, &options).unwrap(), b"filter");
+        let delay = Duration::from_millis(1);
+        assert_eq!(
+            *RETRIES.lock().unwrap(),
+            [(1, delay), (2, delay * 2), (3, delay * 4)]
+        );
+    }
+
+    #[test]
+    fn retries_are_bounded() {
+        let (url, requests) = serve(vec![response("503 Service Unavailable", ""); 3]);
+        assert!(fetch(&url, &retrying(1)).is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn client_errors_are_not_retried() {
+        let (url, requests) = serve(vec![
+            response("404 Not Found", ""),
+            response("200 OK", "filter"),
+        ]);
+        assert!(fetch(&url, &retrying(3)).is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn truncated_downloads_fail() {
+        let truncated = "HTTP/1.1 200 OK\r\nContent-Length: 100\r\nConnection: close\r\n\r\nfilter";
+        let (url, requests) = serve(vec![truncated.to_string(), response("200 OK", "filter")]);
+        assert!(fetch(&url, &retrying(0)).is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+
+        // truncation is transient, so a retry gets the whole download
+        let (url, requests) = serve(vec![truncated.to_string(), response("200 OK", "filter")]);
+        assert_eq!(fetch(&url, &retrying(1)).unwrap(), b"filter");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn downloads_must_match_a_checksum() {
+        let digest: [u8; 32] = Sha256::digest(b"filter").into();
+        let options = |sha256| RemoteOptions {
+            sha256,
+            ..retrying(3)
+        };
+
+        let (url, _) = serve(vec![response("200 OK", "filter")]);
+        assert_eq!(
+            fetch(&url, &options(vec![[0; 32], digest])).unwrap(),
+            b"filter"
+        );
+
+        // a mismatch is not transient and fails without a retry
+        let (url, requests) = serve(vec![response("200 OK", "filter"); 2]);
+        let err = fetch(&url, &options(vec![[0; 32]])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+}
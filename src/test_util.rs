@@ -0,0 +1,72 @@
+//! Fixtures shared by the unit tests
+
+use crate::{Descriptor, DescriptorBuilder, Filter, FilterOptions, Manifest, PublicKeyManifest};
+use helium_crypto::{KeyTag, KeyType, Keypair, Network, PublicKeyBinary};
+use rand::rngs::OsRng;
+use std::path::PathBuf;
+
+pub fn keypair() -> Keypair {
+    let key_tag = KeyTag {
+        network: Network::MainNet,
+        key_type: KeyType::Ed25519,
+    };
+    Keypair::generate(key_tag, &mut OsRng)
+}
+
+pub fn key() -> PublicKeyBinary {
+    keypair().public_key().clone().into()
+}
+
+pub fn keys(count: usize) -> Vec<PublicKeyBinary> {
+    (0..count).map(|_| key()).collect()
+}
+
+/// A descriptor with the given number of full nodes and undirected edges
+/// between fresh keys
+pub fn descriptor(nodes: usize, edges: usize) -> Descriptor {
+    let mut builder = DescriptorBuilder::default();
+    for key in keys(nodes) {
+        builder.add_node(key, None, 0);
+    }
+    for _ in 0..edges {
+        builder.add_edge(&key(), &key(), None, 0);
+    }
+    builder.build()
+}
+
+/// An unsigned filter with the given serial over a descriptor of fresh keys
+/// and edges
+pub fn filter(serial: u32) -> Filter {
+    Filter::from_descriptor(serial, &descriptor(100, 20), &FilterOptions::default()).unwrap()
+}
+
+/// The given number of member keypairs and a public key requiring the given
+/// number of their signatures
+pub fn signers(members: usize, required: u8) -> (Vec<Keypair>, PublicKeyManifest) {
+    let keypairs: Vec<Keypair> = (0..members).map(|_| keypair()).collect();
+    let public_keys = keypairs
+        .iter()
+        .map(|keypair| keypair.public_key().clone())
+        .collect();
+    (keypairs, PublicKeyManifest::new(public_keys, required))
+}
+
+/// Signs the filter with the given members and returns the manifest they
+/// signed
+pub fn sign(
+    filter: &mut Filter,
+    keypairs: &[Keypair],
+    key_manifest: &PublicKeyManifest,
+) -> Manifest {
+    let mut manifest = Manifest::from_filter(filter, key_manifest).unwrap();
+    manifest
+        .sign_with(&filter.to_signing_bytes().unwrap(), keypairs)
+        .unwrap();
+    filter.apply_manifest(&manifest, key_manifest).unwrap();
+    manifest
+}
+
+/// A path in the temporary directory that is unique to the calling test
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("xorf-generator-{}-{name}", std::process::id()))
+}
@@ -0,0 +1,66 @@
+//! Bindings for checking filter membership and signatures from JavaScript.
+//!
+//! Build with `--no-default-features --features wasm` for
+//! `wasm32-unknown-unknown`. Filters are passed in as bytes since the path
+//! based loaders have no filesystem to read from there.
+
+use crate::Filter;
+use helium_crypto::{PublicKey, PublicKeyBinary};
+use wasm_bindgen::prelude::*;
+
+/// Returns whether the filter contains the given public key, or the edge
+/// between the key and `target` if one is given.
+#[wasm_bindgen]
+pub fn contains(filter_bytes: &[u8], key: &str, target: Option<String>) -> Result<bool, JsError> {
+    let filter = Filter::from_bytes(filter_bytes)?;
+    let key: PublicKeyBinary = key.parse::<PublicKey>()?.into();
+    let in_filter = if let Some(target) = target {
+        let target: PublicKeyBinary = target.parse::<PublicKey>()?.into();
+        filter.contains_edge(&key, &target)
+    } else {
+        filter.contains(&key)
+    };
+    Ok(in_filter)
+}
+
+/// Returns whether the filter signature verifies against the given multisig
+/// public key address.
+#[wasm_bindgen]
+pub fn verify(filter_bytes: &[u8], public_key: &str) -> Result<bool, JsError> {
+    let filter = Filter::from_bytes(filter_bytes)?;
+    let public_key: PublicKey = public_key.parse()?;
+    Ok(filter.verify(&public_key).is_ok())
+}
+
+// Only the successful calls are checked natively, a JsError can only be
+// created on wasm targets
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_util, FilterOptions};
+
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen_test::wasm_bindgen_test)]
+    #[cfg_attr(not(target_arch = "wasm32"), test)]
+    fn checks_a_signed_filter() {
+        let keys = test_util::keys(4);
+        let mut builder = crate::DescriptorBuilder::default();
+        builder.add_node(keys[0].clone(), None, 0);
+        builder.add_edge(&keys[1], &keys[2], None, 0);
+        let descriptor = builder.build();
+        let mut filter =
+            Filter::from_descriptor(1, &descriptor, &FilterOptions::default()).unwrap();
+        let (keypairs, key_manifest) = test_util::signers(2, 1);
+        test_util::sign(&mut filter, &keypairs[..1], &key_manifest);
+        let bytes = filter.to_bytes().unwrap();
+
+        assert!(contains(&bytes, &keys[0].to_string(), None).unwrap());
+        assert!(!contains(&bytes, &keys[3].to_string(), None).unwrap());
+        assert!(contains(&bytes, &keys[2].to_string(), Some(keys[1].to_string())).unwrap());
+        assert!(!contains(&bytes, &keys[1].to_string(), Some(keys[3].to_string())).unwrap());
+
+        let address = key_manifest.public_key().unwrap().to_string();
+        assert!(verify(&bytes, &address).unwrap());
+        let other = test_util::signers(2, 1).1.public_key().unwrap().to_string();
+        assert!(!verify(&bytes, &other).unwrap());
+    }
+}